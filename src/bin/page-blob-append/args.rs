@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+
+pub const USAGE: &str = "Usage: page-blob-append [options] <command> <blob> [command options]
+
+Commands:
+  info <blob>                                  Blob size and header
+  dump <blob> [--format hex|base64|json]       All the records
+  tail <blob> [-n <count>] [--format ...]      Last records. Default count is 10
+  verify <blob>                                Record boundaries and anomalies
+  copy <from_blob> <to_blob>                   Copies all the pages
+  truncate <blob> --at <position>              Drops records starting from the position
+  append <blob> --from-file <path> [--lines]   Appends file content as one record or a record per line.
+                                               Empty file is refused, empty lines are skipped
+
+Blob:
+  file:<root_folder>/<container>/<blob>        Local file-backed page blob
+  azure:<container>/<blob>                     Azure page blob
+
+Options:
+  --connection-string <value>                  Azure connection string.
+                                               Default is AZURE_STORAGE_CONNECTION_STRING env variable
+  --record-format legacy|crc32c|crc32c-seq     Format of a new blob and of the blob without header.
+                                               Default is crc32c-seq
+  --compression none|lz4|zstd                  Compression of appended records. Default is none
+  --key-file <path>                            File with the hex of the 32 byte key. Encrypted records are
+                                               decrypted with it and appended records are encrypted.
+                                               Without it verify, dump and tail give encrypted payloads as stored
+  --key-id <id>                                Id of the key from --key-file
+  --encryption aes-gcm|chacha20poly1305        Algorithm of appended records. Default is aes-gcm";
+
+const FLAGS: [&str; 1] = ["--lines"];
+
+pub struct Args {
+    pub command: String,
+    pub positional: Vec<String>,
+    options: HashMap<String, String>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    pub fn parse(mut src: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut flags = Vec::new();
+
+        while let Some(arg) = src.next() {
+            if FLAGS.contains(&arg.as_str()) {
+                flags.push(arg);
+                continue;
+            }
+
+            if arg.starts_with('-') {
+                let name = if arg == "-n" {
+                    "--count".to_string()
+                } else {
+                    arg
+                };
+
+                match src.next() {
+                    Some(value) => {
+                        options.insert(name, value);
+                    }
+                    None => return Err(format!("Option {} requires a value", name)),
+                }
+
+                continue;
+            }
+
+            positional.push(arg);
+        }
+
+        if positional.is_empty() {
+            return Err("Command is not specified".to_string());
+        }
+
+        let command = positional.remove(0);
+
+        Ok(Self {
+            command,
+            positional,
+            options,
+            flags,
+        })
+    }
+
+    pub fn get_positional(&self, index: usize, name: &str) -> Result<&str, String> {
+        match self.positional.get(index) {
+            Some(value) => Ok(value.as_str()),
+            None => Err(format!("{} is not specified", name)),
+        }
+    }
+
+    pub fn get_option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(|value| value.as_str())
+    }
+
+    pub fn get_required_option(&self, name: &str) -> Result<&str, String> {
+        match self.get_option(name) {
+            Some(value) => Ok(value),
+            None => Err(format!("Option {} is required", name)),
+        }
+    }
+
+    pub fn get_usize_option(&self, name: &str) -> Result<Option<usize>, String> {
+        match self.get_option(name) {
+            Some(value) => match value.parse() {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(format!("Option {} must be a number. Got: {}", name, value)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn has_flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+}
@@ -0,0 +1,193 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use my_azure_page_blob::{MyAzurePageBlob, MyPageBlob};
+use my_azure_page_blob_append::FilePageBlob;
+use my_azure_storage_sdk::{AzureStorageConnection, AzureStorageError};
+
+// Lets every command work the same way against both kinds of blobs
+pub enum CliPageBlob {
+    File(FilePageBlob),
+    Azure(MyAzurePageBlob),
+}
+
+impl CliPageBlob {
+    // file:<root_folder>/<container>/<blob> or azure:<container>/<blob>
+    pub fn open(location: &str, connection_string: Option<&str>) -> Result<Self, String> {
+        if let Some(path) = location.strip_prefix("file:") {
+            let mut parts: Vec<&str> = path.rsplitn(3, '/').collect();
+
+            if parts.len() != 3 || parts.iter().any(|part| part.is_empty()) {
+                return Err(format!(
+                    "Invalid blob {}. Expected file:<root_folder>/<container>/<blob>",
+                    location
+                ));
+            }
+
+            parts.reverse();
+            return Ok(Self::File(FilePageBlob::new(parts[0], parts[1], parts[2])));
+        }
+
+        if let Some(path) = location.strip_prefix("azure:") {
+            let parts: Vec<&str> = path.split('/').collect();
+
+            if parts.len() != 2 || parts.iter().any(|part| part.is_empty()) {
+                return Err(format!(
+                    "Invalid blob {}. Expected azure:<container>/<blob>",
+                    location
+                ));
+            }
+
+            let connection_string = match connection_string {
+                Some(connection_string) => connection_string.to_string(),
+                None => std::env::var("AZURE_STORAGE_CONNECTION_STRING").map_err(|_| {
+                    "Azure connection string is not specified. Use --connection-string or AZURE_STORAGE_CONNECTION_STRING".to_string()
+                })?,
+            };
+
+            let connection = AzureStorageConnection::from_conn_string(&connection_string);
+
+            return Ok(Self::Azure(MyAzurePageBlob::new(
+                Arc::new(connection),
+                parts[0].to_string(),
+                parts[1].to_string(),
+            )));
+        }
+
+        Err(format!(
+            "Invalid blob {}. It must start with file: or azure:",
+            location
+        ))
+    }
+}
+
+#[async_trait]
+impl MyPageBlob for CliPageBlob {
+    fn get_container_name(&self) -> &str {
+        match self {
+            Self::File(page_blob) => page_blob.get_container_name(),
+            Self::Azure(page_blob) => page_blob.get_container_name(),
+        }
+    }
+
+    fn get_blob_name(&self) -> &str {
+        match self {
+            Self::File(page_blob) => page_blob.get_blob_name(),
+            Self::Azure(page_blob) => page_blob.get_blob_name(),
+        }
+    }
+
+    async fn create_container_if_not_exist(&mut self) -> Result<(), AzureStorageError> {
+        match self {
+            Self::File(page_blob) => page_blob.create_container_if_not_exist().await,
+            Self::Azure(page_blob) => page_blob.create_container_if_not_exist().await,
+        }
+    }
+
+    async fn create_if_not_exists(
+        &mut self,
+        init_pages_amounts: usize,
+    ) -> Result<(), AzureStorageError> {
+        match self {
+            Self::File(page_blob) => page_blob.create_if_not_exists(init_pages_amounts).await,
+            Self::Azure(page_blob) => page_blob.create_if_not_exists(init_pages_amounts).await,
+        }
+    }
+
+    async fn get_available_pages_amount(&mut self) -> Result<usize, AzureStorageError> {
+        match self {
+            Self::File(page_blob) => page_blob.get_available_pages_amount().await,
+            Self::Azure(page_blob) => page_blob.get_available_pages_amount().await,
+        }
+    }
+
+    async fn get(
+        &mut self,
+        start_page_no: usize,
+        pages_to_read: usize,
+    ) -> Result<Vec<u8>, AzureStorageError> {
+        match self {
+            Self::File(page_blob) => page_blob.get(start_page_no, pages_to_read).await,
+            Self::Azure(page_blob) => page_blob.get(start_page_no, pages_to_read).await,
+        }
+    }
+
+    async fn resize(&mut self, pages_amount: usize) -> Result<(), AzureStorageError> {
+        match self {
+            Self::File(page_blob) => page_blob.resize(pages_amount).await,
+            Self::Azure(page_blob) => page_blob.resize(pages_amount).await,
+        }
+    }
+
+    async fn save_pages(
+        &mut self,
+        start_page_no: usize,
+        max_pages_to_write_per_round_trip: usize,
+        payload: Vec<u8>,
+    ) -> Result<(), AzureStorageError> {
+        match self {
+            Self::File(page_blob) => {
+                page_blob
+                    .save_pages(start_page_no, max_pages_to_write_per_round_trip, payload)
+                    .await
+            }
+            Self::Azure(page_blob) => {
+                page_blob
+                    .save_pages(start_page_no, max_pages_to_write_per_round_trip, payload)
+                    .await
+            }
+        }
+    }
+
+    async fn auto_ressize_and_save_pages(
+        &mut self,
+        start_page_no: usize,
+        max_pages_to_write_per_round_trip: usize,
+        payload: Vec<u8>,
+        resize_pages_ration: usize,
+    ) -> Result<usize, AzureStorageError> {
+        match self {
+            Self::File(page_blob) => {
+                page_blob
+                    .auto_ressize_and_save_pages(
+                        start_page_no,
+                        max_pages_to_write_per_round_trip,
+                        payload,
+                        resize_pages_ration,
+                    )
+                    .await
+            }
+            Self::Azure(page_blob) => {
+                page_blob
+                    .auto_ressize_and_save_pages(
+                        start_page_no,
+                        max_pages_to_write_per_round_trip,
+                        payload,
+                        resize_pages_ration,
+                    )
+                    .await
+            }
+        }
+    }
+
+    async fn download(&mut self) -> Result<Vec<u8>, AzureStorageError> {
+        match self {
+            Self::File(page_blob) => page_blob.download().await,
+            Self::Azure(page_blob) => page_blob.download().await,
+        }
+    }
+
+    async fn delete(&mut self) -> Result<(), AzureStorageError> {
+        match self {
+            Self::File(page_blob) => page_blob.delete().await,
+            Self::Azure(page_blob) => page_blob.delete().await,
+        }
+    }
+
+    async fn delete_if_exists(&mut self) -> Result<(), AzureStorageError> {
+        match self {
+            Self::File(page_blob) => page_blob.delete_if_exists().await,
+            Self::Azure(page_blob) => page_blob.delete_if_exists().await,
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::VecDeque;
+
+use my_azure_page_blob::MyPageBlob;
+use my_azure_page_blob_append::{
+    AppendPageBlobSettings, BlobHeader, PageBlobAppend, ReadCheckpoint, VerifiedRecord,
+    VerifyReport,
+};
+
+use crate::{args::Args, cli_page_blob::CliPageBlob};
+
+pub enum OutputFormat {
+    Hex,
+    Base64,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn parse(src: Option<&str>) -> Result<Self, String> {
+        match src {
+            None | Some("hex") => Ok(Self::Hex),
+            Some("base64") => Ok(Self::Base64),
+            Some("json") => Ok(Self::Json),
+            Some(other) => Err(format!("Unknown format {}", other)),
+        }
+    }
+
+    pub fn print(&self, record: &VerifiedRecord, payload: &[u8]) {
+        match self {
+            Self::Hex => println!("{}\t{}\t{}", record.seq, record.position, to_hex(payload)),
+            Self::Base64 => println!(
+                "{}\t{}\t{}",
+                record.seq,
+                record.position,
+                to_base64(payload)
+            ),
+            Self::Json => println!(
+                "{{\"seq\":{},\"position\":{},\"size\":{},\"decoded\":{},\"payload\":\"{}\"}}",
+                record.seq,
+                record.position,
+                record.payload_size,
+                record.is_decoded,
+                to_base64(payload)
+            ),
+        }
+    }
+}
+
+pub struct CommandContext {
+    pub settings: AppendPageBlobSettings,
+    pub connection_string: Option<String>,
+}
+
+impl CommandContext {
+    pub fn open(&self, location: &str) -> Result<CliPageBlob, String> {
+        CliPageBlob::open(location, self.connection_string.as_deref())
+    }
+}
+
+// Returns the exit code
+pub async fn info(ctx: &CommandContext, args: &Args) -> Result<i32, String> {
+    let mut page_blob = ctx.open(args.get_positional(0, "Blob")?)?;
+
+    let pages_amount = page_blob
+        .get_available_pages_amount()
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+
+    println!("container: {}", page_blob.get_container_name());
+    println!("blob: {}", page_blob.get_blob_name());
+    println!("blob_size_in_pages: {}", pages_amount);
+
+    if pages_amount == 0 {
+        return Ok(0);
+    }
+
+    let first_page = page_blob
+        .get(0, 1)
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+
+    if !BlobHeader::is_header(&first_page) {
+        println!("header: none");
+        return Ok(0);
+    }
+
+    let header = BlobHeader::deserialize(&first_page)?;
+    println!("header_version: {}", header.version);
+    println!("header_flags: {:#06b}", header.flags);
+    println!(
+        "created_unix_microseconds: {}",
+        header.created_unix_microseconds
+    );
+    println!("record_format: {:?}", header.get_record_format());
+
+    if let Err(err) = header.validate() {
+        println!("header_error: {}", err);
+        return Ok(1);
+    }
+
+    Ok(0)
+}
+
+pub async fn dump(ctx: &CommandContext, args: &Args) -> Result<i32, String> {
+    let page_blob = ctx.open(args.get_positional(0, "Blob")?)?;
+    let format = OutputFormat::parse(args.get_option("--format"))?;
+
+    let report =
+        my_azure_page_blob_append::verify_blob(page_blob, &ctx.settings, |record, payload| {
+            format.print(record, payload)
+        })
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+
+    print_undecoded(&report);
+
+    Ok(print_corruption(&report))
+}
+
+pub async fn tail(ctx: &CommandContext, args: &Args) -> Result<i32, String> {
+    let page_blob = ctx.open(args.get_positional(0, "Blob")?)?;
+    let format = OutputFormat::parse(args.get_option("--format"))?;
+    let count = args.get_usize_option("--count")?.unwrap_or(10);
+
+    let mut last_records = VecDeque::with_capacity(count + 1);
+
+    let report =
+        my_azure_page_blob_append::verify_blob(page_blob, &ctx.settings, |record, payload| {
+            last_records.push_back((record.clone(), payload.to_vec()));
+
+            if last_records.len() > count {
+                last_records.pop_front();
+            }
+        })
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+
+    for (record, payload) in &last_records {
+        format.print(record, payload);
+    }
+
+    print_undecoded(&report);
+
+    Ok(print_corruption(&report))
+}
+
+pub async fn verify(ctx: &CommandContext, args: &Args) -> Result<i32, String> {
+    let page_blob = ctx.open(args.get_positional(0, "Blob")?)?;
+
+    let report = my_azure_page_blob_append::verify_blob(page_blob, &ctx.settings, |record, _| {
+        println!(
+            "record pos:{} seq:{} size:{}{}",
+            record.position,
+            record.seq,
+            record.payload_size,
+            if record.is_decoded {
+                ""
+            } else {
+                " not decoded"
+            }
+        )
+    })
+    .await
+    .map_err(|err| format!("{:?}", err))?;
+
+    println!("blob_size_in_pages: {}", report.blob_size_in_pages);
+    println!("has_header: {}", report.header.is_some());
+    println!("record_format: {:?}", report.record_format);
+    println!("records_count: {}", report.records_count);
+    println!("payload_bytes: {}", report.payload_bytes);
+    println!("last_seq: {}", report.last_seq);
+    println!("end_marker_position: {:?}", report.end_marker_position);
+    println!("wasted_tail_bytes: {}", report.wasted_tail_bytes);
+    println!(
+        "blob_size_is_consistent: {}",
+        report.blob_size_is_consistent
+    );
+    println!("undecoded_records: {}", report.undecoded_records);
+
+    print_undecoded(&report);
+    print_corruption(&report);
+
+    if report.is_healthy() {
+        Ok(0)
+    } else {
+        Ok(1)
+    }
+}
+
+pub async fn copy(ctx: &CommandContext, args: &Args) -> Result<i32, String> {
+    let mut src = ctx.open(args.get_positional(0, "Source blob")?)?;
+    let mut dest = ctx.open(args.get_positional(1, "Destination blob")?)?;
+
+    my_azure_page_blob_append::page_blob_utils::copy_blob(&mut src, &mut dest, &ctx.settings)
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+
+    Ok(0)
+}
+
+pub async fn truncate(ctx: &CommandContext, args: &Args) -> Result<i32, String> {
+    let location = args.get_positional(0, "Blob")?;
+    let position: usize = match args.get_usize_option("--at")? {
+        Some(position) => position,
+        None => return Err("Option --at is required".to_string()),
+    };
+
+    // Position has to be a record boundary. Looking for it and for the last seq before it
+    let mut checkpoint = None;
+
+    let report =
+        my_azure_page_blob_append::verify_blob(ctx.open(location)?, &ctx.settings, |record, _| {
+            if record.position == position {
+                checkpoint = Some(ReadCheckpoint {
+                    blob_position: position,
+                    last_seq: record.seq - 1,
+                });
+            }
+        })
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+
+    if report.end_marker_position == Some(position) {
+        println!(
+            "Nothing to truncate. Position {} is the end of the log",
+            position
+        );
+        return Ok(0);
+    }
+
+    // Cutting the log right before the first corrupted record
+    if let Some(corruption) = &report.first_corruption {
+        if corruption.position == position {
+            checkpoint = Some(ReadCheckpoint {
+                blob_position: position,
+                last_seq: report.last_seq,
+            });
+        }
+    }
+
+    let checkpoint = match checkpoint {
+        Some(checkpoint) => checkpoint,
+        None => {
+            return Err(format!(
+                "Position {} is not a record boundary. Use verify to see the record positions",
+                position
+            ))
+        }
+    };
+
+    let mut page_blob_append = PageBlobAppend::new(ctx.open(location)?, ctx.settings.clone());
+
+    page_blob_append
+        .truncate_at(checkpoint)
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+
+    for dropped in &page_blob_append.get_recovery_report().dropped {
+        println!(
+            "dropped: {}..{} ({} bytes)",
+            dropped.from_position,
+            dropped.to_position,
+            dropped.get_size()
+        );
+    }
+
+    Ok(0)
+}
+
+pub async fn append(ctx: &CommandContext, args: &Args) -> Result<i32, String> {
+    let mut page_blob = ctx.open(args.get_positional(0, "Blob")?)?;
+    let file_name = args.get_required_option("--from-file")?;
+
+    let content = tokio::fs::read(file_name)
+        .await
+        .map_err(|err| format!("Can not read file {}. Err: {}", file_name, err))?;
+
+    let records: Vec<Vec<u8>> = if args.has_flag("--lines") {
+        content
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| line.to_vec())
+            .collect()
+    } else {
+        vec![content]
+    };
+
+    // Empty record would be read as the end of the log
+    if records.iter().all(|record| record.is_empty()) {
+        return Err(format!("File {} has nothing to append", file_name));
+    }
+
+    page_blob
+        .create_container_if_not_exist()
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+    page_blob
+        .create_if_not_exists(0)
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+
+    let mut page_blob_append = PageBlobAppend::new(page_blob, ctx.settings.clone());
+
+    // Records can only be appended after the whole log is read
+    while page_blob_append
+        .get_next_payload()
+        .await
+        .map_err(|err| format!("{:?}", err))?
+        .is_some()
+    {}
+
+    let seq_range = page_blob_append
+        .append_and_write(&records)
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+
+    println!("appended: {} records. seq: {:?}", records.len(), seq_range);
+
+    Ok(0)
+}
+
+// Framing and checksums of such records are verified, the payloads are printed as stored
+fn print_undecoded(report: &VerifyReport) {
+    if let Some(reason) = &report.first_undecoded_reason {
+        eprintln!(
+            "{} records are not decoded. Use --key-file and --key-id for encrypted records",
+            report.undecoded_records
+        );
+        eprintln!("first_undecoded: {}", reason);
+    }
+}
+
+fn print_corruption(report: &VerifyReport) -> i32 {
+    match &report.first_corruption {
+        Some(corruption) => {
+            eprintln!("corrupted_at: {}", corruption.position);
+            eprintln!("reason: {}", corruption.msg);
+            eprint!("{}", corruption.page_dump);
+            1
+        }
+        None => 0,
+    }
+}
+
+fn to_hex(src: &[u8]) -> String {
+    let mut result = String::with_capacity(src.len() * 2);
+
+    for b in src {
+        result.push_str(&format!("{:02x}", b));
+    }
+
+    result
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn to_base64(src: &[u8]) -> String {
+    let mut result = String::with_capacity(src.len().div_ceil(3) * 4);
+
+    for chunk in src.chunks(3) {
+        let b0 = chunk[0] as u32;
+        let b1 = chunk.get(1).copied().unwrap_or(0) as u32;
+        let b2 = chunk.get(2).copied().unwrap_or(0) as u32;
+
+        let triple = (b0 << 16) | (b1 << 8) | b2;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (triple >> (18 - i * 6)) & 0b111111;
+                result.push(BASE64_ALPHABET[index as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!("", to_base64(b""));
+        assert_eq!("Zg==", to_base64(b"f"));
+        assert_eq!("Zm8=", to_base64(b"fo"));
+        assert_eq!("Zm9v", to_base64(b"foo"));
+        assert_eq!("Zm9vYmFy", to_base64(b"foobar"));
+    }
+}
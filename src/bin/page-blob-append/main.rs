@@ -0,0 +1,132 @@
+mod args;
+mod cli_page_blob;
+mod commands;
+
+use std::{sync::Arc, time::Duration};
+
+use args::{Args, USAGE};
+use commands::CommandContext;
+use my_azure_page_blob_append::{
+    AppendPageBlobSettings, Compression, Encryption, EncryptionAlgorithm, RecordFormat,
+    RetryPolicy, StaticKeyProvider, KEY_SIZE,
+};
+
+#[tokio::main]
+async fn main() {
+    let result = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => run(args).await,
+        Err(err) => Err(format!("{}\n\n{}", err, USAGE)),
+    };
+
+    match result {
+        Ok(exit_code) => std::process::exit(exit_code),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<i32, String> {
+    let ctx = CommandContext {
+        settings: get_settings(&args)?,
+        connection_string: args
+            .get_option("--connection-string")
+            .map(|s| s.to_string()),
+    };
+
+    match args.command.as_str() {
+        "info" => commands::info(&ctx, &args).await,
+        "dump" => commands::dump(&ctx, &args).await,
+        "tail" => commands::tail(&ctx, &args).await,
+        "verify" => commands::verify(&ctx, &args).await,
+        "copy" => commands::copy(&ctx, &args).await,
+        "truncate" => commands::truncate(&ctx, &args).await,
+        "append" => commands::append(&ctx, &args).await,
+        other => Err(format!("Unknown command {}\n\n{}", other, USAGE)),
+    }
+}
+
+fn get_settings(args: &Args) -> Result<AppendPageBlobSettings, String> {
+    // Blobs with the header are always read with the format from the header
+    let record_format = match args.get_option("--record-format") {
+        Some("legacy") => RecordFormat::Legacy,
+        Some("crc32c") => RecordFormat::Crc32c,
+        None | Some("crc32c-seq") => RecordFormat::Crc32cWithSequence,
+        Some(other) => return Err(format!("Unknown record format {}", other)),
+    };
+
+    // Codecs are available only with the lz4 and zstd features
+    let compression = match args.get_option("--compression") {
+        None | Some("none") => Compression::None,
+        #[cfg(feature = "lz4")]
+        Some("lz4") => Compression::Lz4,
+        #[cfg(feature = "zstd")]
+        Some("zstd") => Compression::Zstd { level: 3 },
+        Some(other) => return Err(format!("Unknown or disabled compression {}", other)),
+    };
+
+    let mut settings = AppendPageBlobSettings::default();
+    settings.record_format = record_format;
+    settings.compression = compression;
+    settings.encryption = get_encryption(args)?;
+    settings.retry_policy = RetryPolicy {
+        max_attempts: Some(5),
+        deadline: Some(Duration::from_secs(60)),
+        ..Default::default()
+    };
+
+    Ok(settings)
+}
+
+// Algorithms are available only with the aes-gcm and chacha20poly1305 features
+fn get_encryption(args: &Args) -> Result<Option<Encryption>, String> {
+    let key_file = match args.get_option("--key-file") {
+        Some(key_file) => key_file,
+        None => return Ok(None),
+    };
+
+    let key_id = args.get_required_option("--key-id")?;
+    let key_id: u32 = key_id
+        .parse()
+        .map_err(|_| format!("Option --key-id must be a number. Got: {}", key_id))?;
+
+    let content = std::fs::read_to_string(key_file)
+        .map_err(|err| format!("Can not read key file {}. Err: {}", key_file, err))?;
+    let key = parse_key(content.trim())
+        .map_err(|err| format!("Key file {} is invalid. {}", key_file, err))?;
+
+    let algorithm: Result<EncryptionAlgorithm, String> = match args.get_option("--encryption") {
+        #[cfg(feature = "aes-gcm")]
+        None | Some("aes-gcm") => Ok(EncryptionAlgorithm::Aes256Gcm),
+        #[cfg(all(not(feature = "aes-gcm"), feature = "chacha20poly1305"))]
+        None => Ok(EncryptionAlgorithm::ChaCha20Poly1305),
+        #[cfg(feature = "chacha20poly1305")]
+        Some("chacha20poly1305") => Ok(EncryptionAlgorithm::ChaCha20Poly1305),
+        #[cfg(not(any(feature = "aes-gcm", feature = "chacha20poly1305")))]
+        None => Err("Encryption needs the aes-gcm or chacha20poly1305 feature".to_string()),
+        Some(other) => Err(format!("Unknown or disabled encryption {}", other)),
+    };
+
+    algorithm.map(|algorithm| {
+        Some(Encryption::new(
+            algorithm,
+            Arc::new(StaticKeyProvider::new(key_id, key)),
+        ))
+    })
+}
+
+fn parse_key(src: &str) -> Result<[u8; KEY_SIZE], String> {
+    if src.len() != KEY_SIZE * 2 || !src.is_ascii() {
+        return Err(format!("Key must be {} hex characters", KEY_SIZE * 2));
+    }
+
+    let mut result = [0u8; KEY_SIZE];
+
+    for (index, b) in result.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&src[index * 2..index * 2 + 2], 16)
+            .map_err(|_| format!("Key must be {} hex characters", KEY_SIZE * 2))?;
+    }
+
+    Ok(result)
+}
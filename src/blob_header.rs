@@ -0,0 +1,225 @@
+use my_azure_storage_sdk::page_blob::consts::BLOB_PAGE_SIZE;
+use rust_extensions::date_time::DateTimeAsMicroseconds;
+
+use crate::settings::RecordFormat;
+
+pub const BLOB_HEADER_MAGIC: [u8; 8] = *b"PBAPPEND";
+pub const BLOB_HEADER_CURRENT_VERSION: u32 = 1;
+pub const BLOB_HEADER_SIZE_IN_PAGES: usize = 1;
+
+pub const FLAG_CHECKSUMS: u32 = 0b0001;
+pub const FLAG_COMPRESSION: u32 = 0b0010;
+pub const FLAG_SEQUENCE_NUMBERS: u32 = 0b0100;
+pub const FLAG_ENCRYPTION: u32 = 0b1000;
+// Blob is being replaced by its compacted copy. The content is valid only in the backup
+pub const FLAG_COMPACTION_IN_PROGRESS: u32 = 0b1_0000;
+
+const SUPPORTED_FLAGS: u32 = FLAG_CHECKSUMS
+    | FLAG_COMPRESSION
+    | FLAG_SEQUENCE_NUMBERS
+    | FLAG_ENCRYPTION
+    | FLAG_COMPACTION_IN_PROGRESS;
+
+// Layout of the header page (all numbers are LE):
+// [0..8] magic, [8..12] version, [12..16] page size, [16..20] flags,
+// [20..28] created (unix microseconds), [28..32] crc32c of [0..28]
+const HEADER_CONTENT_SIZE: usize = 28;
+
+#[derive(Debug, Clone)]
+pub struct BlobHeader {
+    pub version: u32,
+    pub page_size: u32,
+    pub flags: u32,
+    pub created_unix_microseconds: i64,
+}
+
+impl BlobHeader {
+    pub fn new(record_format: RecordFormat) -> Self {
+        let flags = match record_format {
+            RecordFormat::Legacy => 0,
+            RecordFormat::Crc32c => FLAG_CHECKSUMS,
+            RecordFormat::Crc32cWithSequence => FLAG_CHECKSUMS | FLAG_SEQUENCE_NUMBERS,
+        };
+
+        Self {
+            version: BLOB_HEADER_CURRENT_VERSION,
+            page_size: BLOB_PAGE_SIZE as u32,
+            flags,
+            created_unix_microseconds: DateTimeAsMicroseconds::now().unix_microseconds,
+        }
+    }
+
+    pub fn get_data_start_position() -> usize {
+        BLOB_HEADER_SIZE_IN_PAGES * BLOB_PAGE_SIZE
+    }
+
+    pub fn get_record_format(&self) -> RecordFormat {
+        if self.flags & FLAG_CHECKSUMS != FLAG_CHECKSUMS {
+            return RecordFormat::Legacy;
+        }
+
+        if self.flags & FLAG_SEQUENCE_NUMBERS == FLAG_SEQUENCE_NUMBERS {
+            RecordFormat::Crc32cWithSequence
+        } else {
+            RecordFormat::Crc32c
+        }
+    }
+
+    // Records may be compressed. Codec of every record is in the high bits of its size
+    pub fn has_compression(&self) -> bool {
+        self.flags & FLAG_COMPRESSION == FLAG_COMPRESSION
+    }
+
+    pub fn has_encryption(&self) -> bool {
+        self.flags & FLAG_ENCRYPTION == FLAG_ENCRYPTION
+    }
+
+    pub fn is_compaction_in_progress(&self) -> bool {
+        self.flags & FLAG_COMPACTION_IN_PROGRESS == FLAG_COMPACTION_IN_PROGRESS
+    }
+
+    pub fn is_header(page: &[u8]) -> bool {
+        page.len() >= BLOB_HEADER_MAGIC.len()
+            && page[..BLOB_HEADER_MAGIC.len()] == BLOB_HEADER_MAGIC
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(BLOB_PAGE_SIZE);
+
+        result.extend_from_slice(&BLOB_HEADER_MAGIC);
+        result.extend_from_slice(&self.version.to_le_bytes());
+        result.extend_from_slice(&self.page_size.to_le_bytes());
+        result.extend_from_slice(&self.flags.to_le_bytes());
+        result.extend_from_slice(&self.created_unix_microseconds.to_le_bytes());
+
+        let crc = crc32c::crc32c(&result[..HEADER_CONTENT_SIZE]);
+        result.extend_from_slice(&crc.to_le_bytes());
+
+        result.resize(BLOB_PAGE_SIZE, 0);
+
+        result
+    }
+
+    pub fn deserialize(page: &[u8]) -> Result<Self, String> {
+        if !Self::is_header(page) {
+            return Err("Blob does not start with the PageBlobAppend header".to_string());
+        }
+
+        if page.len() < HEADER_CONTENT_SIZE + 4 {
+            return Err(format!("Header page is too short. Size: {}", page.len()));
+        }
+
+        let stored_crc = read_u32(page, HEADER_CONTENT_SIZE);
+        let calculated_crc = crc32c::crc32c(&page[..HEADER_CONTENT_SIZE]);
+
+        if stored_crc != calculated_crc {
+            return Err(format!(
+                "Header checksum mismatch. Stored: {:08x}. Calculated: {:08x}",
+                stored_crc, calculated_crc
+            ));
+        }
+
+        let mut created = [0u8; 8];
+        created.copy_from_slice(&page[20..28]);
+
+        Ok(Self {
+            version: read_u32(page, 8),
+            page_size: read_u32(page, 12),
+            flags: read_u32(page, 16),
+            created_unix_microseconds: i64::from_le_bytes(created),
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.version > BLOB_HEADER_CURRENT_VERSION {
+            return Err(format!(
+                "Blob format version {} is newer than the supported version {}",
+                self.version, BLOB_HEADER_CURRENT_VERSION
+            ));
+        }
+
+        if self.page_size as usize != BLOB_PAGE_SIZE {
+            return Err(format!(
+                "Blob page size {} is not supported. Expected page size is {}",
+                self.page_size, BLOB_PAGE_SIZE
+            ));
+        }
+
+        let unsupported_flags = self.flags & !SUPPORTED_FLAGS;
+
+        if unsupported_flags != 0 {
+            return Err(format!(
+                "Blob uses unsupported feature flags {:#b}",
+                unsupported_flags
+            ));
+        }
+
+        if self.flags & FLAG_SEQUENCE_NUMBERS == FLAG_SEQUENCE_NUMBERS
+            && self.flags & FLAG_CHECKSUMS != FLAG_CHECKSUMS
+        {
+            return Err("Sequence numbers are supported only for checksummed records".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+fn read_u32(src: &[u8], pos: usize) -> u32 {
+    let mut result = [0u8; 4];
+    result.copy_from_slice(&src[pos..pos + 4]);
+    u32::from_le_bytes(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_deserialize() {
+        let header = BlobHeader::new(RecordFormat::Crc32c);
+
+        let page = header.serialize();
+
+        assert_eq!(BLOB_PAGE_SIZE, page.len());
+        assert!(BlobHeader::is_header(&page));
+
+        let result = BlobHeader::deserialize(&page).unwrap();
+
+        assert_eq!(BLOB_HEADER_CURRENT_VERSION, result.version);
+        assert_eq!(BLOB_PAGE_SIZE as u32, result.page_size);
+        assert_eq!(FLAG_CHECKSUMS, result.flags);
+        assert_eq!(
+            header.created_unix_microseconds,
+            result.created_unix_microseconds
+        );
+        assert_eq!(RecordFormat::Crc32c, result.get_record_format());
+    }
+
+    #[test]
+    fn test_sequence_numbers_flag() {
+        let header = BlobHeader::new(RecordFormat::Crc32cWithSequence);
+
+        let result = BlobHeader::deserialize(&header.serialize()).unwrap();
+
+        assert_eq!(FLAG_CHECKSUMS | FLAG_SEQUENCE_NUMBERS, result.flags);
+        assert_eq!(RecordFormat::Crc32cWithSequence, result.get_record_format());
+        assert!(result.validate().is_ok());
+    }
+
+    #[test]
+    fn test_damaged_header_is_rejected() {
+        let mut page = BlobHeader::new(RecordFormat::Crc32c).serialize();
+
+        page[17] = 0xff;
+
+        assert!(BlobHeader::deserialize(&page).is_err());
+    }
+
+    #[test]
+    fn test_newer_version_is_rejected() {
+        let mut header = BlobHeader::new(RecordFormat::Crc32c);
+        header.version = BLOB_HEADER_CURRENT_VERSION + 1;
+
+        assert!(header.validate().is_err());
+    }
+}
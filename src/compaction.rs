@@ -0,0 +1,718 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use my_azure_page_blob::MyPageBlob;
+use my_azure_storage_sdk::{page_blob::consts::BLOB_PAGE_SIZE, AzureStorageError};
+
+use crate::{
+    blob_header::{BLOB_HEADER_SIZE_IN_PAGES, FLAG_COMPACTION_IN_PROGRESS},
+    compression::Compression,
+    encryption::Encryption,
+    read_write::{PackageBuilder, PageBlobSequenceWriter},
+    states::{ChangeState, GetNextPayloadResult, StateDataReading},
+    verify::open_read_only,
+    AppendPageBlobSettings, BlobHeader, PageBlobAppendError, RecordFormat, SparseIndex,
+    SparseIndexEntry,
+};
+
+pub enum RecordKey {
+    // Only the latest record with the key survives
+    Key(Vec<u8>),
+    // Removes the key. Dropped together with older records unless keep_tombstones is set
+    // or it is the last record of the blob
+    Tombstone(Vec<u8>),
+    // Record is always kept
+    NoKey,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CompactionReport {
+    pub records_before: usize,
+    pub records_after: usize,
+    pub payload_bytes_before: usize,
+    pub payload_bytes_after: usize,
+    pub blob_size_in_pages_before: usize,
+    pub blob_size_in_pages_after: usize,
+}
+
+// Nothing must append to the blob while it is compacted and PageBlobAppend opened before has to be reopened.
+// Records are written to compacted_blob first, then the original blob is copied to backup_blob.
+// After that the header of the original blob is marked and compacted_blob is copied over it.
+// Header page is copied last, so the mark is removed only when the copy is complete.
+// If the process stops before that, the blob is refused with CompactionInterrupted until
+// restore_interrupted_compaction copies the backup back.
+// Seqs of the records stay the same, so only the Crc32cWithSequence format is compacted.
+// Corrupted blob is not compacted. It has to be recovered first.
+// Sparse index of the blob is emptied before the blob is marked and rebuilt after the copy.
+// Record with the highest seq is always kept, so the writer continues the seqs after it
+pub async fn compact_blob<TMyPageBlob: MyPageBlob, TKeyExtractor: Fn(&[u8]) -> RecordKey>(
+    page_blob: TMyPageBlob,
+    mut compacted_blob: TMyPageBlob,
+    backup_blob: &mut TMyPageBlob,
+    settings: &AppendPageBlobSettings,
+    mut sparse_index: Option<&mut SparseIndex<TMyPageBlob>>,
+    keep_tombstones: bool,
+    key_extractor: TKeyExtractor,
+) -> Result<(TMyPageBlob, CompactionReport), PageBlobAppendError> {
+    let mut read_only = open_read_only(page_blob, settings).await?;
+
+    let mut report = CompactionReport {
+        blob_size_in_pages_before: read_only.blob_size_in_pages,
+        blob_size_in_pages_after: read_only.blob_size_in_pages,
+        ..Default::default()
+    };
+
+    if read_only.blob_size_in_pages == 0 {
+        return Ok((read_only.state.seq_reader.page_blob, report));
+    }
+
+    // Legacy and Crc32c records get their seqs by the order, so removed records would renumber the rest
+    let mut header = match read_only.header {
+        Some(header) if header.get_record_format() == RecordFormat::Crc32cWithSequence => header,
+        _ => {
+            let page_blob = &read_only.state.seq_reader.page_blob;
+
+            return Err(PageBlobAppendError::UnsupportedFormat(format!(
+                "Only the blob with the header and the Crc32cWithSequence format can be compacted. PageBlobAppend {}/{}",
+                page_blob.get_container_name(),
+                page_blob.get_blob_name()
+            )));
+        }
+    };
+
+    // First pass finds the seq of the latest record of every key
+    let mut latest_seqs: HashMap<Vec<u8>, u64> = HashMap::new();
+    let mut last_seq = 0;
+
+    while let Some((seq, payload)) = get_next_record(&mut read_only.state).await? {
+        report.records_before += 1;
+        last_seq = seq;
+        report.payload_bytes_before += payload.len();
+
+        match key_extractor(&payload) {
+            RecordKey::Key(key) | RecordKey::Tombstone(key) => {
+                latest_seqs.insert(key, seq);
+            }
+            RecordKey::NoKey => {}
+        }
+    }
+
+    let record_format = read_only.record_format;
+
+    header.flags |= settings.get_required_header_flags();
+    let compression = settings.compression;
+    let encryption = settings.encryption.clone();
+
+    let mut read_only = open_read_only(read_only.state.seq_reader.page_blob, settings).await?;
+
+    crate::with_retries::create_container_if_not_exist(
+        &mut compacted_blob,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+    crate::with_retries::create_blob_if_not_exists(
+        &mut compacted_blob,
+        0,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+    crate::with_retries::resize_page_blob(
+        &mut compacted_blob,
+        0,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+
+    // Original header is kept, so the compacted blob has the same format and created time
+    crate::states::write_header(&mut compacted_blob, &header, settings).await?;
+
+    let mut writer = PageBlobSequenceWriter::brand_new(
+        compacted_blob,
+        settings,
+        BlobHeader::get_data_start_position(),
+    );
+
+    let max_package_size = settings.max_pages_to_write_single_round_trip * BLOB_PAGE_SIZE;
+    let mut package = new_package(&writer, record_format, compression, &encryption)?;
+    let mut index_entries: Vec<SparseIndexEntry> = Vec::new();
+
+    // Second pass writes the records which survived. Seqs stay the same
+    while let Some((seq, payload)) = get_next_record(&mut read_only.state).await? {
+        let keep = match key_extractor(&payload) {
+            RecordKey::Key(key) => latest_seqs.get(&key) == Some(&seq),
+            RecordKey::Tombstone(key) => keep_tombstones && latest_seqs.get(&key) == Some(&seq),
+            RecordKey::NoKey => true,
+        };
+
+        // Dropped tombstone at the end would make the writer issue its seq again
+        if !keep && seq != last_seq {
+            continue;
+        }
+
+        report.records_after += 1;
+        report.payload_bytes_after += payload.len();
+
+        if let Some(sparse_index) = &sparse_index {
+            let blob_position = writer.write_cache.write_position + package.get_records_size();
+
+            let is_due = match index_entries.last() {
+                Some(last_entry) => {
+                    blob_position >= last_entry.blob_position + sparse_index.interval_in_bytes
+                }
+                None => true,
+            };
+
+            if is_due {
+                index_entries.push(SparseIndexEntry { seq, blob_position });
+            }
+        }
+
+        package.add_payload_with_seq(seq, &payload)?;
+
+        if package.get_records_size() >= max_package_size {
+            writer.append(package).await?;
+            package = new_package(&writer, record_format, compression, &encryption)?;
+        }
+    }
+
+    // Writes the end marker even if no record survived
+    writer.append(package).await?;
+
+    let mut compacted_blob = writer.page_blob;
+    let mut page_blob = read_only.state.seq_reader.page_blob;
+
+    crate::states::copy_blob(
+        &mut page_blob,
+        backup_blob,
+        settings.max_pages_to_write_single_round_trip,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+
+    // Index entries point at the records of the blob before the compaction.
+    // Empty index only makes seek read from the start
+    if let Some(sparse_index) = &mut sparse_index {
+        sparse_index.truncate(0).await?;
+    }
+
+    // Backup is complete, so from now on the blob can be restored from it
+    let mut marked_header = header.clone();
+    marked_header.flags |= FLAG_COMPACTION_IN_PROGRESS;
+
+    crate::with_retries::write_pages(
+        &mut page_blob,
+        0,
+        1,
+        marked_header.serialize(),
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+
+    copy_blob_header_last(&mut compacted_blob, &mut page_blob, settings).await?;
+
+    if let Some(sparse_index) = sparse_index {
+        for entry in index_entries {
+            sparse_index.append(entry).await?;
+        }
+    }
+
+    crate::with_retries::delete_blob_if_exists(
+        &mut compacted_blob,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+
+    report.blob_size_in_pages_after = crate::with_retries::get_available_pages_amount(
+        &mut page_blob,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+
+    Ok((page_blob, report))
+}
+
+// Copies backup_blob back if compact_blob stopped after it marked the blob.
+// Returns false if the blob is not marked
+pub async fn restore_interrupted_compaction<TMyPageBlob: MyPageBlob>(
+    page_blob: &mut TMyPageBlob,
+    backup_blob: &mut TMyPageBlob,
+    settings: &AppendPageBlobSettings,
+) -> Result<bool, PageBlobAppendError> {
+    let blob_size_in_pages = crate::with_retries::get_available_pages_amount(
+        page_blob,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+
+    if blob_size_in_pages == 0 {
+        return Ok(false);
+    }
+
+    let first_page =
+        crate::with_retries::read_pages(page_blob, 0, 1, &settings.retry_policy, &settings.metrics)
+            .await?;
+
+    if !BlobHeader::is_header(&first_page) {
+        return Ok(false);
+    }
+
+    match BlobHeader::deserialize(&first_page) {
+        Ok(header) if header.is_compaction_in_progress() => {}
+        _ => return Ok(false),
+    }
+
+    // The mark stays until the header of the backup is copied
+    copy_blob_header_last(backup_blob, page_blob, settings).await?;
+
+    Ok(true)
+}
+
+async fn copy_blob_header_last<TMyPageBlob: MyPageBlob>(
+    src: &mut TMyPageBlob,
+    dest: &mut TMyPageBlob,
+    settings: &AppendPageBlobSettings,
+) -> Result<(), AzureStorageError> {
+    let src_pages_amount = crate::with_retries::get_available_pages_amount(
+        src,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+
+    crate::with_retries::resize_page_blob(
+        dest,
+        src_pages_amount,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+
+    let max_pages_per_write = settings.max_pages_to_write_single_round_trip.max(1);
+    let mut page_no = BLOB_HEADER_SIZE_IN_PAGES;
+
+    while page_no < src_pages_amount {
+        let pages_to_copy = (src_pages_amount - page_no).min(max_pages_per_write);
+
+        let payload = crate::with_retries::read_pages(
+            src,
+            page_no,
+            pages_to_copy,
+            &settings.retry_policy,
+            &settings.metrics,
+        )
+        .await?;
+
+        crate::with_retries::write_pages(
+            dest,
+            page_no,
+            max_pages_per_write,
+            payload,
+            &settings.retry_policy,
+            &settings.metrics,
+        )
+        .await?;
+
+        page_no += pages_to_copy;
+    }
+
+    let header_page = crate::with_retries::read_pages(
+        src,
+        0,
+        BLOB_HEADER_SIZE_IN_PAGES,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+
+    crate::with_retries::write_pages(
+        dest,
+        0,
+        BLOB_HEADER_SIZE_IN_PAGES,
+        header_page,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await
+}
+
+fn new_package<TMyPageBlob: MyPageBlob>(
+    writer: &PageBlobSequenceWriter<TMyPageBlob>,
+    record_format: RecordFormat,
+    compression: Compression,
+    encryption: &Option<Encryption>,
+) -> Result<PackageBuilder, PageBlobAppendError> {
+    let encryptor = match encryption {
+        Some(encryption) => Some(encryption.get_encryptor()?),
+        None => None,
+    };
+
+    Ok(writer
+        .new_package(record_format, 1)
+        .with_compression(compression)
+        .with_encryptor(encryptor))
+}
+
+async fn get_next_record<TMyPageBlob: MyPageBlob>(
+    state: &mut StateDataReading<TMyPageBlob>,
+) -> Result<Option<(u64, Bytes)>, PageBlobAppendError> {
+    match state.get_next_payload().await? {
+        GetNextPayloadResult::NextPayload(seq, payload) => Ok(Some((seq, payload))),
+        GetNextPayloadResult::ChangeState(ChangeState::ToCorrupted(info)) => {
+            Err(PageBlobAppendError::Corrupted(info))
+        }
+        GetNextPayloadResult::ChangeState(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use my_azure_page_blob::MyPageBlobMock;
+
+    use super::*;
+    use crate::{
+        test_utils::{copy_blob, create_blob, get_settings},
+        FaultInjectingPageBlob, FilePageBlob, PageBlobAppend,
+    };
+
+    // Payload is <key>=<value>. Empty value is a tombstone. Payload without = has no key
+    fn get_key(payload: &[u8]) -> RecordKey {
+        match payload.iter().position(|b| *b == b'=') {
+            Some(index) if index == payload.len() - 1 => {
+                RecordKey::Tombstone(payload[..index].to_vec())
+            }
+            Some(index) => RecordKey::Key(payload[..index].to_vec()),
+            None => RecordKey::NoKey,
+        }
+    }
+
+    async fn write_records(records: &[&[u8]]) -> MyPageBlobMock {
+        let mut writer = PageBlobAppend::new(
+            create_blob().await,
+            get_settings(RecordFormat::Crc32cWithSequence),
+        );
+        assert!(writer.get_next_payload().await.unwrap().is_none());
+
+        let records: Vec<Vec<u8>> = records.iter().map(|record| record.to_vec()).collect();
+        writer.append_and_write(&records).await.unwrap();
+
+        copy_blob(writer.get_page_blob_mut()).await
+    }
+
+    async fn read_records(page_blob: MyPageBlobMock) -> Vec<(u64, Vec<u8>)> {
+        let mut reader =
+            PageBlobAppend::new(page_blob, get_settings(RecordFormat::Crc32cWithSequence));
+        let mut result = Vec::new();
+
+        while let Some(record) = reader.get_next_payload().await.unwrap() {
+            result.push(record);
+        }
+
+        result
+    }
+
+    #[tokio::test]
+    async fn test_latest_record_per_key_is_kept() {
+        let page_blob = write_records(&[b"a=1", b"b=1", b"no key", b"a=2", b"b=", b"c=1"]).await;
+
+        let mut backup_blob = create_blob().await;
+
+        let (page_blob, report) = compact_blob(
+            page_blob,
+            create_blob().await,
+            &mut backup_blob,
+            &get_settings(RecordFormat::Crc32cWithSequence),
+            None,
+            false,
+            get_key,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(6, report.records_before);
+        assert_eq!(3, report.records_after);
+
+        let records = read_records(page_blob).await;
+
+        assert_eq!(
+            vec![
+                (3, b"no key".to_vec()),
+                (4, b"a=2".to_vec()),
+                (6, b"c=1".to_vec())
+            ],
+            records
+        );
+
+        // Backup keeps the content before the compaction
+        assert_eq!(6, read_records(backup_blob).await.len());
+    }
+
+    #[tokio::test]
+    async fn test_tombstone_with_the_last_seq_is_kept() {
+        let page_blob = write_records(&[b"a=1", b"a=2", b"b="]).await;
+
+        let (page_blob, report) = compact_blob(
+            page_blob,
+            create_blob().await,
+            &mut create_blob().await,
+            &get_settings(RecordFormat::Crc32cWithSequence),
+            None,
+            false,
+            get_key,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(2, report.records_after);
+
+        let mut writer =
+            PageBlobAppend::new(page_blob, get_settings(RecordFormat::Crc32cWithSequence));
+        assert_eq!(
+            Some((2, b"a=2".to_vec())),
+            writer.get_next_payload().await.unwrap()
+        );
+        assert_eq!(
+            Some((3, b"b=".to_vec())),
+            writer.get_next_payload().await.unwrap()
+        );
+        assert!(writer.get_next_payload().await.unwrap().is_none());
+
+        let seq_range = writer
+            .append_and_write(&vec![b"c=1".to_vec()])
+            .await
+            .unwrap();
+        assert!(seq_range.start > 3);
+    }
+
+    #[tokio::test]
+    async fn test_tombstones_can_be_kept() {
+        let page_blob = write_records(&[b"a=1", b"b=1", b"b="]).await;
+
+        let (page_blob, report) = compact_blob(
+            page_blob,
+            create_blob().await,
+            &mut create_blob().await,
+            &get_settings(RecordFormat::Crc32cWithSequence),
+            None,
+            true,
+            get_key,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(2, report.records_after);
+
+        let records = read_records(page_blob).await;
+        assert_eq!(vec![(1, b"a=1".to_vec()), (3, b"b=".to_vec())], records);
+    }
+
+    #[tokio::test]
+    async fn test_sparse_index_is_rebuilt() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let mut writer = PageBlobAppend::new(create_blob().await, settings.clone());
+        writer.set_sparse_index(SparseIndex::new(create_blob().await, 256));
+        assert!(writer.get_next_payload().await.unwrap().is_none());
+
+        for i in 1..=40u32 {
+            writer
+                .append_and_write(&vec![format!("{}={:0100}", i % 10, i).into_bytes()])
+                .await
+                .unwrap();
+        }
+
+        let page_blob = copy_blob(writer.get_page_blob_mut()).await;
+        let index_blob = copy_blob(&mut writer.get_sparse_index_mut().unwrap().page_blob).await;
+        let mut sparse_index = SparseIndex::new(index_blob, 256);
+
+        let (mut page_blob, _) = compact_blob(
+            page_blob,
+            create_blob().await,
+            &mut create_blob().await,
+            &settings,
+            Some(&mut sparse_index),
+            false,
+            get_key,
+        )
+        .await
+        .unwrap();
+
+        // Only the records 31..=40 are left
+        let first_entry = sparse_index.get_entry(0).await.unwrap().unwrap();
+        assert_eq!(31, first_entry.seq);
+        assert_eq!(
+            BlobHeader::get_data_start_position(),
+            first_entry.blob_position
+        );
+
+        let mut reader = PageBlobAppend::new(copy_blob(&mut page_blob).await, settings.clone());
+        reader.set_sparse_index(SparseIndex::new(
+            copy_blob(&mut sparse_index.page_blob).await,
+            256,
+        ));
+
+        reader.seek(37).await.unwrap();
+        let (seq, payload) = reader.get_next_payload().await.unwrap().unwrap();
+        assert_eq!(37, seq);
+        assert_eq!(format!("7={:0100}", 37).into_bytes(), payload);
+        assert!(reader.get_recovery_report().is_empty());
+    }
+
+    async fn read_all<TMyPageBlob: MyPageBlob>(
+        page_blob: TMyPageBlob,
+    ) -> Result<Vec<(u64, Vec<u8>)>, PageBlobAppendError> {
+        let mut reader =
+            PageBlobAppend::new(page_blob, get_settings(RecordFormat::Crc32cWithSequence));
+        let mut result = Vec::new();
+
+        while let Some(record) = reader.get_next_payload().await? {
+            result.push(record);
+        }
+
+        Ok(result)
+    }
+
+    #[tokio::test]
+    async fn test_crashed_compaction_is_restored_from_backup() {
+        let root_folder = std::env::temp_dir().join(format!(
+            "page-blob-append-compaction-crash-{}",
+            std::process::id()
+        ));
+
+        // Every write is a single page, so the blob is replaced by many writes
+        let mut settings = get_settings(RecordFormat::Crc32cWithSequence);
+        settings.max_pages_to_write_single_round_trip = 1;
+
+        let records: Vec<Vec<u8>> = (0..40u32)
+            .map(|i| format!("{}={:0300}", i % 20, i).into_bytes())
+            .collect();
+
+        let mut interrupted = 0;
+
+        for seed in 0..64 {
+            let _ = std::fs::remove_dir_all(&root_folder);
+            let get_blob = |blob_name: &str| FilePageBlob::new(&root_folder, "c", blob_name);
+
+            let mut writer = PageBlobAppend::new(
+                get_blob("log"),
+                get_settings(RecordFormat::Crc32cWithSequence),
+            );
+            writer.init_blob(None).await.unwrap();
+            writer.append_and_write(&records).await.unwrap();
+
+            let before = read_all(get_blob("log")).await.unwrap();
+            let after: Vec<(u64, Vec<u8>)> = before[before.len() - 20..].to_vec();
+
+            let result = compact_blob(
+                FaultInjectingPageBlob::new(get_blob("log"), seed, 30),
+                FaultInjectingPageBlob::new(get_blob("compacted"), seed, 0),
+                &mut FaultInjectingPageBlob::new(get_blob("backup"), seed, 0),
+                &settings,
+                None,
+                false,
+                get_key,
+            )
+            .await;
+
+            let records_after_crash = match read_all(get_blob("log")).await {
+                Ok(records) => records,
+                Err(err) => {
+                    assert!(err.is_compaction_interrupted(), "Seed {}: {:?}", seed, err);
+                    interrupted += 1;
+
+                    let restored = restore_interrupted_compaction(
+                        &mut get_blob("log"),
+                        &mut get_blob("backup"),
+                        &settings,
+                    )
+                    .await
+                    .unwrap();
+                    assert!(restored);
+
+                    let records = read_all(get_blob("log")).await.unwrap();
+                    assert_eq!(before, records, "Seed {}", seed);
+                    records
+                }
+            };
+
+            if result.is_ok() {
+                assert_eq!(after, records_after_crash, "Seed {}", seed);
+            } else {
+                assert!(
+                    records_after_crash == before || records_after_crash == after,
+                    "Seed {}",
+                    seed
+                );
+            }
+        }
+
+        assert!(interrupted > 0);
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+
+    #[tokio::test]
+    async fn test_format_without_seqs_is_refused() {
+        let mut settings = get_settings(RecordFormat::Crc32cWithSequence);
+        settings.record_format = crate::RecordFormat::Crc32c;
+
+        let mut writer = PageBlobAppend::new(create_blob().await, settings.clone());
+        assert!(writer.get_next_payload().await.unwrap().is_none());
+        writer
+            .append_and_write(&vec![b"a=1".to_vec(), b"a=2".to_vec()])
+            .await
+            .unwrap();
+
+        let page_blob = std::mem::replace(writer.get_page_blob_mut(), create_blob().await);
+
+        let err = compact_blob(
+            page_blob,
+            create_blob().await,
+            &mut create_blob().await,
+            &settings,
+            None,
+            false,
+            get_key,
+        )
+        .await
+        .err()
+        .unwrap();
+
+        assert!(err.is_unsupported_format());
+    }
+
+    #[tokio::test]
+    async fn test_compacted_blob_can_be_appended() {
+        let page_blob = write_records(&[b"a=1", b"a=2"]).await;
+
+        let (page_blob, _) = compact_blob(
+            page_blob,
+            create_blob().await,
+            &mut create_blob().await,
+            &get_settings(RecordFormat::Crc32cWithSequence),
+            None,
+            false,
+            get_key,
+        )
+        .await
+        .unwrap();
+
+        let mut writer =
+            PageBlobAppend::new(page_blob, get_settings(RecordFormat::Crc32cWithSequence));
+        assert!(writer.get_next_payload().await.unwrap().is_some());
+        assert!(writer.get_next_payload().await.unwrap().is_none());
+
+        let seq_range = writer
+            .append_and_write(&vec![b"a=3".to_vec()])
+            .await
+            .unwrap();
+        assert_eq!(3..4, seq_range);
+    }
+}
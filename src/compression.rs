@@ -0,0 +1,152 @@
+use std::borrow::Cow;
+
+use bytes::Bytes;
+
+// Codec of a record is kept in its size field. See RecordSizeField
+pub const CODEC_NONE: u32 = 0;
+pub const CODEC_LZ4: u32 = 1;
+pub const CODEC_ZSTD: u32 = 2;
+
+// Every record is compressed separately, so record positions, checkpoints and seeks keep working
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    #[cfg(feature = "lz4")]
+    Lz4,
+    #[cfg(feature = "zstd")]
+    Zstd { level: i32 },
+}
+
+impl Compression {
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, Self::None)
+    }
+
+    // Payload is stored as is if compression does not make it smaller
+    pub fn compress<'s>(&self, payload: &'s [u8]) -> (u32, Cow<'s, [u8]>) {
+        let compressed: Option<(u32, Vec<u8>)> = match self {
+            Self::None => None,
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => Some((CODEC_LZ4, lz4_flex::compress_prepend_size(payload))),
+            #[cfg(feature = "zstd")]
+            Self::Zstd { level } => zstd::bulk::compress(payload, *level)
+                .ok()
+                .map(|compressed| (CODEC_ZSTD, compressed)),
+        };
+
+        match compressed {
+            Some((codec, compressed)) if compressed.len() < payload.len() => {
+                (codec, Cow::Owned(compressed))
+            }
+            _ => (CODEC_NONE, Cow::Borrowed(payload)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DecompressError {
+    // Codec is unknown or its feature is not enabled. Data is fine, so it must not be treated as corruption
+    UnsupportedCodec(String),
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(dead_code))]
+    InvalidData(String),
+}
+
+// Uncompressed data is given back as is
+pub fn decompress(codec: u32, data: Bytes, max_size: usize) -> Result<Bytes, DecompressError> {
+    match codec {
+        CODEC_NONE => Ok(data),
+        CODEC_LZ4 => decompress_lz4(&data, max_size).map(Bytes::from),
+        CODEC_ZSTD => decompress_zstd(&data, max_size).map(Bytes::from),
+        _ => Err(DecompressError::UnsupportedCodec(format!(
+            "Unknown compression codec {}",
+            codec
+        ))),
+    }
+}
+
+#[cfg(feature = "lz4")]
+fn decompress_lz4(data: &[u8], max_size: usize) -> Result<Vec<u8>, DecompressError> {
+    // Size is checked before the buffer is allocated
+    if data.len() < 4 {
+        return Err(DecompressError::InvalidData(
+            "Lz4 block is too short".to_string(),
+        ));
+    }
+
+    let size = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+
+    if size > max_size {
+        return Err(DecompressError::InvalidData(format!(
+            "Decompressed size {} is too huge. Maximum allowed amount is {}",
+            size, max_size
+        )));
+    }
+
+    lz4_flex::decompress_size_prepended(data)
+        .map_err(|err| DecompressError::InvalidData(format!("{:?}", err)))
+}
+
+#[cfg(not(feature = "lz4"))]
+fn decompress_lz4(_data: &[u8], _max_size: usize) -> Result<Vec<u8>, DecompressError> {
+    Err(DecompressError::UnsupportedCodec(
+        "Record is compressed with Lz4. Enable the lz4 feature to read it".to_string(),
+    ))
+}
+
+#[cfg(feature = "zstd")]
+fn decompress_zstd(data: &[u8], max_size: usize) -> Result<Vec<u8>, DecompressError> {
+    zstd::bulk::decompress(data, max_size)
+        .map_err(|err| DecompressError::InvalidData(format!("{:?}", err)))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress_zstd(_data: &[u8], _max_size: usize) -> Result<Vec<u8>, DecompressError> {
+    Err(DecompressError::UnsupportedCodec(
+        "Record is compressed with Zstd. Enable the zstd feature to read it".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_incompressible_payload_is_stored_as_is() {
+        let (codec, data) = Compression::None.compress(&[1u8, 2u8, 3u8]);
+        assert_eq!(CODEC_NONE, codec);
+        assert_eq!(&[1u8, 2u8, 3u8], data.as_ref());
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_lz4() {
+        let payload = vec![7u8; 1000];
+        let (codec, data) = Compression::Lz4.compress(&payload);
+
+        assert_eq!(CODEC_LZ4, codec);
+        assert!(data.len() < payload.len());
+
+        assert_eq!(
+            payload,
+            decompress(codec, Bytes::from(data.to_vec()), 1000).unwrap()
+        );
+        assert!(decompress(codec, Bytes::from(data.to_vec()), 999).is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        let payload = vec![7u8; 1000];
+        let (codec, data) = Compression::Zstd { level: 3 }.compress(&payload);
+
+        assert_eq!(CODEC_ZSTD, codec);
+        assert!(data.len() < payload.len());
+
+        assert_eq!(
+            payload,
+            decompress(codec, Bytes::from(data.to_vec()), 1000).unwrap()
+        );
+        assert!(decompress(codec, Bytes::from(data.to_vec()), 999).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use std::{future::Future, ops::Range, time::Duration};
+
+use my_azure_page_blob::MyPageBlob;
+use my_azure_storage_sdk::AzureStorageError;
+
+use crate::PageBlobAppendError;
+
+#[cfg(feature = "tracing")]
+use crate::metrics_sink::get_error_kind;
+
+// Without the tracing feature diagnostics and spans are no-ops.
+// error_kind of the storage events is the same as the one given to the metrics sink
+
+#[cfg(feature = "tracing")]
+pub type OperationSpan = tracing::Span;
+
+#[cfg(not(feature = "tracing"))]
+pub struct OperationSpan;
+
+#[cfg(feature = "tracing")]
+pub type EnteredSpan = tracing::span::EnteredSpan;
+
+#[cfg(not(feature = "tracing"))]
+pub struct EnteredSpan;
+
+#[cfg(feature = "tracing")]
+pub fn operation_span<TMyPageBlob: MyPageBlob>(
+    operation: &'static str,
+    page_blob: &TMyPageBlob,
+) -> OperationSpan {
+    tracing::info_span!(
+        "page_blob_append",
+        operation,
+        container = page_blob.get_container_name(),
+        blob = page_blob.get_blob_name(),
+    )
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn operation_span<TMyPageBlob: MyPageBlob>(
+    _operation: &'static str,
+    _page_blob: &TMyPageBlob,
+) -> OperationSpan {
+    OperationSpan
+}
+
+#[cfg(feature = "tracing")]
+pub async fn instrument<TFuture: Future>(future: TFuture, span: OperationSpan) -> TFuture::Output {
+    use tracing::Instrument;
+    future.instrument(span).await
+}
+
+#[cfg(not(feature = "tracing"))]
+pub async fn instrument<TFuture: Future>(future: TFuture, _span: OperationSpan) -> TFuture::Output {
+    future.await
+}
+
+#[cfg(feature = "tracing")]
+pub fn enter_state_transition<TMyPageBlob: MyPageBlob>(
+    page_blob: &TMyPageBlob,
+    from: &'static str,
+    to: &'static str,
+) -> EnteredSpan {
+    let span = tracing::info_span!(
+        "state_transition",
+        container = page_blob.get_container_name(),
+        blob = page_blob.get_blob_name(),
+        from,
+        to,
+    );
+
+    let entered = span.entered();
+    tracing::debug!(from, to, "State changed");
+    entered
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn enter_state_transition<TMyPageBlob: MyPageBlob>(
+    _page_blob: &TMyPageBlob,
+    _from: &'static str,
+    _to: &'static str,
+) -> EnteredSpan {
+    EnteredSpan
+}
+
+#[cfg(feature = "tracing")]
+pub fn retry_attempt(
+    operation: &'static str,
+    pages: &Option<Range<usize>>,
+    attempt_no: usize,
+    delay: Duration,
+    err: &AzureStorageError,
+) {
+    tracing::warn!(
+        operation,
+        attempt_no,
+        pages = ?pages,
+        delay_ms = delay.as_millis() as u64,
+        error_kind = get_error_kind(err),
+        error = ?err,
+        "Storage operation failed. Retrying"
+    );
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn retry_attempt(
+    _operation: &'static str,
+    _pages: &Option<Range<usize>>,
+    _attempt_no: usize,
+    _delay: Duration,
+    _err: &AzureStorageError,
+) {
+}
+
+#[cfg(feature = "tracing")]
+pub fn retries_exhausted(
+    operation: &'static str,
+    pages: &Option<Range<usize>>,
+    attempt_no: usize,
+    err: &AzureStorageError,
+) {
+    tracing::error!(
+        operation,
+        attempt_no,
+        pages = ?pages,
+        error_kind = get_error_kind(err),
+        error = ?err,
+        "Storage operation failed. Giving up"
+    );
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn retries_exhausted(
+    _operation: &'static str,
+    _pages: &Option<Range<usize>>,
+    _attempt_no: usize,
+    _err: &AzureStorageError,
+) {
+}
+
+#[cfg(feature = "tracing")]
+pub fn corruption_detected<TMyPageBlob: MyPageBlob>(
+    page_blob: &TMyPageBlob,
+    broken_pos: usize,
+    msg: &str,
+) {
+    tracing::error!(
+        container = page_blob.get_container_name(),
+        blob = page_blob.get_blob_name(),
+        broken_pos,
+        msg,
+        "Corruption detected"
+    );
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn corruption_detected<TMyPageBlob: MyPageBlob>(
+    _page_blob: &TMyPageBlob,
+    _broken_pos: usize,
+    _msg: &str,
+) {
+}
+
+#[cfg(feature = "tracing")]
+pub fn sparse_index_update_failed<TMyPageBlob: MyPageBlob>(
+    page_blob: &TMyPageBlob,
+    err: &AzureStorageError,
+) {
+    tracing::warn!(
+        container = page_blob.get_container_name(),
+        blob = page_blob.get_blob_name(),
+        error = ?err,
+        "Can not update sparse index"
+    );
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn sparse_index_update_failed<TMyPageBlob: MyPageBlob>(
+    _page_blob: &TMyPageBlob,
+    _err: &AzureStorageError,
+) {
+}
+
+#[cfg(feature = "tracing")]
+pub fn retention_failed(log_name: &str, err: &PageBlobAppendError) {
+    tracing::warn!(log_name, error = ?err, "Can not apply retention policy");
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn retention_failed(_log_name: &str, _err: &PageBlobAppendError) {}
+
+#[cfg(feature = "tracing")]
+pub fn snapshot_rejected<TMyPageBlob: MyPageBlob>(page_blob: &TMyPageBlob, reason: &str) {
+    tracing::warn!(
+        container = page_blob.get_container_name(),
+        blob = page_blob.get_blob_name(),
+        reason,
+        "Snapshot is rejected. Older snapshot or the whole log is used"
+    );
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn snapshot_rejected<TMyPageBlob: MyPageBlob>(_page_blob: &TMyPageBlob, _reason: &str) {}
@@ -0,0 +1,359 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::PageBlobAppendError;
+
+pub const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+
+// Encrypted payload is stored as [algorithm: u8][key id: u32 LE][nonce][ciphertext][tag].
+// Algorithm and key id are kept in every record, so keys and algorithms can be changed at any time.
+// Codec and seq of the record are authenticated as well, so records can not be swapped unnoticed
+const ENVELOPE_PREFIX_SIZE: usize = 1 + 4 + NONCE_SIZE;
+
+const ALGORITHM_AES_256_GCM: u8 = 1;
+const ALGORITHM_CHACHA20_POLY1305: u8 = 2;
+
+pub trait KeyProvider: Send + Sync {
+    // New records are encrypted with this key. Changing it rotates the key
+    fn get_current_key_id(&self) -> u32;
+
+    // Previous keys have to stay available while there are records encrypted with them.
+    // Called for every record read, so the keys are expected to be cached
+    fn get_key(&self, key_id: u32) -> Option<[u8; KEY_SIZE]>;
+}
+
+pub struct StaticKeyProvider {
+    current_key_id: u32,
+    keys: HashMap<u32, [u8; KEY_SIZE]>,
+}
+
+impl StaticKeyProvider {
+    pub fn new(current_key_id: u32, current_key: [u8; KEY_SIZE]) -> Self {
+        let mut keys = HashMap::new();
+        keys.insert(current_key_id, current_key);
+
+        Self {
+            current_key_id,
+            keys,
+        }
+    }
+
+    // Previous key which is used only to read records encrypted with it
+    pub fn with_key(mut self, key_id: u32, key: [u8; KEY_SIZE]) -> Self {
+        self.keys.insert(key_id, key);
+        self
+    }
+}
+
+impl KeyProvider for StaticKeyProvider {
+    fn get_current_key_id(&self) -> u32 {
+        self.current_key_id
+    }
+
+    fn get_key(&self, key_id: u32) -> Option<[u8; KEY_SIZE]> {
+        self.keys.get(&key_id).copied()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionAlgorithm {
+    #[cfg(feature = "aes-gcm")]
+    Aes256Gcm,
+    #[cfg(feature = "chacha20poly1305")]
+    ChaCha20Poly1305,
+}
+
+impl EncryptionAlgorithm {
+    fn get_id(&self) -> u8 {
+        match *self {
+            #[cfg(feature = "aes-gcm")]
+            Self::Aes256Gcm => ALGORITHM_AES_256_GCM,
+            #[cfg(feature = "chacha20poly1305")]
+            Self::ChaCha20Poly1305 => ALGORITHM_CHACHA20_POLY1305,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Encryption {
+    pub algorithm: EncryptionAlgorithm,
+    pub key_provider: Arc<dyn KeyProvider>,
+}
+
+impl Encryption {
+    pub fn new(algorithm: EncryptionAlgorithm, key_provider: Arc<dyn KeyProvider>) -> Self {
+        Self {
+            algorithm,
+            key_provider,
+        }
+    }
+
+    // Current key is resolved once per package
+    pub fn get_encryptor(&self) -> Result<RecordEncryptor, PageBlobAppendError> {
+        let key_id = self.key_provider.get_current_key_id();
+
+        let key = self.key_provider.get_key(key_id).ok_or_else(|| {
+            PageBlobAppendError::EncryptionFailed(format!("Key {} is not found", key_id))
+        })?;
+
+        let nonce_base = generate_nonce_base().map_err(PageBlobAppendError::EncryptionFailed)?;
+
+        Ok(RecordEncryptor {
+            algorithm: self.algorithm,
+            key_id,
+            key,
+            nonce_base,
+            records_encrypted: 0,
+        })
+    }
+}
+
+// Nonce of a record is the random nonce base of the package with the record number mixed in,
+// so nonces never repeat within a package and collide between packages only by chance
+pub struct RecordEncryptor {
+    algorithm: EncryptionAlgorithm,
+    key_id: u32,
+    key: [u8; KEY_SIZE],
+    nonce_base: [u8; NONCE_SIZE],
+    records_encrypted: u64,
+}
+
+impl RecordEncryptor {
+    pub fn encrypt(&mut self, payload: &[u8], aad: &[u8]) -> Vec<u8> {
+        let mut nonce = self.nonce_base;
+        for (dest, src) in nonce[4..]
+            .iter_mut()
+            .zip(self.records_encrypted.to_le_bytes())
+        {
+            *dest ^= src;
+        }
+        self.records_encrypted += 1;
+
+        let mut result = Vec::with_capacity(ENVELOPE_PREFIX_SIZE + payload.len() + TAG_SIZE);
+        result.push(self.algorithm.get_id());
+        result.extend_from_slice(&self.key_id.to_le_bytes());
+        result.extend_from_slice(&nonce);
+        result.extend_from_slice(&seal(self.algorithm, &self.key, &nonce, payload, aad));
+
+        result
+    }
+}
+
+#[cfg(any(feature = "aes-gcm", feature = "chacha20poly1305"))]
+fn generate_nonce_base() -> Result<[u8; NONCE_SIZE], String> {
+    let mut result = [0u8; NONCE_SIZE];
+    getrandom::fill(&mut result).map_err(|err| format!("Can not generate nonce. {}", err))?;
+    Ok(result)
+}
+
+#[cfg(not(any(feature = "aes-gcm", feature = "chacha20poly1305")))]
+fn generate_nonce_base() -> Result<[u8; NONCE_SIZE], String> {
+    Err("Encryption needs the aes-gcm or chacha20poly1305 feature".to_string())
+}
+
+pub fn get_aad(codec: u32, seq: Option<u64>) -> Vec<u8> {
+    let mut result = codec.to_le_bytes().to_vec();
+
+    if let Some(seq) = seq {
+        result.extend_from_slice(&seq.to_le_bytes());
+    }
+
+    result
+}
+
+#[derive(Debug)]
+pub enum DecryptError {
+    // Algorithm is unknown or its feature is not enabled
+    UnsupportedAlgorithm(String),
+    // Key is not available or the authentication tag does not match
+    Failed(String),
+}
+
+pub fn decrypt(
+    encryption: Option<&Encryption>,
+    data: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, DecryptError> {
+    if data.len() < ENVELOPE_PREFIX_SIZE + TAG_SIZE {
+        return Err(DecryptError::Failed(
+            "Encrypted payload is too short".to_string(),
+        ));
+    }
+
+    let algorithm = data[0];
+    let key_id = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+
+    let mut nonce = [0u8; NONCE_SIZE];
+    nonce.copy_from_slice(&data[5..ENVELOPE_PREFIX_SIZE]);
+
+    let encryption = encryption.ok_or_else(|| {
+        DecryptError::Failed(format!(
+            "Record is encrypted with key {}, but encryption is not configured",
+            key_id
+        ))
+    })?;
+
+    let key = encryption
+        .key_provider
+        .get_key(key_id)
+        .ok_or_else(|| DecryptError::Failed(format!("Key {} is not found", key_id)))?;
+
+    open(algorithm, &key, &nonce, &data[ENVELOPE_PREFIX_SIZE..], aad)
+}
+
+#[cfg_attr(
+    not(any(feature = "aes-gcm", feature = "chacha20poly1305")),
+    allow(unused_variables)
+)]
+fn seal(
+    algorithm: EncryptionAlgorithm,
+    key: &[u8; KEY_SIZE],
+    nonce: &[u8; NONCE_SIZE],
+    msg: &[u8],
+    aad: &[u8],
+) -> Vec<u8> {
+    // Fails only if the payload is bigger than 64GiB, which can not happen with u32 record sizes
+    match algorithm {
+        #[cfg(feature = "aes-gcm")]
+        EncryptionAlgorithm::Aes256Gcm => {
+            use aes_gcm::{aead::Aead, KeyInit};
+            aes_gcm::Aes256Gcm::new(key.into())
+                .encrypt(nonce.into(), aes_gcm::aead::Payload { msg, aad })
+                .expect("Aes256Gcm encryption failed")
+        }
+        #[cfg(feature = "chacha20poly1305")]
+        EncryptionAlgorithm::ChaCha20Poly1305 => {
+            use chacha20poly1305::{aead::Aead, KeyInit};
+            chacha20poly1305::ChaCha20Poly1305::new(key.into())
+                .encrypt(nonce.into(), chacha20poly1305::aead::Payload { msg, aad })
+                .expect("ChaCha20Poly1305 encryption failed")
+        }
+    }
+}
+
+#[cfg_attr(
+    not(any(feature = "aes-gcm", feature = "chacha20poly1305")),
+    allow(unused_variables)
+)]
+fn open(
+    algorithm: u8,
+    key: &[u8; KEY_SIZE],
+    nonce: &[u8; NONCE_SIZE],
+    msg: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, DecryptError> {
+    match algorithm {
+        #[cfg(feature = "aes-gcm")]
+        ALGORITHM_AES_256_GCM => {
+            use aes_gcm::{aead::Aead, KeyInit};
+            aes_gcm::Aes256Gcm::new(key.into())
+                .decrypt(nonce.into(), aes_gcm::aead::Payload { msg, aad })
+                .map_err(tag_mismatch)
+        }
+        #[cfg(not(feature = "aes-gcm"))]
+        ALGORITHM_AES_256_GCM => Err(DecryptError::UnsupportedAlgorithm(
+            "Record is encrypted with Aes256Gcm. Enable the aes-gcm feature to read it".to_string(),
+        )),
+        #[cfg(feature = "chacha20poly1305")]
+        ALGORITHM_CHACHA20_POLY1305 => {
+            use chacha20poly1305::{aead::Aead, KeyInit};
+            chacha20poly1305::ChaCha20Poly1305::new(key.into())
+                .decrypt(nonce.into(), chacha20poly1305::aead::Payload { msg, aad })
+                .map_err(tag_mismatch)
+        }
+        #[cfg(not(feature = "chacha20poly1305"))]
+        ALGORITHM_CHACHA20_POLY1305 => Err(DecryptError::UnsupportedAlgorithm(
+            "Record is encrypted with ChaCha20Poly1305. Enable the chacha20poly1305 feature to read it"
+                .to_string(),
+        )),
+        _ => Err(DecryptError::UnsupportedAlgorithm(format!(
+            "Unknown encryption algorithm {}",
+            algorithm
+        ))),
+    }
+}
+
+#[cfg(any(feature = "aes-gcm", feature = "chacha20poly1305"))]
+fn tag_mismatch<TErr>(_err: TErr) -> DecryptError {
+    DecryptError::Failed("Authentication tag mismatch. Wrong key or tampered record".to_string())
+}
+
+#[cfg(all(test, any(feature = "aes-gcm", feature = "chacha20poly1305")))]
+mod tests {
+    use super::*;
+
+    fn get_algorithms() -> Vec<EncryptionAlgorithm> {
+        vec![
+            #[cfg(feature = "aes-gcm")]
+            EncryptionAlgorithm::Aes256Gcm,
+            #[cfg(feature = "chacha20poly1305")]
+            EncryptionAlgorithm::ChaCha20Poly1305,
+        ]
+    }
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        for algorithm in get_algorithms() {
+            let encryption = Encryption::new(
+                algorithm,
+                Arc::new(StaticKeyProvider::new(1, [5u8; KEY_SIZE])),
+            );
+
+            let mut encryptor = encryption.get_encryptor().unwrap();
+
+            let first = encryptor.encrypt(&[1u8, 2u8, 3u8], &get_aad(0, Some(1)));
+            let second = encryptor.encrypt(&[1u8, 2u8, 3u8], &get_aad(0, Some(2)));
+
+            assert_eq!(ENVELOPE_PREFIX_SIZE + 3 + TAG_SIZE, first.len());
+
+            // Nonces differ within the package
+            assert_ne!(
+                first[5..ENVELOPE_PREFIX_SIZE],
+                second[5..ENVELOPE_PREFIX_SIZE]
+            );
+
+            let decrypted = decrypt(Some(&encryption), &first, &get_aad(0, Some(1))).unwrap();
+            assert_eq!(vec![1u8, 2u8, 3u8], decrypted);
+
+            // Record moved to another seq is rejected
+            let err = decrypt(Some(&encryption), &first, &get_aad(0, Some(2))).unwrap_err();
+            assert!(matches!(err, DecryptError::Failed(_)));
+
+            let mut tampered = first.clone();
+            *tampered.last_mut().unwrap() ^= 1;
+            let err = decrypt(Some(&encryption), &tampered, &get_aad(0, Some(1))).unwrap_err();
+            assert!(matches!(err, DecryptError::Failed(_)));
+        }
+    }
+
+    #[test]
+    fn test_unknown_key_and_algorithm() {
+        let algorithm = get_algorithms()[0];
+
+        let encryption = Encryption::new(
+            algorithm,
+            Arc::new(StaticKeyProvider::new(2, [5u8; KEY_SIZE])),
+        );
+
+        let encrypted = encryption
+            .get_encryptor()
+            .unwrap()
+            .encrypt(&[1u8], &get_aad(0, None));
+
+        let other_keys = Encryption::new(
+            algorithm,
+            Arc::new(StaticKeyProvider::new(3, [5u8; KEY_SIZE])),
+        );
+        let err = decrypt(Some(&other_keys), &encrypted, &get_aad(0, None)).unwrap_err();
+        assert!(matches!(err, DecryptError::Failed(_)));
+
+        let err = decrypt(None, &encrypted, &get_aad(0, None)).unwrap_err();
+        assert!(matches!(err, DecryptError::Failed(_)));
+
+        let mut unknown_algorithm = encrypted.clone();
+        unknown_algorithm[0] = 200;
+        let err = decrypt(Some(&encryption), &unknown_algorithm, &get_aad(0, None)).unwrap_err();
+        assert!(matches!(err, DecryptError::UnsupportedAlgorithm(_)));
+    }
+}
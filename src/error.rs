@@ -6,12 +6,39 @@ pub struct CorruptedErrorInfo {
     pub last_page: Option<Vec<u8>>,
 }
 
+#[derive(Debug, Clone)]
+pub struct DecodeErrorInfo {
+    pub seq: u64,
+    pub blob_position: usize,
+    pub msg: String,
+}
+
 #[derive(Debug)]
 pub enum PageBlobAppendError {
     NotInitialized,
     Corrupted(CorruptedErrorInfo),
     AzureStorageError(AzureStorageError),
     Forbidden(String),
+    UnsupportedFormat(String),
+    InvalidCheckpoint(String),
+    GroupCommitFailed(String),
+    // Another writer holds the blob lease. Nothing was written
+    LostOwnership(String),
+    // Current key is not available. Nothing was written
+    EncryptionFailed(String),
+    // Key of the record is not available or its authentication tag does not match.
+    // It is not a corruption, so recovery never drops such records
+    DecryptionFailed(String),
+    // Codec could not serialize the item. Nothing was written
+    EncodeFailed(String),
+    // Reading continues after the record, so the record can be skipped
+    DecodeFailed(DecodeErrorInfo),
+    // Record of size 0 would be read as the end marker. Nothing was written
+    EmptyPayload(String),
+    // Blob has to be restored from the backup with restore_interrupted_compaction
+    CompactionInterrupted(String),
+    // Stored payload does not fit into the record size field. Nothing was written
+    PayloadTooLarge(String),
 }
 
 impl PageBlobAppendError {
@@ -21,6 +48,62 @@ impl PageBlobAppendError {
         }
         return false;
     }
+
+    pub fn is_unsupported_format(&self) -> bool {
+        if let Self::UnsupportedFormat(_) = self {
+            return true;
+        }
+        false
+    }
+
+    pub fn is_invalid_checkpoint(&self) -> bool {
+        if let Self::InvalidCheckpoint(_) = self {
+            return true;
+        }
+        false
+    }
+
+    pub fn is_lost_ownership(&self) -> bool {
+        if let Self::LostOwnership(_) = self {
+            return true;
+        }
+        false
+    }
+
+    pub fn is_decryption_failed(&self) -> bool {
+        if let Self::DecryptionFailed(_) = self {
+            return true;
+        }
+        false
+    }
+
+    pub fn is_decode_failed(&self) -> bool {
+        if let Self::DecodeFailed(_) = self {
+            return true;
+        }
+        false
+    }
+
+    pub fn is_empty_payload(&self) -> bool {
+        if let Self::EmptyPayload(_) = self {
+            return true;
+        }
+        false
+    }
+
+    pub fn is_compaction_interrupted(&self) -> bool {
+        if let Self::CompactionInterrupted(_) = self {
+            return true;
+        }
+        false
+    }
+
+    pub fn is_payload_too_large(&self) -> bool {
+        if let Self::PayloadTooLarge(_) = self {
+            return true;
+        }
+        false
+    }
 }
 
 impl From<AzureStorageError> for PageBlobAppendError {
@@ -0,0 +1,419 @@
+use async_trait::async_trait;
+use my_azure_page_blob::MyPageBlob;
+use my_azure_storage_sdk::{page_blob::consts::BLOB_PAGE_SIZE, AzureStorageError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageBlobFault {
+    // Write does not reach the blob
+    DropWrite,
+    // Only the first pages of the write reach the blob
+    TearWrite,
+    // hyper::Error can not be constructed outside of hyper, so the transport failure is reported as UnknownError
+    TransportError,
+    // Resize stops somewhere between the current and the requested size
+    TruncateOnResize,
+}
+
+// Decorator which fails operations of the inner blob following a schedule derived from the seed.
+// Every operation fails with probability 1/fail_one_in. Any failure should be treated as a crash
+pub struct FaultInjectingPageBlob<TMyPageBlob: MyPageBlob> {
+    pub inner: TMyPageBlob,
+    fail_one_in: u64,
+    rng_state: u64,
+    injected_faults: Vec<PageBlobFault>,
+}
+
+impl<TMyPageBlob: MyPageBlob> FaultInjectingPageBlob<TMyPageBlob> {
+    pub fn new(inner: TMyPageBlob, seed: u64, fail_one_in: u64) -> Self {
+        Self {
+            inner,
+            fail_one_in,
+            // Xorshift state must not be zero
+            rng_state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+            injected_faults: Vec::new(),
+        }
+    }
+
+    pub fn disable_faults(&mut self) {
+        self.fail_one_in = 0;
+    }
+
+    pub fn get_injected_faults(&self) -> &[PageBlobFault] {
+        &self.injected_faults
+    }
+
+    fn next_random(&mut self) -> u64 {
+        let mut x = self.rng_state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng_state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn should_fail(&mut self) -> bool {
+        if self.fail_one_in == 0 {
+            return false;
+        }
+
+        self.next_random().is_multiple_of(self.fail_one_in)
+    }
+
+    fn pick_fault(&mut self, faults: &[PageBlobFault]) -> Option<PageBlobFault> {
+        if !self.should_fail() {
+            return None;
+        }
+
+        let fault = faults[(self.next_random() % faults.len() as u64) as usize];
+        self.injected_faults.push(fault);
+        Some(fault)
+    }
+
+    fn pick_write_fault(&mut self) -> Option<PageBlobFault> {
+        self.pick_fault(&[
+            PageBlobFault::DropWrite,
+            PageBlobFault::TearWrite,
+            PageBlobFault::TransportError,
+        ])
+    }
+
+    // Keeps whole pages only. Amount of kept pages is less than the amount of pages in the payload
+    fn tear_payload(&mut self, mut payload: Vec<u8>) -> Vec<u8> {
+        let pages_amount =
+            crate::page_blob_utils::get_pages_amount_by_size(payload.len(), BLOB_PAGE_SIZE);
+
+        if pages_amount == 0 {
+            return payload;
+        }
+
+        let pages_to_keep = (self.next_random() % pages_amount as u64) as usize;
+        payload.truncate(pages_to_keep * BLOB_PAGE_SIZE);
+        payload
+    }
+}
+
+fn injected_error(fault: PageBlobFault) -> AzureStorageError {
+    AzureStorageError::UnknownError {
+        msg: format!("Injected fault: {:?}", fault),
+    }
+}
+
+#[async_trait]
+impl<TMyPageBlob: MyPageBlob + Send + Sync> MyPageBlob for FaultInjectingPageBlob<TMyPageBlob> {
+    fn get_container_name(&self) -> &str {
+        self.inner.get_container_name()
+    }
+
+    fn get_blob_name(&self) -> &str {
+        self.inner.get_blob_name()
+    }
+
+    async fn create_container_if_not_exist(&mut self) -> Result<(), AzureStorageError> {
+        if let Some(fault) = self.pick_fault(&[PageBlobFault::TransportError]) {
+            return Err(injected_error(fault));
+        }
+
+        self.inner.create_container_if_not_exist().await
+    }
+
+    async fn create_if_not_exists(
+        &mut self,
+        init_pages_amounts: usize,
+    ) -> Result<(), AzureStorageError> {
+        if let Some(fault) = self.pick_fault(&[PageBlobFault::TransportError]) {
+            return Err(injected_error(fault));
+        }
+
+        self.inner.create_if_not_exists(init_pages_amounts).await
+    }
+
+    async fn get_available_pages_amount(&mut self) -> Result<usize, AzureStorageError> {
+        if let Some(fault) = self.pick_fault(&[PageBlobFault::TransportError]) {
+            return Err(injected_error(fault));
+        }
+
+        self.inner.get_available_pages_amount().await
+    }
+
+    async fn get(
+        &mut self,
+        start_page_no: usize,
+        pages_to_read: usize,
+    ) -> Result<Vec<u8>, AzureStorageError> {
+        if let Some(fault) = self.pick_fault(&[PageBlobFault::TransportError]) {
+            return Err(injected_error(fault));
+        }
+
+        self.inner.get(start_page_no, pages_to_read).await
+    }
+
+    async fn resize(&mut self, pages_amount: usize) -> Result<(), AzureStorageError> {
+        let fault = self.pick_fault(&[
+            PageBlobFault::TruncateOnResize,
+            PageBlobFault::TransportError,
+        ]);
+
+        match fault {
+            Some(PageBlobFault::TruncateOnResize) => {
+                let current_pages_amount = self.inner.get_available_pages_amount().await?;
+
+                let (from, to) = if current_pages_amount < pages_amount {
+                    (current_pages_amount, pages_amount)
+                } else {
+                    (pages_amount, current_pages_amount)
+                };
+
+                let truncated_pages_amount =
+                    from + (self.next_random() % (to - from + 1) as u64) as usize;
+
+                self.inner.resize(truncated_pages_amount).await?;
+                Err(injected_error(PageBlobFault::TruncateOnResize))
+            }
+            Some(fault) => Err(injected_error(fault)),
+            None => self.inner.resize(pages_amount).await,
+        }
+    }
+
+    async fn save_pages(
+        &mut self,
+        start_page_no: usize,
+        max_pages_to_write_per_round_trip: usize,
+        payload: Vec<u8>,
+    ) -> Result<(), AzureStorageError> {
+        match self.pick_write_fault() {
+            Some(PageBlobFault::TearWrite) => {
+                let payload = self.tear_payload(payload);
+
+                if !payload.is_empty() {
+                    self.inner
+                        .save_pages(start_page_no, max_pages_to_write_per_round_trip, payload)
+                        .await?;
+                }
+
+                Err(injected_error(PageBlobFault::TearWrite))
+            }
+            Some(fault) => Err(injected_error(fault)),
+            None => {
+                self.inner
+                    .save_pages(start_page_no, max_pages_to_write_per_round_trip, payload)
+                    .await
+            }
+        }
+    }
+
+    async fn auto_ressize_and_save_pages(
+        &mut self,
+        start_page_no: usize,
+        max_pages_to_write_per_round_trip: usize,
+        payload: Vec<u8>,
+        resize_pages_ration: usize,
+    ) -> Result<usize, AzureStorageError> {
+        match self.pick_write_fault() {
+            Some(PageBlobFault::TearWrite) => {
+                let payload = self.tear_payload(payload);
+
+                if !payload.is_empty() {
+                    self.inner
+                        .auto_ressize_and_save_pages(
+                            start_page_no,
+                            max_pages_to_write_per_round_trip,
+                            payload,
+                            resize_pages_ration,
+                        )
+                        .await?;
+                }
+
+                Err(injected_error(PageBlobFault::TearWrite))
+            }
+            Some(fault) => Err(injected_error(fault)),
+            None => {
+                self.inner
+                    .auto_ressize_and_save_pages(
+                        start_page_no,
+                        max_pages_to_write_per_round_trip,
+                        payload,
+                        resize_pages_ration,
+                    )
+                    .await
+            }
+        }
+    }
+
+    async fn download(&mut self) -> Result<Vec<u8>, AzureStorageError> {
+        if let Some(fault) = self.pick_fault(&[PageBlobFault::TransportError]) {
+            return Err(injected_error(fault));
+        }
+
+        self.inner.download().await
+    }
+
+    async fn delete(&mut self) -> Result<(), AzureStorageError> {
+        if let Some(fault) = self.pick_fault(&[PageBlobFault::TransportError]) {
+            return Err(injected_error(fault));
+        }
+
+        self.inner.delete().await
+    }
+
+    async fn delete_if_exists(&mut self) -> Result<(), AzureStorageError> {
+        if let Some(fault) = self.pick_fault(&[PageBlobFault::TransportError]) {
+            return Err(injected_error(fault));
+        }
+
+        self.inner.delete_if_exists().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use my_azure_page_blob::MyPageBlobMock;
+
+    use super::*;
+    use crate::{test_utils::copy_blob, AppendPageBlobSettings, PageBlobAppend, RecordFormat};
+
+    const CRASH_TEST_SEEDS: u64 = 2000;
+    const INCARNATIONS_PER_SEED: u64 = 8;
+
+    fn get_settings() -> AppendPageBlobSettings {
+        AppendPageBlobSettings {
+            blob_auto_resize_in_pages: 2,
+            ..crate::test_utils::get_settings(RecordFormat::Crc32cWithSequence)
+        }
+    }
+
+    fn create_payload(payload_no: u64) -> Vec<u8> {
+        let mut result = payload_no.to_le_bytes().to_vec();
+        // Payloads of different sizes so records cross page boundaries
+        result.resize(8 + (payload_no as usize * 37) % 700, payload_no as u8);
+        result
+    }
+
+    // Reads everything which is visible and switches to the Writing mode. Any error is a crash
+    async fn recover(
+        page_blob_append: &mut PageBlobAppend<FaultInjectingPageBlob<MyPageBlobMock>>,
+        visible: &mut Vec<(u64, Vec<u8>)>,
+    ) -> Result<(), String> {
+        loop {
+            match page_blob_append.get_next_payload().await {
+                Ok(Some(item)) => visible.push(item),
+                Ok(None) => return Ok(()),
+                Err(err) => {
+                    if !err.is_corrupted() {
+                        return Err(format!("{:?}", err));
+                    }
+
+                    return page_blob_append
+                        .init_blob(None)
+                        .await
+                        .map_err(|err| format!("{:?}", err));
+                }
+            }
+        }
+    }
+
+    fn assert_acknowledged_are_visible(
+        seed: u64,
+        acknowledged: &[(u64, Vec<u8>)],
+        visible: &[(u64, Vec<u8>)],
+    ) {
+        for i in 1..visible.len() {
+            assert!(visible[i - 1].0 < visible[i].0, "Seed: {}", seed);
+        }
+
+        for item in acknowledged {
+            assert!(
+                visible.contains(item),
+                "Seed: {}. Lost seq {}",
+                seed,
+                item.0
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_torn_write_keeps_whole_pages() {
+        let mut inner = MyPageBlobMock::new();
+        inner.create_container_if_not_exist().await.unwrap();
+        inner.create_if_not_exists(4).await.unwrap();
+
+        let mut page_blob = FaultInjectingPageBlob::new(inner, 1, 1);
+
+        let mut torn = false;
+
+        while !torn {
+            let result = page_blob.save_pages(0, 10, vec![7u8; 1500]).await;
+
+            if result.is_ok() {
+                continue;
+            }
+
+            torn = page_blob.get_injected_faults().last() == Some(&PageBlobFault::TearWrite);
+        }
+
+        let content = page_blob.inner.download().await.unwrap();
+        let written = content.iter().filter(|b| **b == 7).count();
+        assert_eq!(0, written % BLOB_PAGE_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_acknowledged_records_survive_crashes() {
+        let settings = get_settings();
+        let mut faults_injected = 0;
+
+        for seed in 0..CRASH_TEST_SEEDS {
+            let page_blob = crate::test_utils::create_blob().await;
+
+            let mut acknowledged = Vec::new();
+            let mut next_payload_no = 0;
+
+            for incarnation in 0..INCARNATIONS_PER_SEED {
+                let faulty_blob = FaultInjectingPageBlob::new(
+                    page_blob,
+                    seed * INCARNATIONS_PER_SEED + incarnation,
+                    10,
+                );
+
+                let mut page_blob_append = PageBlobAppend::new(faulty_blob, settings.clone());
+
+                let mut visible = Vec::new();
+                let recovered = recover(&mut page_blob_append, &mut visible).await;
+
+                if recovered.is_ok() {
+                    assert_acknowledged_are_visible(seed, &acknowledged, &visible);
+
+                    for _ in 0..5 {
+                        let amount = next_payload_no % 4 + 1;
+
+                        let payloads: Vec<Vec<u8>> = (next_payload_no..next_payload_no + amount)
+                            .map(create_payload)
+                            .collect();
+
+                        next_payload_no += amount;
+
+                        match page_blob_append.append_and_write(&payloads).await {
+                            Ok(seq_range) => {
+                                assert_eq!(amount, seq_range.end - seq_range.start);
+                                acknowledged.extend(seq_range.zip(payloads));
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                }
+
+                let faulty_blob = page_blob_append.get_page_blob_mut();
+                faults_injected += faulty_blob.get_injected_faults().len();
+                page_blob = copy_blob(&mut faulty_blob.inner).await;
+            }
+
+            let faulty_blob = FaultInjectingPageBlob::new(page_blob, seed, 0);
+            let mut page_blob_append = PageBlobAppend::new(faulty_blob, settings.clone());
+
+            let mut visible = Vec::new();
+            recover(&mut page_blob_append, &mut visible).await.unwrap();
+
+            assert_acknowledged_are_visible(seed, &acknowledged, &visible);
+        }
+
+        assert!(faults_injected > 0);
+    }
+}
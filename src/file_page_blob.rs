@@ -0,0 +1,310 @@
+use std::{io::SeekFrom, path::PathBuf};
+
+use async_trait::async_trait;
+use my_azure_page_blob::MyPageBlob;
+use my_azure_storage_sdk::{page_blob::consts::BLOB_PAGE_SIZE, AzureStorageError};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+// Container is a directory under the root folder. Blob is a file inside the container directory
+pub struct FilePageBlob {
+    root_folder: PathBuf,
+    container_name: String,
+    blob_name: String,
+}
+
+impl FilePageBlob {
+    pub fn new(root_folder: impl Into<PathBuf>, container_name: &str, blob_name: &str) -> Self {
+        Self {
+            root_folder: root_folder.into(),
+            container_name: container_name.to_string(),
+            blob_name: blob_name.to_string(),
+        }
+    }
+
+    pub fn get_file_path(&self) -> PathBuf {
+        self.get_container_path().join(&self.blob_name)
+    }
+
+    fn get_container_path(&self) -> PathBuf {
+        self.root_folder.join(&self.container_name)
+    }
+
+    async fn open_file(&self) -> Result<File, AzureStorageError> {
+        if !self.get_container_path().is_dir() {
+            return Err(AzureStorageError::ContainerNotFound);
+        }
+
+        let result = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.get_file_path())
+            .await;
+
+        match result {
+            Ok(file) => Ok(file),
+            Err(err) => {
+                if err.kind() == std::io::ErrorKind::NotFound {
+                    Err(AzureStorageError::BlobNotFound)
+                } else {
+                    Err(io_error(err))
+                }
+            }
+        }
+    }
+
+    async fn get_pages_amount(file: &File) -> Result<usize, AzureStorageError> {
+        let metadata = file.metadata().await.map_err(io_error)?;
+        Ok(metadata.len() as usize / BLOB_PAGE_SIZE)
+    }
+}
+
+#[async_trait]
+impl MyPageBlob for FilePageBlob {
+    fn get_container_name(&self) -> &str {
+        &self.container_name
+    }
+
+    fn get_blob_name(&self) -> &str {
+        &self.blob_name
+    }
+
+    async fn create_container_if_not_exist(&mut self) -> Result<(), AzureStorageError> {
+        tokio::fs::create_dir_all(self.get_container_path())
+            .await
+            .map_err(io_error)
+    }
+
+    async fn create_if_not_exists(
+        &mut self,
+        init_pages_amounts: usize,
+    ) -> Result<(), AzureStorageError> {
+        match self.open_file().await {
+            Ok(_) => Ok(()),
+            Err(AzureStorageError::BlobNotFound) => {
+                let file = File::create(self.get_file_path()).await.map_err(io_error)?;
+
+                file.set_len((init_pages_amounts * BLOB_PAGE_SIZE) as u64)
+                    .await
+                    .map_err(io_error)?;
+
+                file.sync_all().await.map_err(io_error)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn get_available_pages_amount(&mut self) -> Result<usize, AzureStorageError> {
+        let file = self.open_file().await?;
+        Self::get_pages_amount(&file).await
+    }
+
+    async fn get(
+        &mut self,
+        start_page_no: usize,
+        pages_to_read: usize,
+    ) -> Result<Vec<u8>, AzureStorageError> {
+        let mut file = self.open_file().await?;
+
+        let pages_amount = Self::get_pages_amount(&file).await?;
+
+        if start_page_no + pages_to_read > pages_amount {
+            return Err(AzureStorageError::InvalidPageRange);
+        }
+
+        file.seek(SeekFrom::Start((start_page_no * BLOB_PAGE_SIZE) as u64))
+            .await
+            .map_err(io_error)?;
+
+        let mut result = vec![0u8; pages_to_read * BLOB_PAGE_SIZE];
+        file.read_exact(&mut result).await.map_err(io_error)?;
+
+        Ok(result)
+    }
+
+    async fn resize(&mut self, pages_amount: usize) -> Result<(), AzureStorageError> {
+        let file = self.open_file().await?;
+
+        file.set_len((pages_amount * BLOB_PAGE_SIZE) as u64)
+            .await
+            .map_err(io_error)?;
+
+        file.sync_all().await.map_err(io_error)
+    }
+
+    async fn save_pages(
+        &mut self,
+        start_page_no: usize,
+        _max_pages_to_write_per_round_trip: usize,
+        mut payload: Vec<u8>,
+    ) -> Result<(), AzureStorageError> {
+        crate::page_blob_utils::extend_buffer_to_full_pages_size(&mut payload, BLOB_PAGE_SIZE);
+
+        let mut file = self.open_file().await?;
+
+        let pages_amount = Self::get_pages_amount(&file).await?;
+
+        if start_page_no + payload.len() / BLOB_PAGE_SIZE > pages_amount {
+            return Err(AzureStorageError::InvalidPageRange);
+        }
+
+        file.seek(SeekFrom::Start((start_page_no * BLOB_PAGE_SIZE) as u64))
+            .await
+            .map_err(io_error)?;
+
+        file.write_all(&payload).await.map_err(io_error)?;
+
+        file.sync_data().await.map_err(io_error)
+    }
+
+    async fn auto_ressize_and_save_pages(
+        &mut self,
+        start_page_no: usize,
+        max_pages_to_write_per_round_trip: usize,
+        mut payload: Vec<u8>,
+        resize_pages_ration: usize,
+    ) -> Result<usize, AzureStorageError> {
+        crate::page_blob_utils::extend_buffer_to_full_pages_size(&mut payload, BLOB_PAGE_SIZE);
+
+        let file = self.open_file().await?;
+        let mut pages_amount = Self::get_pages_amount(&file).await?;
+
+        let required_pages_amount = start_page_no + payload.len() / BLOB_PAGE_SIZE;
+
+        if required_pages_amount > pages_amount {
+            let resize_pages_ration = resize_pages_ration.max(1);
+
+            pages_amount = crate::page_blob_utils::get_pages_amount_by_size(
+                required_pages_amount,
+                resize_pages_ration,
+            ) * resize_pages_ration;
+
+            self.resize(pages_amount).await?;
+        }
+
+        self.save_pages(start_page_no, max_pages_to_write_per_round_trip, payload)
+            .await?;
+
+        Ok(pages_amount)
+    }
+
+    async fn download(&mut self) -> Result<Vec<u8>, AzureStorageError> {
+        let mut file = self.open_file().await?;
+
+        let mut result = Vec::new();
+        file.read_to_end(&mut result).await.map_err(io_error)?;
+
+        Ok(result)
+    }
+
+    async fn delete(&mut self) -> Result<(), AzureStorageError> {
+        self.open_file().await?;
+
+        tokio::fs::remove_file(self.get_file_path())
+            .await
+            .map_err(io_error)
+    }
+
+    async fn delete_if_exists(&mut self) -> Result<(), AzureStorageError> {
+        match self.delete().await {
+            Ok(_) => Ok(()),
+            Err(AzureStorageError::BlobNotFound) => Ok(()),
+            Err(AzureStorageError::ContainerNotFound) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+pub fn io_error(err: std::io::Error) -> AzureStorageError {
+    AzureStorageError::UnknownError {
+        msg: format!("{:?}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PageBlobAppend, RecordFormat};
+
+    fn get_test_root_folder(test_name: &str) -> PathBuf {
+        let result = std::env::temp_dir().join(format!(
+            "page-blob-append-{}-{}",
+            test_name,
+            std::process::id()
+        ));
+
+        let _ = std::fs::remove_dir_all(&result);
+
+        result
+    }
+
+    #[tokio::test]
+    async fn test_pages_are_saved_and_read() {
+        let root_folder = get_test_root_folder("pages");
+
+        let mut page_blob = FilePageBlob::new(&root_folder, "container", "blob");
+
+        let err = page_blob.get_available_pages_amount().await.err().unwrap();
+        assert!(matches!(err, AzureStorageError::ContainerNotFound));
+
+        page_blob.create_container_if_not_exist().await.unwrap();
+
+        let err = page_blob.get_available_pages_amount().await.err().unwrap();
+        assert!(matches!(err, AzureStorageError::BlobNotFound));
+
+        page_blob.create_if_not_exists(1).await.unwrap();
+        assert_eq!(1, page_blob.get_available_pages_amount().await.unwrap());
+
+        let pages_amount = page_blob
+            .auto_ressize_and_save_pages(1, 10, vec![5u8; 600], 4)
+            .await
+            .unwrap();
+
+        assert_eq!(4, pages_amount);
+        assert_eq!(4, page_blob.get_available_pages_amount().await.unwrap());
+
+        let pages = page_blob.get(1, 2).await.unwrap();
+        assert_eq!(&[5u8; 600], &pages[..600]);
+        assert_eq!(&[0u8; 424], &pages[600..]);
+
+        assert!(page_blob.get(3, 2).await.is_err());
+
+        page_blob.delete().await.unwrap();
+        page_blob.delete_if_exists().await.unwrap();
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+
+    #[tokio::test]
+    async fn test_records_survive_reopen() {
+        let root_folder = get_test_root_folder("reopen");
+
+        let settings = crate::test_utils::get_settings(RecordFormat::Crc32cWithSequence);
+
+        let page_blob = FilePageBlob::new(&root_folder, "container", "blob");
+        let mut writer = PageBlobAppend::new(page_blob, settings.clone());
+        writer.init_blob(None).await.unwrap();
+
+        writer
+            .append_and_write(&vec![vec![1u8; 10], vec![2u8; 700]])
+            .await
+            .unwrap();
+
+        drop(writer);
+
+        let page_blob = FilePageBlob::new(&root_folder, "container", "blob");
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        let payload = reader.get_next_payload().await.unwrap();
+        assert_eq!((1, vec![1u8; 10]), payload.unwrap());
+
+        let payload = reader.get_next_payload().await.unwrap();
+        assert_eq!((2, vec![2u8; 700]), payload.unwrap());
+
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+}
@@ -0,0 +1,408 @@
+use std::ops::Range;
+
+use my_azure_page_blob::MyPageBlob;
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+
+use crate::{PageBlobAppend, PageBlobAppendError};
+
+struct AppendRequest {
+    payloads: Vec<Vec<u8>>,
+    respond_to: oneshot::Sender<Result<Range<u64>, PageBlobAppendError>>,
+}
+
+#[derive(Clone)]
+pub struct GroupCommitWriter {
+    sender: mpsc::Sender<AppendRequest>,
+}
+
+impl GroupCommitWriter {
+    // PageBlobAppend has to be in the Writing mode. It is given back once all the handles are dropped.
+    // Round trip takes whole appends until one of the limits would be exceeded.
+    // An append which alone exceeds them is written in its own round trip
+    pub fn start<TMyPageBlob: MyPageBlob + Send + Sync + 'static>(
+        page_blob_append: PageBlobAppend<TMyPageBlob>,
+        queue_capacity: usize,
+        max_payloads_per_round_trip: usize,
+        max_bytes_per_round_trip: usize,
+    ) -> Result<(Self, JoinHandle<PageBlobAppend<TMyPageBlob>>), PageBlobAppendError> {
+        if !page_blob_append.is_writing() {
+            return Err(PageBlobAppendError::NotInitialized);
+        }
+
+        let (sender, receiver) = mpsc::channel(queue_capacity);
+
+        let join_handle = tokio::spawn(write_loop(
+            page_blob_append,
+            receiver,
+            max_payloads_per_round_trip,
+            max_bytes_per_round_trip,
+        ));
+
+        Ok((Self { sender }, join_handle))
+    }
+
+    // Resolves once the payloads are written to the blob.
+    // Error of a write is given to all the appends of the round trip.
+    // Append which is refused before the write fails alone
+    pub async fn append(&self, payloads: Vec<Vec<u8>>) -> Result<Range<u64>, PageBlobAppendError> {
+        // Empty payload fails the whole round trip, so it is refused before it is batched with others
+        if let Some(index) = payloads.iter().position(|payload| payload.is_empty()) {
+            return Err(PageBlobAppendError::EmptyPayload(format!(
+                "Payload #{} is empty",
+                index
+            )));
+        }
+
+        let (respond_to, response) = oneshot::channel();
+
+        let request = AppendRequest {
+            payloads,
+            respond_to,
+        };
+
+        if self.sender.send(request).await.is_err() {
+            return Err(PageBlobAppendError::GroupCommitFailed(
+                "Group commit writer is stopped".to_string(),
+            ));
+        }
+
+        match response.await {
+            Ok(result) => result,
+            Err(_) => Err(PageBlobAppendError::GroupCommitFailed(
+                "Group commit writer is stopped before the payloads were written".to_string(),
+            )),
+        }
+    }
+}
+
+async fn write_loop<TMyPageBlob: MyPageBlob>(
+    mut page_blob_append: PageBlobAppend<TMyPageBlob>,
+    mut receiver: mpsc::Receiver<AppendRequest>,
+    max_payloads_per_round_trip: usize,
+    max_bytes_per_round_trip: usize,
+) -> PageBlobAppend<TMyPageBlob> {
+    // Request which did not fit into the previous round trip
+    let mut next_request = None;
+
+    loop {
+        let request = match next_request.take() {
+            Some(request) => request,
+            None => match receiver.recv().await {
+                Some(request) => request,
+                None => break,
+            },
+        };
+
+        let mut bytes = get_payloads_size(&request.payloads);
+        let mut payloads_amount = request.payloads.len();
+        let mut requests = vec![request];
+
+        while let Ok(request) = receiver.try_recv() {
+            let request_bytes = get_payloads_size(&request.payloads);
+
+            if payloads_amount + request.payloads.len() > max_payloads_per_round_trip
+                || bytes + request_bytes > max_bytes_per_round_trip
+            {
+                next_request = Some(request);
+                break;
+            }
+
+            bytes += request_bytes;
+            payloads_amount += request.payloads.len();
+            requests.push(request);
+        }
+
+        let payloads = requests.iter().flat_map(|request| request.payloads.iter());
+
+        match page_blob_append.append_payloads(payloads).await {
+            Ok(seq_range) => {
+                let mut seq = seq_range.start;
+
+                for request in requests {
+                    let payloads_amount = request.payloads.len() as u64;
+                    let _ = request.respond_to.send(Ok(seq..seq + payloads_amount));
+                    seq += payloads_amount;
+                }
+            }
+            // One of the appends was refused before anything was written.
+            // The others must not fail with it, so each append is written alone
+            Err(err) if requests.len() > 1 && is_refused_before_write(&err) => {
+                for request in requests {
+                    let result = page_blob_append.append_and_write(&request.payloads).await;
+                    let _ = request.respond_to.send(result);
+                }
+            }
+            Err(err) => {
+                let last_request = requests.pop().unwrap();
+
+                for request in requests {
+                    let _ = request.respond_to.send(Err(copy_error(&err)));
+                }
+
+                let _ = last_request.respond_to.send(Err(err));
+            }
+        }
+    }
+
+    page_blob_append
+}
+
+fn get_payloads_size(payloads: &[Vec<u8>]) -> usize {
+    payloads.iter().map(|payload| payload.len()).sum()
+}
+
+// Errors which are caused by a single payload and are given before the blob is touched
+fn is_refused_before_write(err: &PageBlobAppendError) -> bool {
+    match err {
+        PageBlobAppendError::EmptyPayload(_) => true,
+        PageBlobAppendError::PayloadTooLarge(_) => true,
+        PageBlobAppendError::EncryptionFailed(_) => true,
+        _ => false,
+    }
+}
+
+// Storage error can not be cloned, so only one append gets it. The others get its description
+fn copy_error(err: &PageBlobAppendError) -> PageBlobAppendError {
+    match err {
+        PageBlobAppendError::NotInitialized => PageBlobAppendError::NotInitialized,
+        PageBlobAppendError::Corrupted(info) => PageBlobAppendError::Corrupted(info.clone()),
+        PageBlobAppendError::AzureStorageError(err) => {
+            PageBlobAppendError::GroupCommitFailed(format!("Round trip failed. Err: {:?}", err))
+        }
+        PageBlobAppendError::Forbidden(msg) => PageBlobAppendError::Forbidden(msg.clone()),
+        PageBlobAppendError::UnsupportedFormat(msg) => {
+            PageBlobAppendError::UnsupportedFormat(msg.clone())
+        }
+        PageBlobAppendError::InvalidCheckpoint(msg) => {
+            PageBlobAppendError::InvalidCheckpoint(msg.clone())
+        }
+        PageBlobAppendError::GroupCommitFailed(msg) => {
+            PageBlobAppendError::GroupCommitFailed(msg.clone())
+        }
+        PageBlobAppendError::LostOwnership(msg) => PageBlobAppendError::LostOwnership(msg.clone()),
+        PageBlobAppendError::EncryptionFailed(msg) => {
+            PageBlobAppendError::EncryptionFailed(msg.clone())
+        }
+        PageBlobAppendError::DecryptionFailed(msg) => {
+            PageBlobAppendError::DecryptionFailed(msg.clone())
+        }
+        PageBlobAppendError::EncodeFailed(msg) => PageBlobAppendError::EncodeFailed(msg.clone()),
+        PageBlobAppendError::DecodeFailed(info) => PageBlobAppendError::DecodeFailed(info.clone()),
+        PageBlobAppendError::EmptyPayload(msg) => PageBlobAppendError::EmptyPayload(msg.clone()),
+        PageBlobAppendError::CompactionInterrupted(msg) => {
+            PageBlobAppendError::CompactionInterrupted(msg.clone())
+        }
+        PageBlobAppendError::PayloadTooLarge(msg) => {
+            PageBlobAppendError::PayloadTooLarge(msg.clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AppendPageBlobSettings, RecordFormat};
+
+    async fn create_writing_append(
+        settings: AppendPageBlobSettings,
+    ) -> PageBlobAppend<my_azure_page_blob::MyPageBlobMock> {
+        let page_blob = crate::test_utils::create_blob().await;
+
+        let mut page_blob_append = PageBlobAppend::new(page_blob, settings);
+        assert!(page_blob_append.get_next_payload().await.unwrap().is_none());
+
+        page_blob_append
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_appends() {
+        let settings = crate::test_utils::get_settings(RecordFormat::Crc32cWithSequence);
+
+        let page_blob_append = create_writing_append(settings.clone()).await;
+
+        let (writer, join_handle) =
+            GroupCommitWriter::start(page_blob_append, 16, 100, 1024 * 1024).unwrap();
+
+        let mut tasks = Vec::new();
+
+        for i in 0..20u8 {
+            let writer = writer.clone();
+            tasks.push(tokio::spawn(async move {
+                let range = writer.append(vec![vec![i], vec![i, i]]).await.unwrap();
+                (i, range)
+            }));
+        }
+
+        let mut expected = Vec::new();
+
+        for task in tasks {
+            let (i, range) = task.await.unwrap();
+            assert_eq!(2, range.end - range.start);
+            expected.push((range.start, vec![i]));
+            expected.push((range.start + 1, vec![i, i]));
+        }
+
+        expected.sort();
+
+        drop(writer);
+        let mut page_blob_append = join_handle.await.unwrap();
+
+        let page_blob = crate::test_utils::copy_blob(page_blob_append.get_page_blob_mut()).await;
+
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        let mut result = Vec::new();
+        while let Some(item) = reader.get_next_payload().await.unwrap() {
+            result.push(item);
+        }
+
+        assert_eq!(expected, result);
+    }
+
+    #[tokio::test]
+    async fn test_append_which_exceeds_byte_limit_is_written_alone() {
+        let settings = crate::test_utils::get_settings(RecordFormat::Crc32cWithSequence);
+
+        let page_blob_append = create_writing_append(settings.clone()).await;
+
+        let (writer, join_handle) =
+            GroupCommitWriter::start(page_blob_append, 16, 100, 100).unwrap();
+
+        let mut tasks = Vec::new();
+
+        for i in 0..10u8 {
+            let writer = writer.clone();
+            let payload_size = if i % 3 == 0 { 300 } else { 40 };
+            tasks.push(tokio::spawn(async move {
+                writer.append(vec![vec![i; payload_size]]).await.unwrap()
+            }));
+        }
+
+        for task in tasks {
+            let seq_range = task.await.unwrap();
+            assert_eq!(1, seq_range.end - seq_range.start);
+        }
+
+        drop(writer);
+        let mut page_blob_append = join_handle.await.unwrap();
+
+        let page_blob = crate::test_utils::copy_blob(page_blob_append.get_page_blob_mut()).await;
+
+        let mut reader = PageBlobAppend::new(page_blob, settings);
+
+        let mut seqs = Vec::new();
+        while let Some((seq, _)) = reader.get_next_payload().await.unwrap() {
+            seqs.push(seq);
+        }
+
+        assert_eq!((1..=10).collect::<Vec<u64>>(), seqs);
+    }
+
+    #[tokio::test]
+    async fn test_start_is_refused_if_not_writing() {
+        let page_blob = crate::test_utils::create_blob().await;
+
+        let settings = crate::test_utils::get_settings(RecordFormat::Crc32cWithSequence);
+
+        let page_blob_append = PageBlobAppend::new(page_blob, settings);
+
+        let err = GroupCommitWriter::start(page_blob_append, 16, 100, 1024)
+            .err()
+            .unwrap();
+        assert!(matches!(err, PageBlobAppendError::NotInitialized));
+    }
+
+    #[tokio::test]
+    async fn test_write_error_is_given_to_every_append() {
+        let settings = AppendPageBlobSettings {
+            max_payload_size_protection: 10,
+            ..crate::test_utils::get_settings(RecordFormat::Crc32cWithSequence)
+        };
+
+        let page_blob_append = create_writing_append(settings).await;
+
+        let (writer, join_handle) =
+            GroupCommitWriter::start(page_blob_append, 16, 100, 1024).unwrap();
+
+        let err = writer.append(vec![vec![1u8], vec![]]).await.unwrap_err();
+        assert!(err.is_empty_payload());
+
+        let mut tasks = Vec::new();
+
+        // Every append has a payload which is too large, so every append fails
+        for i in 0..5u8 {
+            let writer = writer.clone();
+            tasks.push(tokio::spawn(async move {
+                writer.append(vec![vec![i], vec![i; 20]]).await
+            }));
+        }
+
+        for task in tasks {
+            let err = task.await.unwrap().unwrap_err();
+            assert!(err.is_payload_too_large());
+        }
+
+        drop(writer);
+        let page_blob_append = join_handle.await.unwrap();
+        assert_eq!(
+            crate::BlobHeader::get_data_start_position(),
+            page_blob_append.get_blob_position()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_too_large_append_does_not_fail_the_others() {
+        let settings = AppendPageBlobSettings {
+            max_payload_size_protection: 10,
+            ..crate::test_utils::get_settings(RecordFormat::Crc32cWithSequence)
+        };
+
+        let page_blob_append = create_writing_append(settings.clone()).await;
+
+        let (writer, join_handle) =
+            GroupCommitWriter::start(page_blob_append, 16, 100, 1024).unwrap();
+
+        let mut tasks = Vec::new();
+
+        for i in 0..6u8 {
+            let writer = writer.clone();
+            let payload_size = if i == 2 { 20 } else { 5 };
+            tasks.push(tokio::spawn(async move {
+                (i, writer.append(vec![vec![i; payload_size]]).await)
+            }));
+        }
+
+        let mut expected = Vec::new();
+
+        for task in tasks {
+            let (i, result) = task.await.unwrap();
+
+            if i == 2 {
+                assert!(result.unwrap_err().is_payload_too_large());
+            } else {
+                let seq_range = result.unwrap();
+                assert_eq!(1, seq_range.end - seq_range.start);
+                expected.push((seq_range.start, vec![i; 5]));
+            }
+        }
+
+        expected.sort();
+
+        drop(writer);
+        let mut page_blob_append = join_handle.await.unwrap();
+
+        let page_blob = crate::test_utils::copy_blob(page_blob_append.get_page_blob_mut()).await;
+
+        let mut reader = PageBlobAppend::new(page_blob, settings);
+
+        let mut result = Vec::new();
+        while let Some(item) = reader.get_next_payload().await.unwrap() {
+            result.push(item);
+        }
+
+        assert_eq!(expected, result);
+    }
+}
@@ -1,14 +1,63 @@
+pub mod blob_header;
+mod compaction;
+mod compression;
+mod diagnostics;
+mod encryption;
 mod error;
+mod fault_injecting_page_blob;
+mod file_page_blob;
+mod group_commit_writer;
+mod metrics_sink;
 mod page_blob_append;
 
 pub mod page_blob_utils;
+mod read_checkpoint;
 mod read_write;
+mod record_size_field;
+mod recovery_report;
+mod retry_policy;
+mod segmented;
 mod settings;
+mod snapshot;
+mod sparse_index;
 mod states;
+#[cfg(test)]
+mod test_utils;
+mod typed;
+mod verify;
 mod with_retries;
+mod writer_lock;
 
-pub use error::PageBlobAppendError;
+pub use blob_header::BlobHeader;
+pub use compaction::{compact_blob, restore_interrupted_compaction, CompactionReport, RecordKey};
+pub use compression::Compression;
+pub use encryption::{Encryption, EncryptionAlgorithm, KeyProvider, StaticKeyProvider, KEY_SIZE};
+pub use error::{DecodeErrorInfo, PageBlobAppendError};
+pub use fault_injecting_page_blob::{FaultInjectingPageBlob, PageBlobFault};
+pub use file_page_blob::FilePageBlob;
+pub use group_commit_writer::GroupCommitWriter;
+#[cfg(feature = "metrics")]
+pub use metrics_sink::MetricsCrateSink;
+pub use metrics_sink::{MetricsSink, PageBlobMetrics};
 pub use page_blob_append::PageBlobAppend;
+pub use read_checkpoint::ReadCheckpoint;
+pub use recovery_report::{DroppedRange, RecoveryReport};
+pub use retry_policy::{is_transport_error, RetryPolicy};
+pub use segmented::{
+    FileSegmentStorage, RetentionPolicy, SegmentArchiver, SegmentInfo, SegmentSettings,
+    SegmentStorage, SegmentedPageBlobAppend,
+};
 
-pub use settings::AppendPageBlobSettings;
+pub use settings::{AppendPageBlobSettings, RecordFormat, RecoveryMode};
+pub use snapshot::Snapshot;
+pub use sparse_index::{SparseIndex, SparseIndexEntry};
 pub use states::{ChangeState, PageBlobAppendCacheState};
+#[cfg(feature = "bincode")]
+pub use typed::BincodeCodec;
+#[cfg(feature = "serde_json")]
+pub use typed::JsonCodec;
+#[cfg(feature = "msgpack")]
+pub use typed::MsgPackCodec;
+pub use typed::{Codec, TypedPageBlobAppend};
+pub use verify::{hex_dump, verify_blob, VerifiedRecord, VerifyCorruption, VerifyReport};
+pub use writer_lock::{FileWriterLock, WriterLock};
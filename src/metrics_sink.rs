@@ -0,0 +1,274 @@
+use std::{sync::Arc, time::Duration};
+
+use my_azure_storage_sdk::AzureStorageError;
+
+// Sink is created per PageBlobAppend instance, so it is up to the implementation to label the values
+pub trait MetricsSink: Send + Sync {
+    fn records_appended(&self, _records: usize, _bytes: usize) {}
+    fn records_read(&self, _records: usize, _bytes: usize) {}
+    fn pages_downloaded(&self, _pages: usize) {}
+    fn write_round_trip(&self, _latency: Duration, _success: bool) {}
+    fn retry(&self, _operation: &'static str, _error_kind: &str) {}
+    fn time_in_state(&self, _state: &'static str, _duration: Duration) {}
+}
+
+#[derive(Clone, Default)]
+pub struct PageBlobMetrics {
+    sink: Option<Arc<dyn MetricsSink>>,
+}
+
+impl PageBlobMetrics {
+    pub fn new(sink: Arc<dyn MetricsSink>) -> Self {
+        Self { sink: Some(sink) }
+    }
+
+    pub fn disabled() -> Self {
+        Self { sink: None }
+    }
+
+    pub fn records_appended(&self, records: usize, bytes: usize) {
+        if let Some(sink) = &self.sink {
+            sink.records_appended(records, bytes);
+        }
+    }
+
+    pub fn records_read(&self, records: usize, bytes: usize) {
+        if let Some(sink) = &self.sink {
+            sink.records_read(records, bytes);
+        }
+    }
+
+    pub fn pages_downloaded(&self, pages: usize) {
+        if let Some(sink) = &self.sink {
+            sink.pages_downloaded(pages);
+        }
+    }
+
+    pub fn write_round_trip(&self, latency: Duration, success: bool) {
+        if let Some(sink) = &self.sink {
+            sink.write_round_trip(latency, success);
+        }
+    }
+
+    pub fn retry(&self, operation: &'static str, err: &AzureStorageError) {
+        if let Some(sink) = &self.sink {
+            sink.retry(operation, get_error_kind(err));
+        }
+    }
+
+    pub fn time_in_state(&self, state: &'static str, duration: Duration) {
+        if let Some(sink) = &self.sink {
+            sink.time_in_state(state, duration);
+        }
+    }
+}
+
+// Name of the AzureStorageError variant. Variants the log does not handle separately are Other
+pub fn get_error_kind(err: &AzureStorageError) -> &'static str {
+    match err {
+        AzureStorageError::ContainerNotFound => "ContainerNotFound",
+        AzureStorageError::BlobNotFound => "BlobNotFound",
+        AzureStorageError::InvalidPageRange => "InvalidPageRange",
+        AzureStorageError::HyperError { .. } => "HyperError",
+        AzureStorageError::UnknownError { .. } => "UnknownError",
+        _ => "Other",
+    }
+}
+
+#[cfg(feature = "metrics")]
+pub struct MetricsCrateSink {
+    container: String,
+    blob: String,
+}
+
+#[cfg(feature = "metrics")]
+impl MetricsCrateSink {
+    pub fn new(container: &str, blob: &str) -> Self {
+        Self {
+            container: container.to_string(),
+            blob: blob.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl MetricsSink for MetricsCrateSink {
+    fn records_appended(&self, records: usize, bytes: usize) {
+        let labels = [
+            ("container", self.container.clone()),
+            ("blob", self.blob.clone()),
+        ];
+
+        ::metrics::counter!("page_blob_append_records_appended_total", &labels)
+            .increment(records as u64);
+        ::metrics::counter!("page_blob_append_bytes_appended_total", &labels)
+            .increment(bytes as u64);
+    }
+
+    fn records_read(&self, records: usize, bytes: usize) {
+        let labels = [
+            ("container", self.container.clone()),
+            ("blob", self.blob.clone()),
+        ];
+
+        ::metrics::counter!("page_blob_append_records_read_total", &labels)
+            .increment(records as u64);
+        ::metrics::counter!("page_blob_append_bytes_read_total", &labels).increment(bytes as u64);
+    }
+
+    fn pages_downloaded(&self, pages: usize) {
+        ::metrics::counter!(
+            "page_blob_append_pages_downloaded_total",
+            "container" => self.container.clone(),
+            "blob" => self.blob.clone()
+        )
+        .increment(pages as u64);
+    }
+
+    fn write_round_trip(&self, latency: Duration, success: bool) {
+        let labels = [
+            ("container", self.container.clone()),
+            ("blob", self.blob.clone()),
+            ("success", success.to_string()),
+        ];
+
+        ::metrics::counter!("page_blob_append_write_round_trips_total", &labels).increment(1);
+        ::metrics::histogram!("page_blob_append_write_round_trip_seconds", &labels)
+            .record(latency.as_secs_f64());
+    }
+
+    fn retry(&self, operation: &'static str, error_kind: &str) {
+        ::metrics::counter!(
+            "page_blob_append_retries_total",
+            "container" => self.container.clone(),
+            "blob" => self.blob.clone(),
+            "operation" => operation,
+            "error_kind" => error_kind.to_string()
+        )
+        .increment(1);
+    }
+
+    fn time_in_state(&self, state: &'static str, duration: Duration) {
+        ::metrics::histogram!(
+            "page_blob_append_state_duration_seconds",
+            "container" => self.container.clone(),
+            "blob" => self.blob.clone(),
+            "state" => state
+        )
+        .record(duration.as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Default)]
+    pub struct TestMetricsSink {
+        pub records_appended: Mutex<(usize, usize)>,
+        pub records_read: Mutex<(usize, usize)>,
+        pub pages_downloaded: Mutex<usize>,
+        pub write_round_trips: Mutex<usize>,
+        pub retries: Mutex<Vec<String>>,
+        pub states: Mutex<Vec<&'static str>>,
+    }
+
+    impl MetricsSink for TestMetricsSink {
+        fn records_appended(&self, records: usize, bytes: usize) {
+            let mut value = self.records_appended.lock().unwrap();
+            value.0 += records;
+            value.1 += bytes;
+        }
+
+        fn records_read(&self, records: usize, bytes: usize) {
+            let mut value = self.records_read.lock().unwrap();
+            value.0 += records;
+            value.1 += bytes;
+        }
+
+        fn pages_downloaded(&self, pages: usize) {
+            *self.pages_downloaded.lock().unwrap() += pages;
+        }
+
+        fn write_round_trip(&self, _latency: Duration, _success: bool) {
+            *self.write_round_trips.lock().unwrap() += 1;
+        }
+
+        fn retry(&self, operation: &'static str, error_kind: &str) {
+            self.retries
+                .lock()
+                .unwrap()
+                .push(format!("{}:{}", operation, error_kind));
+        }
+
+        fn time_in_state(&self, state: &'static str, _duration: Duration) {
+            self.states.lock().unwrap().push(state);
+        }
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let err = AzureStorageError::UnknownError {
+            msg: "Timeout".to_string(),
+        };
+
+        assert_eq!("UnknownError", get_error_kind(&err));
+        assert_eq!(
+            "BlobNotFound",
+            get_error_kind(&AzureStorageError::BlobNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_page_blob_append_reports_metrics() {
+        use crate::{
+            AppendPageBlobSettings, FaultInjectingPageBlob, PageBlobAppend, RecordFormat,
+            RetryPolicy,
+        };
+
+        let page_blob = crate::test_utils::create_blob().await;
+
+        let sink = Arc::new(TestMetricsSink::default());
+
+        let settings = AppendPageBlobSettings {
+            retry_policy: RetryPolicy {
+                initial_delay: Duration::ZERO,
+                is_retryable: |_| true,
+                ..Default::default()
+            },
+            metrics: PageBlobMetrics::new(sink.clone()),
+            ..crate::test_utils::get_settings(RecordFormat::Crc32cWithSequence)
+        };
+
+        let page_blob = FaultInjectingPageBlob::new(page_blob, 5, 3);
+
+        let mut writer = PageBlobAppend::new(page_blob, settings.clone());
+        assert!(writer.get_next_payload().await.unwrap().is_none());
+
+        for _ in 0..10 {
+            writer
+                .append_and_write(&vec![vec![1u8; 100], vec![2u8; 50]])
+                .await
+                .unwrap();
+        }
+
+        assert_eq!((20, 1500), *sink.records_appended.lock().unwrap());
+        assert!(*sink.write_round_trips.lock().unwrap() >= 10);
+        assert!(!sink.retries.lock().unwrap().is_empty());
+        assert_eq!(vec!["NotInitialized"], *sink.states.lock().unwrap());
+
+        let page_blob = crate::test_utils::copy_blob(&mut writer.get_page_blob_mut().inner).await;
+
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+        while reader.get_next_payload().await.unwrap().is_some() {}
+
+        assert_eq!((20, 1500), *sink.records_read.lock().unwrap());
+        assert!(*sink.pages_downloaded.lock().unwrap() > 0);
+        assert_eq!(
+            vec!["NotInitialized", "NotInitialized", "Reading"],
+            *sink.states.lock().unwrap()
+        );
+    }
+}
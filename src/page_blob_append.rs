@@ -1,24 +1,121 @@
+use std::{ops::Range, time::Instant};
+
+use bytes::Bytes;
+use futures::Stream;
 use my_azure_page_blob::*;
+use my_azure_storage_sdk::page_blob::consts::BLOB_PAGE_SIZE;
 
 use crate::{
+    error::CorruptedErrorInfo,
     settings::AppendPageBlobSettings,
     states::{GetNextPayloadResult, StateDataNotInitialized, StateDataReading, StateDataWriting},
-    ChangeState, PageBlobAppendCacheState, PageBlobAppendError,
+    writer_lock::WriterLease,
+    ChangeState, DroppedRange, PageBlobAppendCacheState, PageBlobAppendError, ReadCheckpoint,
+    RecordFormat, RecoveryMode, RecoveryReport, Snapshot, SparseIndex, WriterLock,
 };
 
 pub struct PageBlobAppend<TMyPageBlob: MyPageBlob> {
     state: Option<PageBlobAppendCacheState<TMyPageBlob>>,
     settings: AppendPageBlobSettings,
+    sparse_index: Option<SparseIndex<TMyPageBlob>>,
+    state_started: Instant,
+    recovery_report: RecoveryReport,
+    writer_lease: Option<WriterLease>,
 }
 
 impl<TMyPageBlob: MyPageBlob> PageBlobAppend<TMyPageBlob> {
     pub fn new(page_blob: TMyPageBlob, settings: AppendPageBlobSettings) -> Self {
         Self {
             state: Some(PageBlobAppendCacheState::NotInitialized(
-                StateDataNotInitialized::new(page_blob),
+                StateDataNotInitialized::new(page_blob, settings.clone()),
+            )),
+            settings,
+            sparse_index: None,
+            state_started: Instant::now(),
+            recovery_report: RecoveryReport::default(),
+            writer_lease: None,
+        }
+    }
+
+    pub fn new_from_checkpoint(
+        page_blob: TMyPageBlob,
+        settings: AppendPageBlobSettings,
+        checkpoint: ReadCheckpoint,
+    ) -> Self {
+        Self {
+            state: Some(PageBlobAppendCacheState::NotInitialized(
+                StateDataNotInitialized::from_checkpoint(page_blob, settings.clone(), checkpoint),
             )),
             settings,
+            sparse_index: None,
+            state_started: Instant::now(),
+            recovery_report: RecoveryReport::default(),
+            writer_lease: None,
+        }
+    }
+
+    // Returns the snapshot payload. get_next_payload continues after the last record the snapshot covers.
+    // Snapshot is used only if the log still has its checkpoint. Otherwise the older snapshot is tried.
+    // Without a valid snapshot the whole log is read
+    pub async fn open_with_snapshot<TSnapshotPageBlob: MyPageBlob>(
+        page_blob: TMyPageBlob,
+        snapshot_blob: &mut TSnapshotPageBlob,
+        settings: AppendPageBlobSettings,
+    ) -> Result<(Self, Option<Vec<u8>>), PageBlobAppendError> {
+        let snapshots = crate::snapshot::read_snapshots(snapshot_blob, &settings).await?;
+
+        let mut result = Self::new(page_blob, settings);
+
+        for snapshot in snapshots {
+            result.reposition(Some(snapshot.checkpoint))?;
+
+            // Reading the next record validates the checkpoint. At the end of the log it switches to the Writing mode
+            match result.get_next_payload().await {
+                Ok(Some(_)) => {
+                    result.reposition(Some(snapshot.checkpoint))?;
+                    return Ok((result, Some(snapshot.payload)));
+                }
+                Ok(None) => return Ok((result, Some(snapshot.payload))),
+                Err(PageBlobAppendError::InvalidCheckpoint(msg)) => {
+                    crate::diagnostics::snapshot_rejected(snapshot_blob, &msg);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        result.reposition(None)?;
+
+        Ok((result, None))
+    }
+
+    // Index inherits the retry policy and the metrics of the log
+    pub fn set_sparse_index(&mut self, mut sparse_index: SparseIndex<TMyPageBlob>) {
+        sparse_index.retry_policy = self.settings.retry_policy;
+        sparse_index.metrics = self.settings.metrics.clone();
+        self.sparse_index = Some(sparse_index);
+    }
+
+    // Lease is acquired before the blob is initialized and renewed before every write.
+    // Once the lease is lost every write fails with LostOwnership
+    pub fn set_writer_lock(&mut self, writer_lock: Box<dyn WriterLock>) {
+        self.writer_lease = Some(WriterLease::new(writer_lock));
+    }
+
+    pub async fn release_writer_lock(&mut self) -> Result<(), PageBlobAppendError> {
+        if let Some(mut writer_lease) = self.writer_lease.take() {
+            writer_lease.release().await?;
         }
+
+        Ok(())
+    }
+
+    pub fn get_sparse_index_mut(&mut self) -> Option<&mut SparseIndex<TMyPageBlob>> {
+        self.sparse_index.as_mut()
+    }
+
+    // Data dropped by the recovery modes since the instance was created
+    pub fn get_recovery_report(&self) -> &RecoveryReport {
+        &self.recovery_report
     }
 
     pub fn get_page_blob_mut(&mut self) -> &mut TMyPageBlob {
@@ -42,7 +139,27 @@ impl<TMyPageBlob: MyPageBlob> PageBlobAppend<TMyPageBlob> {
     pub async fn append_and_write<'s>(
         &mut self,
         payloads: &Vec<Vec<u8>>,
-    ) -> Result<(), PageBlobAppendError> {
+    ) -> Result<Range<u64>, PageBlobAppendError> {
+        self.append_payloads(payloads).await
+    }
+
+    // Accepts anything which gives the payload bytes: &[u8], Vec<u8>, bytes::Bytes
+    pub async fn append_payloads<TPayload: AsRef<[u8]>>(
+        &mut self,
+        payloads: impl IntoIterator<Item = TPayload>,
+    ) -> Result<Range<u64>, PageBlobAppendError> {
+        let span = crate::diagnostics::operation_span("append_and_write", self.get_page_blob());
+        crate::diagnostics::instrument(self.append_and_write_impl(payloads), span).await
+    }
+
+    async fn append_and_write_impl<TPayload: AsRef<[u8]>>(
+        &mut self,
+        payloads: impl IntoIterator<Item = TPayload>,
+    ) -> Result<Range<u64>, PageBlobAppendError> {
+        if let PageBlobAppendCacheState::Writing(_) = self.state.as_ref().unwrap() {
+            self.ensure_ownership().await?;
+        }
+
         match self.state.as_mut().unwrap() {
             PageBlobAppendCacheState::NotInitialized(_) => Err(PageBlobAppendError::NotInitialized),
             PageBlobAppendCacheState::Reading(_) => Err(PageBlobAppendError::NotInitialized),
@@ -53,32 +170,120 @@ impl<TMyPageBlob: MyPageBlob> PageBlobAppend<TMyPageBlob> {
                     state.page_blob.get_blob_name()
                 )))
             }
-            PageBlobAppendCacheState::Writing(state) => state.append_and_write(payloads).await,
+            PageBlobAppendCacheState::Writing(state) => {
+                let blob_position = state.get_blob_position();
+                let (seq_range, payloads_size) = state.append_and_write(payloads).await?;
+
+                self.settings
+                    .metrics
+                    .records_appended((seq_range.end - seq_range.start) as usize, payloads_size);
+
+                if let Some(sparse_index) = &mut self.sparse_index {
+                    if !seq_range.is_empty() {
+                        sparse_index
+                            .on_appended(blob_position, seq_range.start)
+                            .await;
+                    }
+                }
+
+                Ok(seq_range)
+            }
         }
     }
 
-    pub async fn get_next_payload(&mut self) -> Result<Option<Vec<u8>>, PageBlobAppendError> {
+    pub async fn get_next_payload(
+        &mut self,
+    ) -> Result<Option<(u64, Vec<u8>)>, PageBlobAppendError> {
+        let result = self.get_next_payload_with_position().await?;
+        Ok(result.map(|(_, seq, payload)| (seq, payload)))
+    }
+
+    // Same as get_next_payload. Also returns the blob position the record starts at
+    pub async fn get_next_payload_with_position(
+        &mut self,
+    ) -> Result<Option<(usize, u64, Vec<u8>)>, PageBlobAppendError> {
+        let span = crate::diagnostics::operation_span("get_next_payload", self.get_page_blob());
+        let result = crate::diagnostics::instrument(self.get_next_payload_impl(), span).await?;
+        Ok(result.map(|(blob_position, seq, payload)| (blob_position, seq, Vec::from(payload))))
+    }
+
+    // Record which is inside one downloaded buffer is a slice of it, so nothing is copied.
+    // The slice keeps the whole buffer (cache_capacity_in_pages) in memory while it is alive
+    pub async fn get_next_payload_bytes(
+        &mut self,
+    ) -> Result<Option<(u64, Bytes)>, PageBlobAppendError> {
+        let span = crate::diagnostics::operation_span("get_next_payload", self.get_page_blob());
+        let result = crate::diagnostics::instrument(self.get_next_payload_impl(), span).await?;
+        Ok(result.map(|(_, seq, payload)| (seq, payload)))
+    }
+
+    async fn get_next_payload_impl(
+        &mut self,
+    ) -> Result<Option<(usize, u64, Bytes)>, PageBlobAppendError> {
         loop {
             match self.state.as_mut().unwrap() {
-                PageBlobAppendCacheState::NotInitialized(state) => {
-                    let new_state = state.init().await?;
-                    if let Some(new_state) = new_state {
-                        self.change_state(new_state);
-                    }
+                PageBlobAppendCacheState::NotInitialized(_) => {
+                    self.init_if_required().await?;
                 }
                 PageBlobAppendCacheState::Reading(state) => {
+                    let blob_position = state.get_blob_position();
+                    let checkpoint = state.is_validating_checkpoint().then_some(ReadCheckpoint {
+                        blob_position,
+                        last_seq: state.last_seq,
+                    });
                     let result = state.get_next_payload().await;
 
                     match result {
                         Ok(result) => match result {
-                            GetNextPayloadResult::NextPayload(payload) => return Ok(Some(payload)),
+                            GetNextPayloadResult::NextPayload(seq, payload) => {
+                                self.settings.metrics.records_read(1, payload.len());
+                                return Ok(Some((blob_position, seq, payload)));
+                            }
 
                             GetNextPayloadResult::ChangeState(new_state) => {
+                                if let Some(checkpoint) = checkpoint {
+                                    self.confirm_end_of_data(checkpoint).await?;
+                                }
+
+                                if let ChangeState::ToWriteMode = &new_state {
+                                    self.prepare_header_for_writing().await?;
+                                }
+
                                 self.change_state(new_state);
                                 return Ok(None);
                             }
                         },
 
+                        Err(PageBlobAppendError::Corrupted(info)) => {
+                            if self.settings.recovery_mode != RecoveryMode::Fail {
+                                crate::diagnostics::corruption_detected(
+                                    self.get_page_blob(),
+                                    info.broken_pos,
+                                    &info.msg,
+                                );
+                            }
+
+                            match self.settings.recovery_mode {
+                                RecoveryMode::Fail => {}
+                                RecoveryMode::TruncateAtLastGood => {
+                                    self.truncate_at_last_good(info).await?;
+                                    return Ok(None);
+                                }
+                                RecoveryMode::SkipAndResync => {
+                                    if self.skip_and_resync(&info).await? {
+                                        continue;
+                                    }
+
+                                    self.truncate_at_last_good(info).await?;
+                                    return Ok(None);
+                                }
+                            }
+
+                            let err = PageBlobAppendError::Corrupted(info);
+                            self.handle_error(&err);
+                            return Err(err);
+                        }
+
                         Err(err) => {
                             self.handle_error(&err);
                             return Err(err);
@@ -95,13 +300,117 @@ impl<TMyPageBlob: MyPageBlob> PageBlobAppend<TMyPageBlob> {
         }
     }
 
+    // Stream ends when the end of the log is reached and PageBlobAppend switches to the Writing mode
+    pub fn payloads_stream(
+        &mut self,
+    ) -> impl Stream<Item = Result<Vec<u8>, PageBlobAppendError>> + '_ {
+        futures::stream::unfold(Some(self), |page_blob_append| async move {
+            let page_blob_append = page_blob_append?;
+
+            match page_blob_append.get_next_payload().await {
+                Ok(Some((_, payload))) => Some((Ok(payload), Some(page_blob_append))),
+                Ok(None) => None,
+                Err(err) => Some((Err(err), None)),
+            }
+        })
+    }
+
+    // Positions reading right before the first record with sequence number >= seq
+    pub async fn seek(&mut self, seq: u64) -> Result<(), PageBlobAppendError> {
+        let span = crate::diagnostics::operation_span("seek", self.get_page_blob());
+        crate::diagnostics::instrument(self.seek_impl(seq), span).await
+    }
+
+    async fn seek_impl(&mut self, seq: u64) -> Result<(), PageBlobAppendError> {
+        let entry = match &mut self.sparse_index {
+            Some(sparse_index) => sparse_index.find_by_seq(seq).await?,
+            None => None,
+        };
+
+        self.reposition(entry.map(|entry| entry.to_checkpoint()))?;
+        self.init_if_required().await?;
+
+        loop {
+            let checkpoint = match self.state.as_ref().unwrap() {
+                PageBlobAppendCacheState::Reading(_) => self.get_checkpoint().unwrap(),
+                _ => return Ok(()),
+            };
+
+            match self.get_next_payload().await? {
+                Some((record_seq, _)) => {
+                    if record_seq >= seq {
+                        return self.reposition(Some(checkpoint));
+                    }
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    // Positions reading right before the first record which starts at or after blob_position
+    pub async fn seek_to_blob_position(
+        &mut self,
+        blob_position: usize,
+    ) -> Result<(), PageBlobAppendError> {
+        let span =
+            crate::diagnostics::operation_span("seek_to_blob_position", self.get_page_blob());
+        crate::diagnostics::instrument(self.seek_to_blob_position_impl(blob_position), span).await
+    }
+
+    async fn seek_to_blob_position_impl(
+        &mut self,
+        blob_position: usize,
+    ) -> Result<(), PageBlobAppendError> {
+        let entry = match &mut self.sparse_index {
+            Some(sparse_index) => sparse_index.find_by_blob_position(blob_position).await?,
+            None => None,
+        };
+
+        self.reposition(entry.map(|entry| entry.to_checkpoint()))?;
+        self.init_if_required().await?;
+
+        loop {
+            match self.state.as_ref().unwrap() {
+                PageBlobAppendCacheState::Reading(state) => {
+                    if state.get_blob_position() >= blob_position {
+                        return Ok(());
+                    }
+                }
+                _ => return Ok(()),
+            }
+
+            if self.get_next_payload().await?.is_none() {
+                return Ok(());
+            }
+        }
+    }
+
     pub async fn init_blob(
         &mut self,
         backup_blob: Option<&mut TMyPageBlob>,
     ) -> Result<(), PageBlobAppendError> {
+        let span = crate::diagnostics::operation_span("init_blob", self.get_page_blob());
+        crate::diagnostics::instrument(self.init_blob_impl(backup_blob), span).await
+    }
+
+    async fn init_blob_impl(
+        &mut self,
+        backup_blob: Option<&mut TMyPageBlob>,
+    ) -> Result<(), PageBlobAppendError> {
+        self.check_header_flags()?;
+        self.ensure_ownership().await?;
+
+        if !matches!(
+            self.state.as_ref().unwrap(),
+            PageBlobAppendCacheState::Writing(_)
+        ) {
+            self.truncate_sparse_index(0).await?;
+        }
+
         match self.state.as_mut().unwrap() {
             PageBlobAppendCacheState::NotInitialized(state) => {
                 let change_state = state.init_blob().await?;
+                self.settings.record_format = state.get_record_format();
                 self.change_state(change_state);
                 Ok(())
             }
@@ -126,6 +435,39 @@ impl<TMyPageBlob: MyPageBlob> PageBlobAppend<TMyPageBlob> {
         }
     }
 
+    // Sequence numbers of an empty log start from next_seq. Lets a new segment continue the numbering
+    pub fn set_next_seq(&mut self, next_seq: u64) -> Result<(), PageBlobAppendError> {
+        // Seq 0 is the last seq of an empty log
+        if next_seq == 0 {
+            return Err(PageBlobAppendError::Forbidden(format!(
+                "Next seq of PageBlobAppend {}/{} has to be greater than 0",
+                self.get_page_blob().get_container_name(),
+                self.get_page_blob().get_blob_name()
+            )));
+        }
+
+        if let PageBlobAppendCacheState::Writing(state) = self.state.as_mut().unwrap() {
+            if state.get_next_seq() == 1 {
+                state.set_next_seq(next_seq);
+                return Ok(());
+            }
+        }
+
+        Err(PageBlobAppendError::Forbidden(format!(
+            "Next seq can be set only for an empty PageBlobAppend {}/{} in the Writing mode",
+            self.get_page_blob().get_container_name(),
+            self.get_page_blob().get_blob_name()
+        )))
+    }
+
+    // Appends are accepted only in the Writing mode
+    pub fn is_writing(&self) -> bool {
+        matches!(
+            self.state.as_ref(),
+            Some(PageBlobAppendCacheState::Writing(_))
+        )
+    }
+
     pub fn get_blob_position(&self) -> usize {
         if self.state.is_none() {
             return 0;
@@ -138,13 +480,360 @@ impl<TMyPageBlob: MyPageBlob> PageBlobAppend<TMyPageBlob> {
         }
     }
 
+    pub fn get_checkpoint(&self) -> Option<ReadCheckpoint> {
+        match self.state.as_ref()? {
+            PageBlobAppendCacheState::NotInitialized(_) => None,
+            PageBlobAppendCacheState::Reading(state) => Some(ReadCheckpoint {
+                blob_position: state.get_blob_position(),
+                last_seq: state.last_seq,
+            }),
+            PageBlobAppendCacheState::Corrupted(_) => None,
+            PageBlobAppendCacheState::Writing(state) => Some(ReadCheckpoint {
+                blob_position: state.get_blob_position(),
+                last_seq: state.get_next_seq().saturating_sub(1),
+            }),
+        }
+    }
+
+    // Snapshot covers everything read or appended so far. The log is not changed.
+    // Snapshot replaces the older of the two kept snapshots, so if it is not written completely
+    // the previous one is used.
+    // Compaction moves the records, so a new snapshot has to be written after it.
+    // Payload is limited by max_payload_size_protection the same way as a record
+    pub async fn write_snapshot<TSnapshotPageBlob: MyPageBlob>(
+        &self,
+        snapshot_blob: &mut TSnapshotPageBlob,
+        payload: &[u8],
+    ) -> Result<ReadCheckpoint, PageBlobAppendError> {
+        let checkpoint = match self.get_checkpoint() {
+            Some(checkpoint) => checkpoint,
+            None => {
+                return Err(PageBlobAppendError::Forbidden(format!(
+                    "Snapshot can not be written. PageBlobAppend {}/{} is in the {} mode",
+                    self.get_page_blob().get_container_name(),
+                    self.get_page_blob().get_blob_name(),
+                    self.state.as_ref().unwrap().as_string_name()
+                )))
+            }
+        };
+
+        // Bigger snapshot would be rejected when it is read
+        if payload.len() > self.settings.max_payload_size_protection as usize {
+            return Err(PageBlobAppendError::PayloadTooLarge(format!(
+                "Snapshot payload size {} is bigger than max_payload_size_protection {}",
+                payload.len(),
+                self.settings.max_payload_size_protection
+            )));
+        }
+
+        let snapshot = Snapshot {
+            checkpoint,
+            payload: payload.to_vec(),
+        };
+
+        crate::snapshot::write_snapshot(snapshot_blob, &snapshot, &self.settings).await?;
+
+        Ok(checkpoint)
+    }
+
+    async fn ensure_ownership(&mut self) -> Result<(), PageBlobAppendError> {
+        if self.writer_lease.is_none() {
+            return Ok(());
+        }
+
+        let blob_name = format!(
+            "{}/{}",
+            self.get_page_blob().get_container_name(),
+            self.get_page_blob().get_blob_name()
+        );
+
+        self.writer_lease
+            .as_mut()
+            .unwrap()
+            .ensure_ownership(&blob_name)
+            .await
+    }
+
+    // Legacy blobs have no header to record the codec and encryption in
+    fn check_header_flags(&self) -> Result<(), PageBlobAppendError> {
+        if self.settings.get_required_header_flags() != 0
+            && self.settings.record_format == RecordFormat::Legacy
+        {
+            return Err(PageBlobAppendError::UnsupportedFormat(format!(
+                "Compression and encryption are not supported by the Legacy record format. PageBlobAppend {}/{}",
+                self.get_page_blob().get_container_name(),
+                self.get_page_blob().get_blob_name()
+            )));
+        }
+
+        Ok(())
+    }
+
+    async fn init_if_required(&mut self) -> Result<(), PageBlobAppendError> {
+        if let PageBlobAppendCacheState::NotInitialized(_) = self.state.as_ref().unwrap() {
+            self.check_header_flags()?;
+            self.ensure_ownership().await?;
+        }
+
+        if let PageBlobAppendCacheState::NotInitialized(state) = self.state.as_mut().unwrap() {
+            let new_state = state.init().await?;
+            self.settings.record_format = state.get_record_format();
+            if let Some(new_state) = new_state {
+                if let ChangeState::ToWriteMode = &new_state {
+                    self.prepare_header_for_writing().await?;
+                }
+
+                self.change_state(new_state);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Header is written, or gets the flags the settings require, only when switching to the Writing mode.
+    // Opening a blob to read it does not change it
+    async fn prepare_header_for_writing(&mut self) -> Result<(), PageBlobAppendError> {
+        match self.state.as_mut().unwrap() {
+            PageBlobAppendCacheState::NotInitialized(state) => {
+                state.write_header_if_required().await?
+            }
+            PageBlobAppendCacheState::Reading(state) => {
+                state.add_header_flags_if_required().await?
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn reposition(
+        &mut self,
+        checkpoint: Option<ReadCheckpoint>,
+    ) -> Result<(), PageBlobAppendError> {
+        match self.state.as_ref().unwrap() {
+            PageBlobAppendCacheState::NotInitialized(_) => {}
+            PageBlobAppendCacheState::Reading(_) => {}
+            state => {
+                return Err(PageBlobAppendError::Forbidden(format!(
+                    "Seek is forbidden. PageBlobAppend {}/{} is in the {} mode",
+                    self.get_page_blob().get_container_name(),
+                    self.get_page_blob().get_blob_name(),
+                    state.as_string_name()
+                )));
+            }
+        }
+
+        let page_blob = self.state.take().unwrap().into_page_blob();
+
+        let state = match checkpoint {
+            Some(checkpoint) => StateDataNotInitialized::from_checkpoint(
+                page_blob,
+                self.settings.clone(),
+                checkpoint,
+            ),
+            None => StateDataNotInitialized::new(page_blob, self.settings.clone()),
+        };
+
+        self.state = Some(PageBlobAppendCacheState::NotInitialized(state));
+
+        Ok(())
+    }
+
+    // Drops everything starting from the checkpoint position and switches to the Writing mode.
+    // Position must be a record boundary or the end marker position, otherwise InvalidCheckpoint is returned
+    pub async fn truncate_at(
+        &mut self,
+        checkpoint: ReadCheckpoint,
+    ) -> Result<(), PageBlobAppendError> {
+        self.reposition(Some(checkpoint))?;
+        self.init_if_required().await?;
+        self.check_record_boundary(checkpoint).await?;
+
+        let info = match self.state.as_mut().unwrap() {
+            PageBlobAppendCacheState::Reading(state) => {
+                let (broken_pos, last_page) = state
+                    .seq_reader
+                    .read_cache
+                    .get_last_page_remaining_content(0);
+
+                CorruptedErrorInfo {
+                    broken_pos,
+                    last_page,
+                    msg: format!("Truncated at position {}", checkpoint.blob_position),
+                }
+            }
+            _ => {
+                return Err(PageBlobAppendError::Forbidden(format!(
+                    "Truncate is forbidden. PageBlobAppend {}/{} is in the {} mode",
+                    self.get_page_blob().get_container_name(),
+                    self.get_page_blob().get_blob_name(),
+                    self.state.as_ref().unwrap().as_string_name()
+                )));
+            }
+        };
+
+        self.truncate_at_last_good(info).await
+    }
+
+    // Record with the next seq has to start at the checkpoint, or the record with the last seq
+    // has to end there. The second case lets the log be cut right before a corrupted record.
+    // Reading is started from the checkpoint again afterwards
+    async fn check_record_boundary(
+        &mut self,
+        checkpoint: ReadCheckpoint,
+    ) -> Result<(), PageBlobAppendError> {
+        let state = match self.state.as_mut().unwrap() {
+            PageBlobAppendCacheState::Reading(state) => state,
+            _ => return Ok(()),
+        };
+
+        // Record which can not be decoded still proves the boundary
+        state.keep_undecoded_payloads();
+
+        match state.get_next_payload().await {
+            Ok(GetNextPayloadResult::NextPayload(_, _)) => {}
+            Ok(GetNextPayloadResult::ChangeState(_))
+            | Err(PageBlobAppendError::InvalidCheckpoint(_)) => {
+                self.confirm_end_of_data(checkpoint).await?;
+            }
+            Err(err) => return Err(err),
+        }
+
+        self.reposition(Some(checkpoint))?;
+        self.init_if_required().await
+    }
+
+    // Reading from the checkpoint stopped at the end marker or at a corrupted record right away.
+    // Position is confirmed from the pages before it, so the log is not read again
+    async fn confirm_end_of_data(
+        &mut self,
+        checkpoint: ReadCheckpoint,
+    ) -> Result<(), PageBlobAppendError> {
+        let state = match self.state.as_mut().unwrap() {
+            PageBlobAppendCacheState::Reading(state) => state,
+            _ => return Ok(()),
+        };
+
+        let reason = match state.check_end_of_data(checkpoint).await? {
+            Some(reason) => reason,
+            None => return Ok(()),
+        };
+
+        let msg = format!(
+            "Position {} of PageBlobAppend {}/{} is not a record boundary. {}",
+            checkpoint.blob_position,
+            state.seq_reader.page_blob.get_container_name(),
+            state.seq_reader.page_blob.get_blob_name(),
+            reason
+        );
+
+        Err(state.refuse_checkpoint(msg))
+    }
+
+    // Everything starting from the broken position is dropped.
+    // End marker is written there and the pages after it are removed
+    async fn truncate_at_last_good(
+        &mut self,
+        info: CorruptedErrorInfo,
+    ) -> Result<(), PageBlobAppendError> {
+        let (blob_size, last_seq) = match self.state.as_ref().unwrap() {
+            PageBlobAppendCacheState::Reading(state) => {
+                (state.blob_size_in_pages * BLOB_PAGE_SIZE, state.last_seq)
+            }
+            _ => return Ok(()),
+        };
+
+        // Another writer could have appended after the broken position
+        self.ensure_ownership().await?;
+
+        self.truncate_sparse_index(info.broken_pos).await?;
+        self.prepare_header_for_writing().await?;
+
+        // Range is reported only once the end marker is written
+        let dropped = DroppedRange {
+            from_position: info.broken_pos,
+            to_position: blob_size.max(info.broken_pos),
+            last_good_seq: last_seq,
+            reason: info.msg.clone(),
+        };
+
+        self.change_state(ChangeState::ToCorrupted(info));
+        self.change_state(ChangeState::ToWriteMode);
+
+        if let PageBlobAppendCacheState::Writing(state) = self.state.as_mut().unwrap() {
+            state.append_and_write(std::iter::empty::<&[u8]>()).await?;
+            state.seq_writer.drop_pages_after_end_marker().await?;
+        }
+
+        self.recovery_report.dropped.push(dropped);
+
+        Ok(())
+    }
+
+    // Index is truncated before the log, so it never points past the end of the log
+    async fn truncate_sparse_index(
+        &mut self,
+        blob_position: usize,
+    ) -> Result<(), PageBlobAppendError> {
+        if let Some(sparse_index) = &mut self.sparse_index {
+            sparse_index.truncate(blob_position).await?;
+        }
+
+        Ok(())
+    }
+
+    // Returns false if there is no valid record after the broken position
+    async fn skip_and_resync(
+        &mut self,
+        info: &CorruptedErrorInfo,
+    ) -> Result<bool, PageBlobAppendError> {
+        let state = match self.state.as_mut().unwrap() {
+            PageBlobAppendCacheState::Reading(state) => state,
+            _ => return Ok(false),
+        };
+
+        let last_seq = state.last_seq;
+
+        // Reader is moved to the position before the range is reported
+        let position = match state.resync(info.broken_pos).await? {
+            Some(position) => position,
+            None => return Ok(false),
+        };
+
+        self.recovery_report.dropped.push(DroppedRange {
+            from_position: info.broken_pos,
+            to_position: position,
+            last_good_seq: last_seq,
+            reason: info.msg.clone(),
+        });
+
+        Ok(true)
+    }
+
     fn handle_error(&mut self, err: &PageBlobAppendError) {
         if let PageBlobAppendError::Corrupted(info) = err {
+            crate::diagnostics::corruption_detected(
+                self.get_page_blob(),
+                info.broken_pos,
+                &info.msg,
+            );
             self.change_state(ChangeState::ToCorrupted(info.clone()));
         }
     }
 
     fn change_state(&mut self, change_state: ChangeState) {
+        let _span = crate::diagnostics::enter_state_transition(
+            self.get_page_blob(),
+            self.state.as_ref().unwrap().as_string_name(),
+            change_state.as_string_name(),
+        );
+
+        self.settings.metrics.time_in_state(
+            self.state.as_ref().unwrap().as_string_name(),
+            self.state_started.elapsed(),
+        );
+        self.state_started = Instant::now();
+
         let mut old_state = None;
         std::mem::swap(&mut old_state, &mut self.state);
 
@@ -155,8 +844,8 @@ impl<TMyPageBlob: MyPageBlob> PageBlobAppend<TMyPageBlob> {
                 match old_state {
                     PageBlobAppendCacheState::NotInitialized(state) => {
                         let state_data: StateDataReading<TMyPageBlob> =
-                            StateDataReading::from_not_initialized(state, self.settings);
-                        self.state = Some(PageBlobAppendCacheState::Reading(state_data));
+                            StateDataReading::from_not_initialized(state, self.settings.clone());
+                        self.state = Some(PageBlobAppendCacheState::Reading(Box::new(state_data)));
                     }
                     PageBlobAppendCacheState::Reading(_) => {
                         self.state = Some(old_state);
@@ -180,7 +869,7 @@ impl<TMyPageBlob: MyPageBlob> PageBlobAppend<TMyPageBlob> {
                 }
                 PageBlobAppendCacheState::Reading(state) => {
                     let state_data: StateDataWriting<TMyPageBlob> =
-                        StateDataWriting::from_reading_state(state, &self.settings);
+                        StateDataWriting::from_reading_state(*state, &self.settings);
                     self.state = Some(PageBlobAppendCacheState::Writing(state_data));
                 }
                 PageBlobAppendCacheState::Corrupted(state) => {
@@ -196,7 +885,7 @@ impl<TMyPageBlob: MyPageBlob> PageBlobAppend<TMyPageBlob> {
                 self.state = Some(PageBlobAppendCacheState::to_corrupted(
                     old_state.unwrap(),
                     &info,
-                    self.settings,
+                    self.settings.clone(),
                 ));
             }
         }
@@ -208,6 +897,9 @@ mod tests {
     use my_azure_page_blob::MyPageBlobMock;
 
     use super::*;
+    use crate::test_utils::{
+        copy_blob, create_blob, create_blob_with_content, get_settings, CountingPageBlob,
+    };
 
     #[tokio::test]
     async fn test_corrupted_and_restored() {
@@ -234,12 +926,14 @@ mod tests {
             cache_capacity_in_pages: 10,
             max_pages_to_write_single_round_trip: 1000,
             max_payload_size_protection: 1024 * 1024,
+            record_format: RecordFormat::Legacy,
+            ..Default::default()
         };
         let mut reader = PageBlobAppend::new(page_blob, settings);
 
         let payload = reader.get_next_payload().await.unwrap();
 
-        assert_eq!(&[3u8; MSG_SIZE as usize], payload.unwrap().as_slice());
+        assert_eq!(&[3u8; MSG_SIZE as usize], payload.unwrap().1.as_slice());
 
         let payload = reader.get_next_payload().await;
 
@@ -255,4 +949,1322 @@ mod tests {
 
         assert_eq!(&[4u8, 0, 0, 0, 5, 5, 5, 5], &result_buffer[516..524]);
     }
+
+    #[tokio::test]
+    async fn test_checksum_mismatch_is_detected() {
+        let page_blob = create_blob().await;
+
+        let settings = get_settings(RecordFormat::Crc32c);
+
+        let mut writer = PageBlobAppend::new(page_blob, settings.clone());
+        assert!(writer.get_next_payload().await.unwrap().is_none());
+
+        writer
+            .append_and_write(&vec![vec![1u8, 1u8, 1u8], vec![2u8, 2u8, 2u8, 2u8]])
+            .await
+            .unwrap();
+
+        let mut content = writer.get_page_blob_mut().download().await.unwrap();
+
+        // Data starts after the header page. Second record starts at 512 + 4 + 4 + 3 = 523.
+        content[531] = 7;
+
+        let page_blob = create_blob_with_content(content).await;
+
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        let payload = reader.get_next_payload().await.unwrap();
+        assert_eq!((1, vec![1u8, 1u8, 1u8]), payload.unwrap());
+
+        let err = reader.get_next_payload().await.err().unwrap();
+
+        if let PageBlobAppendError::Corrupted(info) = err {
+            assert_eq!(523, info.broken_pos);
+            assert_eq!(vec![3u8, 0, 0, 0], info.last_page.unwrap()[..4].to_vec());
+        } else {
+            panic!("Corrupted error is expected");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_newer_format_version_is_refused() {
+        let mut page_blob = create_blob().await;
+
+        let mut header = crate::BlobHeader::new(RecordFormat::Crc32c);
+        header.version += 1;
+
+        page_blob
+            .auto_ressize_and_save_pages(0, 10, header.serialize(), 1)
+            .await
+            .unwrap();
+
+        let settings = get_settings(RecordFormat::Crc32c);
+
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        let err = reader.get_next_payload().await.err().unwrap();
+        assert!(err.is_unsupported_format());
+    }
+
+    #[tokio::test]
+    async fn test_foreign_blob_is_refused() {
+        let mut page_blob = create_blob().await;
+
+        page_blob
+            .auto_ressize_and_save_pages(0, 10, vec![3u8, 0, 0, 0, 1, 1, 1], 1)
+            .await
+            .unwrap();
+
+        let settings = get_settings(RecordFormat::Crc32c);
+
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        let err = reader.get_next_payload().await.err().unwrap();
+        assert!(err.is_unsupported_format());
+    }
+
+    #[tokio::test]
+    async fn test_init_does_not_write_header_of_empty_blob() {
+        let page_blob = create_blob().await;
+
+        let mut state = StateDataNotInitialized::new(page_blob, get_settings(RecordFormat::Crc32c));
+        assert!(matches!(
+            state.init().await.unwrap(),
+            Some(ChangeState::ToWriteMode)
+        ));
+        assert!(state.page_blob.download().await.unwrap().is_empty());
+
+        let mut page_blob_append =
+            PageBlobAppend::new(state.page_blob, get_settings(RecordFormat::Crc32c));
+        assert!(page_blob_append.get_next_payload().await.unwrap().is_none());
+
+        let content = page_blob_append
+            .get_page_blob_mut()
+            .download()
+            .await
+            .unwrap();
+        assert!(crate::BlobHeader::is_header(&content));
+    }
+
+    #[tokio::test]
+    async fn test_header_is_written_and_records_are_read_back() {
+        let page_blob = create_blob().await;
+
+        let settings = get_settings(RecordFormat::Crc32c);
+
+        let mut writer = PageBlobAppend::new(page_blob, settings.clone());
+        assert!(writer.get_next_payload().await.unwrap().is_none());
+
+        writer
+            .append_and_write(&vec![vec![1u8, 1u8, 1u8]])
+            .await
+            .unwrap();
+
+        let content = writer.get_page_blob_mut().download().await.unwrap();
+        assert!(crate::BlobHeader::is_header(&content));
+
+        let page_blob = create_blob_with_content(content).await;
+
+        let settings = get_settings(RecordFormat::Legacy);
+
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        let payload = reader.get_next_payload().await.unwrap();
+        assert_eq!((1, vec![1u8, 1u8, 1u8]), payload.unwrap());
+
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+        assert_eq!(523, reader.get_blob_position());
+    }
+
+    #[tokio::test]
+    async fn test_sequence_numbers_continue_after_reopen() {
+        let page_blob = create_blob().await;
+
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let mut writer = PageBlobAppend::new(page_blob, settings.clone());
+        assert!(writer.get_next_payload().await.unwrap().is_none());
+
+        let seq_range = writer
+            .append_and_write(&vec![vec![1u8], vec![2u8, 2u8]])
+            .await
+            .unwrap();
+        assert_eq!(1..3, seq_range);
+
+        let seq_range = writer
+            .append_and_write(&vec![vec![3u8, 3u8, 3u8]])
+            .await
+            .unwrap();
+        assert_eq!(3..4, seq_range);
+        assert_eq!(512 + 17 + 18 + 19, writer.get_blob_position());
+
+        let page_blob = copy_blob(writer.get_page_blob_mut()).await;
+
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        let mut result = Vec::new();
+        while let Some(item) = reader.get_next_payload().await.unwrap() {
+            result.push(item);
+        }
+
+        assert_eq!(
+            vec![
+                (1, vec![1u8]),
+                (2, vec![2u8, 2u8]),
+                (3, vec![3u8, 3u8, 3u8])
+            ],
+            result
+        );
+
+        let seq_range = reader.append_and_write(&vec![vec![4u8]]).await.unwrap();
+        assert_eq!(4..5, seq_range);
+    }
+
+    #[tokio::test]
+    async fn test_empty_payload_is_refused() {
+        let page_blob = create_blob().await;
+
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let mut writer = PageBlobAppend::new(page_blob, settings.clone());
+        assert!(writer.get_next_payload().await.unwrap().is_none());
+
+        let err = writer
+            .append_and_write(&vec![vec![1u8], vec![]])
+            .await
+            .unwrap_err();
+        assert!(err.is_empty_payload());
+        assert_eq!(512, writer.get_blob_position());
+
+        let seq_range = writer
+            .append_and_write(&vec![vec![2u8, 2u8]])
+            .await
+            .unwrap();
+        assert_eq!(1..2, seq_range);
+
+        let page_blob = copy_blob(writer.get_page_blob_mut()).await;
+
+        let mut reader = PageBlobAppend::new(page_blob, settings);
+
+        let payload = reader.get_next_payload().await.unwrap();
+        assert_eq!((1, vec![2u8, 2u8]), payload.unwrap());
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+    }
+
+    async fn create_blob_with_records(
+        settings: AppendPageBlobSettings,
+        records: Vec<Vec<u8>>,
+    ) -> MyPageBlobMock {
+        let page_blob = create_blob().await;
+
+        let mut writer = PageBlobAppend::new(page_blob, settings.clone());
+        assert!(writer.get_next_payload().await.unwrap().is_none());
+        writer.append_and_write(&records).await.unwrap();
+
+        copy_blob(writer.get_page_blob_mut()).await
+    }
+
+    #[tokio::test]
+    async fn test_resume_reading_from_checkpoint() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let records = vec![vec![1u8; 600], vec![2u8, 2u8], vec![3u8, 3u8, 3u8]];
+
+        let page_blob = create_blob_with_records(settings.clone(), records.clone()).await;
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        reader.get_next_payload().await.unwrap().unwrap();
+        let checkpoint = reader.get_checkpoint().unwrap();
+
+        assert_eq!(1, checkpoint.last_seq);
+        assert_eq!(512 + 16 + 600, checkpoint.blob_position);
+
+        let page_blob = create_blob_with_records(settings.clone(), records).await;
+        let mut reader =
+            PageBlobAppend::new_from_checkpoint(page_blob, settings.clone(), checkpoint);
+
+        let payload = reader.get_next_payload().await.unwrap();
+        assert_eq!((2, vec![2u8, 2u8]), payload.unwrap());
+
+        let payload = reader.get_next_payload().await.unwrap();
+        assert_eq!((3, vec![3u8, 3u8, 3u8]), payload.unwrap());
+
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+
+        let seq_range = reader.append_and_write(&vec![vec![4u8]]).await.unwrap();
+        assert_eq!(4..5, seq_range);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_which_is_not_record_boundary_is_refused() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let records = vec![vec![1u8; 20], vec![2u8; 20]];
+
+        let page_blob = create_blob_with_records(settings.clone(), records.clone()).await;
+
+        let checkpoint = crate::ReadCheckpoint {
+            blob_position: 512 + 4,
+            last_seq: 0,
+        };
+
+        let mut reader =
+            PageBlobAppend::new_from_checkpoint(page_blob, settings.clone(), checkpoint);
+
+        let err = reader.get_next_payload().await.err().unwrap();
+        assert!(err.is_invalid_checkpoint());
+
+        let err = reader.get_next_payload().await.err().unwrap();
+        assert!(err.is_invalid_checkpoint());
+
+        let page_blob = create_blob_with_records(settings.clone(), records).await;
+
+        let checkpoint = crate::ReadCheckpoint {
+            blob_position: 100,
+            last_seq: 0,
+        };
+
+        let mut reader =
+            PageBlobAppend::new_from_checkpoint(page_blob, settings.clone(), checkpoint);
+
+        let err = reader.get_next_payload().await.err().unwrap();
+        assert!(err.is_invalid_checkpoint());
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_into_zeros_of_payload_is_refused() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let records = vec![vec![1u8; 3], vec![0u8; 20], vec![2u8; 5]];
+
+        // Second record starts at 512 + 16 + 3 = 531. Its payload starts at 547
+        let page_blob = create_blob_with_records(settings.clone(), records.clone()).await;
+
+        let checkpoint = crate::ReadCheckpoint {
+            blob_position: 551,
+            last_seq: 1,
+        };
+
+        let mut reader =
+            PageBlobAppend::new_from_checkpoint(page_blob, settings.clone(), checkpoint);
+
+        let err = reader.get_next_payload().await.err().unwrap();
+        assert!(err.is_invalid_checkpoint());
+
+        let err = reader.get_next_payload().await.err().unwrap();
+        assert!(err.is_invalid_checkpoint());
+
+        assert!(reader.append_and_write(&vec![vec![9u8]]).await.is_err());
+
+        // Real end of data is accepted
+        let page_blob = create_blob_with_records(settings.clone(), records).await;
+
+        let checkpoint = crate::ReadCheckpoint {
+            blob_position: 531 + 36 + 21,
+            last_seq: 3,
+        };
+
+        let mut reader =
+            PageBlobAppend::new_from_checkpoint(page_blob, settings.clone(), checkpoint);
+
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+
+        let seq_range = reader.append_and_write(&vec![vec![9u8]]).await.unwrap();
+        assert_eq!(4..5, seq_range);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_into_zeros_at_end_of_last_payload_is_refused() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let mut last_payload = vec![2u8];
+        last_payload.extend_from_slice(&[0u8; 20]);
+
+        // Second record starts at 512 + 16 + 3 = 531. Its payload starts at 547 and ends at 568
+        let records = vec![vec![1u8; 3], last_payload];
+        let page_blob = create_blob_with_records(settings.clone(), records).await;
+
+        let checkpoint = crate::ReadCheckpoint {
+            blob_position: 552,
+            last_seq: 2,
+        };
+
+        let mut reader =
+            PageBlobAppend::new_from_checkpoint(page_blob, settings.clone(), checkpoint);
+
+        let err = reader.get_next_payload().await.err().unwrap();
+        assert!(err.is_invalid_checkpoint());
+        assert!(reader.append_and_write(&vec![vec![9u8]]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_past_end_of_truncated_data_is_refused() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let records = vec![vec![1u8; 3], vec![2u8; 4], vec![3u8; 5]];
+        let page_blob = create_blob_with_records(settings.clone(), records).await;
+
+        let mut page_blob_append = PageBlobAppend::new(page_blob, settings.clone());
+        while page_blob_append.get_next_payload().await.unwrap().is_some() {}
+
+        let end_checkpoint = page_blob_append.get_checkpoint().unwrap();
+        assert_eq!(572, end_checkpoint.blob_position);
+
+        let mut page_blob_append = PageBlobAppend::new(
+            page_blob_append.state.take().unwrap().into_page_blob(),
+            settings.clone(),
+        );
+
+        page_blob_append
+            .truncate_at(ReadCheckpoint {
+                blob_position: 531,
+                last_seq: 1,
+            })
+            .await
+            .unwrap();
+
+        let page_blob = copy_blob(page_blob_append.get_page_blob_mut()).await;
+
+        let mut reader =
+            PageBlobAppend::new_from_checkpoint(page_blob, settings.clone(), end_checkpoint);
+
+        let err = reader.get_next_payload().await.err().unwrap();
+        assert!(err.is_invalid_checkpoint());
+
+        assert!(reader.append_and_write(&vec![vec![9u8]]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_seek_with_sparse_index() {
+        let settings = AppendPageBlobSettings {
+            cache_capacity_in_pages: 2,
+            ..get_settings(RecordFormat::Crc32cWithSequence)
+        };
+
+        let page_blob = create_blob().await;
+
+        let index_blob = create_blob().await;
+
+        let mut writer = PageBlobAppend::new(page_blob, settings.clone());
+        writer.set_sparse_index(crate::SparseIndex::new(index_blob, 1024));
+        assert!(writer.get_next_payload().await.unwrap().is_none());
+
+        for i in 0..100u8 {
+            writer
+                .append_and_write(&vec![vec![i; 100], vec![i; 50]])
+                .await
+                .unwrap();
+        }
+
+        let content = writer.get_page_blob_mut().download().await.unwrap();
+        let sparse_index = writer.get_sparse_index_mut().unwrap();
+        assert!(sparse_index.get_entries_amount().await.unwrap() > 1);
+        let index_content = sparse_index.page_blob.download().await.unwrap();
+
+        let page_blob = create_blob_with_content(content).await;
+
+        let index_blob = create_blob_with_content(index_content).await;
+
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+        reader.set_sparse_index(crate::SparseIndex::new(index_blob, 1024));
+
+        reader.seek(151).await.unwrap();
+
+        let payload = reader.get_next_payload().await.unwrap();
+        assert_eq!((151, vec![75u8; 100]), payload.unwrap());
+
+        reader.seek(10).await.unwrap();
+
+        let payload = reader.get_next_payload().await.unwrap();
+        assert_eq!((10, vec![4u8; 50]), payload.unwrap());
+
+        let checkpoint = reader.get_checkpoint().unwrap();
+
+        reader.seek(1).await.unwrap();
+        reader
+            .seek_to_blob_position(checkpoint.blob_position)
+            .await
+            .unwrap();
+
+        let payload = reader.get_next_payload().await.unwrap();
+        assert_eq!((11, vec![5u8; 100]), payload.unwrap());
+
+        reader.seek(1000).await.unwrap();
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_seek_after_truncate() {
+        let settings = AppendPageBlobSettings {
+            cache_capacity_in_pages: 2,
+            ..get_settings(RecordFormat::Crc32cWithSequence)
+        };
+
+        let page_blob = create_blob().await;
+
+        let index_blob = create_blob().await;
+
+        let mut writer = PageBlobAppend::new(page_blob, settings.clone());
+        writer.set_sparse_index(crate::SparseIndex::new(index_blob, 1024));
+        assert!(writer.get_next_payload().await.unwrap().is_none());
+
+        for i in 0..100u8 {
+            writer
+                .append_and_write(&vec![vec![i; 100], vec![i; 50]])
+                .await
+                .unwrap();
+        }
+
+        let entries_amount = writer
+            .get_sparse_index_mut()
+            .unwrap()
+            .get_entries_amount()
+            .await
+            .unwrap();
+
+        let mut log = PageBlobAppend::new(
+            writer.state.take().unwrap().into_page_blob(),
+            settings.clone(),
+        );
+        log.set_sparse_index(writer.sparse_index.take().unwrap());
+
+        log.seek(101).await.unwrap();
+        let (blob_position, seq, _) = log.get_next_payload_with_position().await.unwrap().unwrap();
+        assert_eq!(101, seq);
+
+        let checkpoint = ReadCheckpoint {
+            blob_position,
+            last_seq: 100,
+        };
+        log.truncate_at(checkpoint).await.unwrap();
+
+        let sparse_index = log.get_sparse_index_mut().unwrap();
+        assert!(sparse_index.get_entries_amount().await.unwrap() < entries_amount);
+        let last_entry = sparse_index.find_by_seq(u64::MAX).await.unwrap().unwrap();
+        assert!(last_entry.blob_position < checkpoint.blob_position);
+
+        let mut reader =
+            PageBlobAppend::new(log.state.take().unwrap().into_page_blob(), settings.clone());
+        reader.set_sparse_index(log.sparse_index.take().unwrap());
+
+        reader.seek(1000).await.unwrap();
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+
+        // Same seqs are written again at other positions
+        for i in 0..100u8 {
+            reader.append_and_write(&vec![vec![i; 30]]).await.unwrap();
+        }
+
+        let sparse_index = reader.sparse_index.take().unwrap();
+        let mut reader = PageBlobAppend::new(
+            reader.state.take().unwrap().into_page_blob(),
+            settings.clone(),
+        );
+        reader.set_sparse_index(sparse_index);
+
+        reader.seek(151).await.unwrap();
+
+        let payload = reader.get_next_payload().await.unwrap();
+        assert_eq!((151, vec![50u8; 30]), payload.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_payloads_stream() {
+        use futures::StreamExt;
+
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let records = vec![vec![1u8], vec![2u8, 2u8], vec![3u8, 3u8, 3u8]];
+
+        let page_blob = create_blob_with_records(settings.clone(), records.clone()).await;
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        let result: Vec<Vec<u8>> = reader
+            .payloads_stream()
+            .map(|payload| payload.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(records, result);
+
+        let seq_range = reader.append_and_write(&vec![vec![4u8]]).await.unwrap();
+        assert_eq!(4..5, seq_range);
+    }
+
+    #[tokio::test]
+    async fn test_skip_and_resync_reads_blob_in_windows() {
+        let settings = AppendPageBlobSettings {
+            max_pages_to_write_single_round_trip: 1,
+            max_payload_size_protection: 1024,
+            recovery_mode: crate::RecoveryMode::SkipAndResync,
+            ..get_settings(RecordFormat::Crc32cWithSequence)
+        };
+
+        let records = vec![vec![1u8; 3], vec![2u8; 1000], vec![3u8; 700], vec![4u8; 5]];
+        let mut page_blob = create_blob_with_records(settings.clone(), records).await;
+
+        let mut content = page_blob.download().await.unwrap();
+
+        // Records start at 512, 531, 1547 and 2263. Third one is in the third and the fourth pages
+        content[600] = 7;
+
+        page_blob
+            .auto_ressize_and_save_pages(0, 10, content, 1)
+            .await
+            .unwrap();
+
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        assert_eq!(
+            (1, vec![1u8; 3]),
+            reader.get_next_payload().await.unwrap().unwrap()
+        );
+        assert_eq!(
+            (3, vec![3u8; 700]),
+            reader.get_next_payload().await.unwrap().unwrap()
+        );
+        assert_eq!(
+            (4, vec![4u8; 5]),
+            reader.get_next_payload().await.unwrap().unwrap()
+        );
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+
+        let report = reader.get_recovery_report();
+        assert_eq!(531, report.dropped[0].from_position);
+        assert_eq!(1547, report.dropped[0].to_position);
+    }
+
+    async fn create_corrupted_blob(settings: AppendPageBlobSettings) -> MyPageBlobMock {
+        let records = vec![vec![1u8; 3], vec![2u8; 4], vec![3u8; 5]];
+        let mut page_blob = create_blob_with_records(settings, records).await;
+
+        let mut content = page_blob.download().await.unwrap();
+
+        // Records start at 512, 531 and 551. Breaking the payload of the second one
+        content[547] = 7;
+
+        page_blob
+            .auto_ressize_and_save_pages(0, 10, content, 1)
+            .await
+            .unwrap();
+
+        page_blob
+    }
+
+    #[tokio::test]
+    async fn test_truncate_at_last_good() {
+        let settings = AppendPageBlobSettings {
+            recovery_mode: crate::RecoveryMode::TruncateAtLastGood,
+            ..get_settings(RecordFormat::Crc32cWithSequence)
+        };
+
+        let page_blob = create_corrupted_blob(settings.clone()).await;
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        assert_eq!(
+            (1, vec![1u8; 3]),
+            reader.get_next_payload().await.unwrap().unwrap()
+        );
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+
+        let report = reader.get_recovery_report();
+        assert_eq!(1, report.dropped.len());
+        assert_eq!(531, report.dropped[0].from_position);
+        assert_eq!(1, report.dropped[0].last_good_seq);
+
+        // Writing resumes right after the last good record
+        let seq_range = reader.append_and_write(&vec![vec![9u8]]).await.unwrap();
+        assert_eq!(2..3, seq_range);
+
+        let page_blob = copy_blob(reader.get_page_blob_mut()).await;
+
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        assert_eq!(
+            (1, vec![1u8; 3]),
+            reader.get_next_payload().await.unwrap().unwrap()
+        );
+        assert_eq!(
+            (2, vec![9u8]),
+            reader.get_next_payload().await.unwrap().unwrap()
+        );
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+        assert!(reader.get_recovery_report().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_truncate_is_not_reported() {
+        let settings = AppendPageBlobSettings {
+            recovery_mode: crate::RecoveryMode::TruncateAtLastGood,
+            retry_policy: crate::RetryPolicy {
+                max_attempts: Some(1),
+                ..Default::default()
+            },
+            ..get_settings(RecordFormat::Crc32cWithSequence)
+        };
+
+        let mut page_blob = CountingPageBlob::new(create_corrupted_blob(settings.clone()).await);
+        page_blob.fail_writes = true;
+
+        let mut reader = PageBlobAppend::new(page_blob, settings);
+
+        assert_eq!(
+            (1, vec![1u8; 3]),
+            reader.get_next_payload().await.unwrap().unwrap()
+        );
+        assert!(reader.get_next_payload().await.is_err());
+        assert!(reader.get_recovery_report().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_skip_and_resync() {
+        let settings = AppendPageBlobSettings {
+            recovery_mode: crate::RecoveryMode::SkipAndResync,
+            ..get_settings(RecordFormat::Crc32cWithSequence)
+        };
+
+        let page_blob = create_corrupted_blob(settings.clone()).await;
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        assert_eq!(
+            (1, vec![1u8; 3]),
+            reader.get_next_payload().await.unwrap().unwrap()
+        );
+        assert_eq!(
+            (3, vec![3u8; 5]),
+            reader.get_next_payload().await.unwrap().unwrap()
+        );
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+
+        let report = reader.get_recovery_report();
+        assert_eq!(1, report.dropped.len());
+        assert_eq!(531, report.dropped[0].from_position);
+        assert_eq!(551, report.dropped[0].to_position);
+        assert_eq!(20, report.get_dropped_bytes());
+
+        let seq_range = reader.append_and_write(&vec![vec![4u8]]).await.unwrap();
+        assert_eq!(4..5, seq_range);
+    }
+
+    #[tokio::test]
+    async fn test_fail_mode_keeps_blob_corrupted() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let page_blob = create_corrupted_blob(settings.clone()).await;
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        reader.get_next_payload().await.unwrap();
+        assert!(reader
+            .get_next_payload()
+            .await
+            .err()
+            .unwrap()
+            .is_corrupted());
+        assert!(reader.append_and_write(&vec![vec![4u8]]).await.is_err());
+        assert!(reader.get_recovery_report().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_next_seq_0_is_refused() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let mut page_blob_append = PageBlobAppend::new(create_blob().await, settings);
+        assert!(page_blob_append.get_next_payload().await.unwrap().is_none());
+
+        let err = page_blob_append.set_next_seq(0).err().unwrap();
+        assert!(matches!(err, PageBlobAppendError::Forbidden(_)));
+        assert_eq!(0, page_blob_append.get_checkpoint().unwrap().last_seq);
+
+        page_blob_append.set_next_seq(5).unwrap();
+
+        let seq_range = page_blob_append
+            .append_and_write(&vec![vec![1u8]])
+            .await
+            .unwrap();
+        assert_eq!(5..6, seq_range);
+    }
+
+    #[tokio::test]
+    async fn test_truncate_at() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let records = vec![vec![1u8; 3], vec![2u8; 4], vec![3u8; 5]];
+        let page_blob = create_blob_with_records(settings.clone(), records).await;
+
+        let mut page_blob_append = PageBlobAppend::new(page_blob, settings.clone());
+
+        page_blob_append
+            .truncate_at(ReadCheckpoint {
+                blob_position: 531,
+                last_seq: 1,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(531, page_blob_append.get_blob_position());
+        assert_eq!(
+            531,
+            page_blob_append.get_recovery_report().dropped[0].from_position
+        );
+
+        let seq_range = page_blob_append
+            .append_and_write(&vec![vec![9u8]])
+            .await
+            .unwrap();
+        assert_eq!(2..3, seq_range);
+    }
+
+    #[tokio::test]
+    async fn test_truncate_at_before_corrupted_record() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let page_blob = create_corrupted_blob(settings.clone()).await;
+
+        let mut page_blob_append = PageBlobAppend::new(page_blob, settings.clone());
+
+        page_blob_append
+            .truncate_at(ReadCheckpoint {
+                blob_position: 531,
+                last_seq: 1,
+            })
+            .await
+            .unwrap();
+
+        let seq_range = page_blob_append
+            .append_and_write(&vec![vec![9u8]])
+            .await
+            .unwrap();
+        assert_eq!(2..3, seq_range);
+
+        let page_blob = copy_blob(page_blob_append.get_page_blob_mut()).await;
+
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        assert_eq!(
+            (1, vec![1u8; 3]),
+            reader.get_next_payload().await.unwrap().unwrap()
+        );
+        assert_eq!(
+            (2, vec![9u8]),
+            reader.get_next_payload().await.unwrap().unwrap()
+        );
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_truncate_at_middle_of_record_is_refused() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let records = vec![vec![1u8; 3], vec![2u8; 4], vec![3u8; 5]];
+        let page_blob = create_blob_with_records(settings.clone(), records).await;
+
+        let mut page_blob_append = PageBlobAppend::new(page_blob, settings.clone());
+
+        let err = page_blob_append
+            .truncate_at(ReadCheckpoint {
+                blob_position: 520,
+                last_seq: 1,
+            })
+            .await
+            .err()
+            .unwrap();
+        assert!(err.is_invalid_checkpoint());
+        assert!(page_blob_append.get_recovery_report().is_empty());
+
+        let page_blob = copy_blob(page_blob_append.get_page_blob_mut()).await;
+
+        let mut reader = PageBlobAppend::new(page_blob, settings.clone());
+
+        let mut seqs = Vec::new();
+        while let Some((seq, _)) = reader.get_next_payload().await.unwrap() {
+            seqs.push(seq);
+        }
+        assert_eq!(vec![1, 2, 3], seqs);
+    }
+
+    #[tokio::test]
+    async fn test_open_with_snapshot() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let records = vec![vec![1u8; 3], vec![2u8; 4]];
+        let page_blob = create_blob_with_records(settings.clone(), records).await;
+
+        let mut snapshot_blob = MyPageBlobMock::new();
+
+        let mut page_blob_append = PageBlobAppend::new(page_blob, settings.clone());
+        while page_blob_append.get_next_payload().await.unwrap().is_some() {}
+
+        let checkpoint = page_blob_append
+            .write_snapshot(&mut snapshot_blob, b"state")
+            .await
+            .unwrap();
+        assert_eq!(2, checkpoint.last_seq);
+
+        page_blob_append
+            .append_and_write(&vec![vec![3u8; 5]])
+            .await
+            .unwrap();
+
+        let page_blob = copy_blob(page_blob_append.get_page_blob_mut()).await;
+
+        let (mut page_blob_append, snapshot) =
+            PageBlobAppend::open_with_snapshot(page_blob, &mut snapshot_blob, settings.clone())
+                .await
+                .unwrap();
+
+        assert_eq!(Some(b"state".to_vec()), snapshot);
+
+        let result = page_blob_append.get_next_payload().await.unwrap();
+        assert_eq!(Some((3, vec![3u8; 5])), result);
+        assert!(page_blob_append.get_next_payload().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_open_with_snapshot_does_not_read_the_log_again() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        // Records take about 230 pages. Reading them again takes more than 20 round trips
+        let records = (0..1000).map(|i| vec![i as u8 | 1; 100]).collect();
+        let page_blob = create_blob_with_records(settings.clone(), records).await;
+
+        let mut snapshot_blob = MyPageBlobMock::new();
+
+        let mut page_blob_append = PageBlobAppend::new(page_blob, settings.clone());
+        while page_blob_append.get_next_payload().await.unwrap().is_some() {}
+
+        page_blob_append
+            .write_snapshot(&mut snapshot_blob, b"state")
+            .await
+            .unwrap();
+
+        let page_blob =
+            CountingPageBlob::new(copy_blob(page_blob_append.get_page_blob_mut()).await);
+
+        let (mut page_blob_append, snapshot) =
+            PageBlobAppend::open_with_snapshot(page_blob, &mut snapshot_blob, settings.clone())
+                .await
+                .unwrap();
+
+        assert_eq!(Some(b"state".to_vec()), snapshot);
+
+        // Header page, the checkpoint page and the pages of the last record
+        assert!(page_blob_append.get_page_blob().get_calls <= 3);
+
+        let seq_range = page_blob_append
+            .append_and_write(&vec![vec![9u8]])
+            .await
+            .unwrap();
+        assert_eq!(1001..1002, seq_range);
+    }
+
+    #[tokio::test]
+    async fn test_torn_snapshot_replays_whole_log() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let records = vec![vec![1u8; 3], vec![2u8; 4]];
+        let page_blob = create_blob_with_records(settings.clone(), records).await;
+
+        // Snapshot is bigger than one page and only the first page is written
+        let snapshot = Snapshot {
+            checkpoint: ReadCheckpoint {
+                blob_position: 531,
+                last_seq: 1,
+            },
+            payload: vec![7u8; 1000],
+        };
+
+        let mut snapshot_blob =
+            create_blob_with_content(snapshot.serialize(1)[..BLOB_PAGE_SIZE].to_vec()).await;
+
+        let (mut page_blob_append, snapshot) =
+            PageBlobAppend::open_with_snapshot(page_blob, &mut snapshot_blob, settings.clone())
+                .await
+                .unwrap();
+
+        assert!(snapshot.is_none());
+
+        let result = page_blob_append.get_next_payload().await.unwrap();
+        assert_eq!(Some((1, vec![1u8; 3])), result);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_past_end_of_log_falls_back_to_older_one() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let records = vec![vec![1u8; 3], vec![2u8; 4], vec![3u8; 5]];
+        let page_blob = create_blob_with_records(settings.clone(), records).await;
+
+        let mut snapshot_blob = MyPageBlobMock::new();
+
+        let mut page_blob_append = PageBlobAppend::new(page_blob, settings.clone());
+        page_blob_append.get_next_payload().await.unwrap().unwrap();
+        page_blob_append.get_next_payload().await.unwrap().unwrap();
+
+        let older = page_blob_append
+            .write_snapshot(&mut snapshot_blob, b"older")
+            .await
+            .unwrap();
+        assert_eq!(2, older.last_seq);
+
+        while page_blob_append.get_next_payload().await.unwrap().is_some() {}
+
+        let newer = page_blob_append
+            .write_snapshot(&mut snapshot_blob, b"newer")
+            .await
+            .unwrap();
+        assert_eq!(3, newer.last_seq);
+
+        // Log loses the last record, so the newer snapshot points past its end
+        let page_blob = copy_blob(page_blob_append.get_page_blob_mut()).await;
+
+        let mut page_blob_append = PageBlobAppend::new(page_blob, settings.clone());
+        page_blob_append.truncate_at(older).await.unwrap();
+
+        let (mut page_blob_append, snapshot) = PageBlobAppend::open_with_snapshot(
+            std::mem::replace(page_blob_append.get_page_blob_mut(), MyPageBlobMock::new()),
+            &mut snapshot_blob,
+            settings.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(Some(b"older".to_vec()), snapshot);
+        assert!(page_blob_append.get_next_payload().await.unwrap().is_none());
+
+        let seq_range = page_blob_append
+            .append_and_write(&vec![vec![4u8; 5]])
+            .await
+            .unwrap();
+        assert_eq!(3..4, seq_range);
+    }
+
+    #[tokio::test]
+    async fn test_append_borrowed_payloads() {
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        let page_blob = create_blob().await;
+
+        let mut page_blob_append = PageBlobAppend::new(page_blob, settings.clone());
+        assert!(page_blob_append.get_next_payload().await.unwrap().is_none());
+
+        let payload = vec![7u8; 300];
+
+        // Every package but the first one starts in the middle of a page
+        for seq in 1..5u64 {
+            let seq_range = page_blob_append
+                .append_payloads([&payload[..100], &payload[100..]])
+                .await
+                .unwrap();
+
+            assert_eq!(seq * 2 - 1..seq * 2 + 1, seq_range);
+        }
+
+        let page_blob = copy_blob(page_blob_append.get_page_blob_mut()).await;
+
+        let mut reader = PageBlobAppend::new(page_blob, settings);
+
+        for seq in 1..9u64 {
+            let (read_seq, read_payload) = reader.get_next_payload().await.unwrap().unwrap();
+            assert_eq!(seq, read_seq);
+            assert_eq!(if seq % 2 == 1 { 100 } else { 200 }, read_payload.len());
+        }
+
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_payloads_are_sliced_from_page_cache() {
+        let settings = AppendPageBlobSettings {
+            cache_capacity_in_pages: 2,
+            ..get_settings(RecordFormat::Crc32cWithSequence)
+        };
+
+        // Third record crosses the end of the first two pages buffer
+        let page_blob = create_blob_with_records(
+            settings.clone(),
+            vec![vec![1u8; 3], vec![2u8; 4], vec![3u8; 1000]],
+        )
+        .await;
+
+        let mut page_blob_append = PageBlobAppend::new(page_blob, settings);
+
+        let (seq, first) = page_blob_append
+            .get_next_payload_bytes()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(1, seq);
+        assert_eq!(&[1u8; 3][..], &first[..]);
+
+        let (_, second) = page_blob_append
+            .get_next_payload_bytes()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&[2u8; 4][..], &second[..]);
+
+        // Both records are slices of the same downloaded buffer: 16 bytes of the record header between them
+        assert_eq!(
+            first.as_ptr() as usize + first.len() + 16,
+            second.as_ptr() as usize
+        );
+
+        let (_, third) = page_blob_append
+            .get_next_payload_bytes()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&[3u8; 1000][..], &third[..]);
+
+        assert!(page_blob_append
+            .get_next_payload_bytes()
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[cfg(feature = "lz4")]
+    fn get_lz4_settings() -> AppendPageBlobSettings {
+        AppendPageBlobSettings {
+            compression: crate::Compression::Lz4,
+            ..get_settings(RecordFormat::Crc32cWithSequence)
+        }
+    }
+
+    #[cfg(feature = "lz4")]
+    #[tokio::test]
+    async fn test_old_and_compressed_records_are_read() {
+        let mut settings = get_lz4_settings();
+        settings.compression = crate::Compression::None;
+
+        let page_blob =
+            create_blob_with_records(settings.clone(), vec![vec![1u8; 1000], vec![2u8; 3]]).await;
+
+        let mut page_blob_append = PageBlobAppend::new(page_blob, get_lz4_settings());
+        while page_blob_append.get_next_payload().await.unwrap().is_some() {}
+
+        page_blob_append
+            .append_and_write(&vec![vec![3u8; 1000], vec![4u8; 3]])
+            .await
+            .unwrap();
+
+        let content = page_blob_append
+            .get_page_blob_mut()
+            .download()
+            .await
+            .unwrap();
+
+        let header = crate::BlobHeader::deserialize(&content[..BLOB_PAGE_SIZE]).unwrap();
+        assert!(header.has_compression());
+
+        // Old record takes 1016 bytes. Compressed one is much smaller
+        assert!(content.len() < 3 * BLOB_PAGE_SIZE + 1016 * 2);
+
+        let page_blob = create_blob_with_content(content).await;
+
+        // Codec is taken from the blob, so the reader does not need the compression settings
+        let mut reader = PageBlobAppend::new(page_blob, settings);
+
+        let mut result = Vec::new();
+        while let Some(record) = reader.get_next_payload().await.unwrap() {
+            result.push(record);
+        }
+
+        assert_eq!(
+            vec![
+                (1, vec![1u8; 1000]),
+                (2, vec![2u8; 3]),
+                (3, vec![3u8; 1000]),
+                (4, vec![4u8; 3])
+            ],
+            result
+        );
+    }
+
+    #[cfg(feature = "lz4")]
+    #[tokio::test]
+    async fn test_flags_are_added_only_when_switching_to_writing() {
+        let mut settings = get_lz4_settings();
+        settings.compression = crate::Compression::None;
+
+        let mut page_blob =
+            create_blob_with_records(settings, vec![vec![1u8; 3], vec![2u8; 4]]).await;
+        let content = page_blob.download().await.unwrap();
+
+        let mut page_blob_append = PageBlobAppend::new(page_blob, get_lz4_settings());
+        assert_eq!(
+            (1, vec![1u8; 3]),
+            page_blob_append.get_next_payload().await.unwrap().unwrap()
+        );
+
+        let content_after_read = page_blob_append
+            .get_page_blob_mut()
+            .download()
+            .await
+            .unwrap();
+        assert_eq!(content, content_after_read);
+
+        assert!(page_blob_append.get_next_payload().await.unwrap().is_some());
+        assert!(page_blob_append.get_next_payload().await.unwrap().is_none());
+
+        let content = page_blob_append
+            .get_page_blob_mut()
+            .download()
+            .await
+            .unwrap();
+        let header = crate::BlobHeader::deserialize(&content[..BLOB_PAGE_SIZE]).unwrap();
+        assert!(header.has_compression());
+    }
+
+    #[cfg(feature = "lz4")]
+    #[tokio::test]
+    async fn test_compression_is_rejected_for_legacy_format() {
+        let mut settings = get_lz4_settings();
+        settings.record_format = RecordFormat::Legacy;
+
+        let page_blob = create_blob().await;
+
+        let mut page_blob_append = PageBlobAppend::new(page_blob, settings);
+
+        let err = page_blob_append.get_next_payload().await.unwrap_err();
+        assert!(err.is_unsupported_format());
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    fn get_encrypted_settings(key_provider: crate::StaticKeyProvider) -> AppendPageBlobSettings {
+        AppendPageBlobSettings {
+            encryption: Some(crate::Encryption::new(
+                crate::EncryptionAlgorithm::Aes256Gcm,
+                std::sync::Arc::new(key_provider),
+            )),
+            ..get_settings(RecordFormat::Crc32cWithSequence)
+        }
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[tokio::test]
+    async fn test_encrypted_records_are_read_after_key_rotation() {
+        let first_key = crate::StaticKeyProvider::new(1, [1u8; crate::KEY_SIZE]);
+
+        let page_blob = create_blob_with_records(
+            get_encrypted_settings(first_key),
+            vec![b"secret one".to_vec()],
+        )
+        .await;
+
+        // New records are encrypted with the key 2. Key 1 is kept to read the old ones
+        let rotated_keys = || {
+            crate::StaticKeyProvider::new(2, [2u8; crate::KEY_SIZE])
+                .with_key(1, [1u8; crate::KEY_SIZE])
+        };
+
+        let mut page_blob_append =
+            PageBlobAppend::new(page_blob, get_encrypted_settings(rotated_keys()));
+        while page_blob_append.get_next_payload().await.unwrap().is_some() {}
+
+        page_blob_append
+            .append_and_write(&vec![b"secret two".to_vec()])
+            .await
+            .unwrap();
+
+        let content = page_blob_append
+            .get_page_blob_mut()
+            .download()
+            .await
+            .unwrap();
+
+        let header = crate::BlobHeader::deserialize(&content[..BLOB_PAGE_SIZE]).unwrap();
+        assert!(header.has_encryption());
+
+        assert!(!content.windows(6).any(|window| window == b"secret"));
+
+        let page_blob = create_blob_with_content(content).await;
+
+        let mut reader = PageBlobAppend::new(page_blob, get_encrypted_settings(rotated_keys()));
+
+        let mut result = Vec::new();
+        while let Some(record) = reader.get_next_payload().await.unwrap() {
+            result.push(record);
+        }
+
+        assert_eq!(
+            vec![(1, b"secret one".to_vec()), (2, b"secret two".to_vec())],
+            result
+        );
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[tokio::test]
+    async fn test_payload_too_large_after_encryption_is_refused() {
+        // Envelope takes 33 bytes, so only payloads up to 7 bytes fit
+        let settings = AppendPageBlobSettings {
+            max_payload_size_protection: 40,
+            ..get_encrypted_settings(crate::StaticKeyProvider::new(1, [1u8; crate::KEY_SIZE]))
+        };
+
+        let mut page_blob_append = PageBlobAppend::new(create_blob().await, settings.clone());
+        assert!(page_blob_append.get_next_payload().await.unwrap().is_none());
+
+        let err = page_blob_append
+            .append_and_write(&vec![vec![1u8; 5], vec![2u8; 10]])
+            .await
+            .unwrap_err();
+        assert!(err.is_payload_too_large());
+
+        let seq_range = page_blob_append
+            .append_and_write(&vec![vec![1u8; 5]])
+            .await
+            .unwrap();
+        assert_eq!(1..2, seq_range);
+
+        let page_blob = copy_blob(page_blob_append.get_page_blob_mut()).await;
+
+        let mut reader = PageBlobAppend::new(page_blob, settings);
+        assert_eq!(
+            Some((1, vec![1u8; 5])),
+            reader.get_next_payload().await.unwrap()
+        );
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[tokio::test]
+    async fn test_wrong_key_is_not_treated_as_corruption() {
+        let page_blob = create_blob_with_records(
+            get_encrypted_settings(crate::StaticKeyProvider::new(1, [1u8; crate::KEY_SIZE])),
+            vec![vec![1u8; 3], vec![2u8; 3]],
+        )
+        .await;
+
+        let mut settings =
+            get_encrypted_settings(crate::StaticKeyProvider::new(1, [7u8; crate::KEY_SIZE]));
+        settings.recovery_mode = crate::RecoveryMode::TruncateAtLastGood;
+
+        let mut page_blob_append = PageBlobAppend::new(page_blob, settings);
+
+        let err = page_blob_append.get_next_payload().await.unwrap_err();
+        assert!(err.is_decryption_failed());
+
+        // Reading stops at the record instead of skipping it
+        let err = page_blob_append.get_next_payload().await.unwrap_err();
+        assert!(err.is_decryption_failed());
+
+        // Nothing was truncated, so the right key reads everything
+        let page_blob = copy_blob(page_blob_append.get_page_blob_mut()).await;
+
+        let mut reader = PageBlobAppend::new(
+            page_blob,
+            get_encrypted_settings(crate::StaticKeyProvider::new(1, [1u8; crate::KEY_SIZE])),
+        );
+
+        assert_eq!(
+            Some((1, vec![1u8; 3])),
+            reader.get_next_payload().await.unwrap()
+        );
+        assert_eq!(
+            Some((2, vec![2u8; 3])),
+            reader.get_next_payload().await.unwrap()
+        );
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+    }
 }
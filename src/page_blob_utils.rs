@@ -1,43 +1,43 @@
-use std::time::Duration;
-
 use my_azure_page_blob::MyPageBlob;
 use my_azure_storage_sdk::AzureStorageError;
 
+use crate::{AppendPageBlobSettings, PageBlobMetrics, RetryPolicy};
+
 pub async fn create_container_with_retires<TMyPageBlob: MyPageBlob>(
     page_blob: &mut TMyPageBlob,
+    retry_policy: &RetryPolicy,
+    metrics: &PageBlobMetrics,
 ) -> Result<(), AzureStorageError> {
-    let mut attempt_no = 1;
+    let mut attempts = crate::with_retries::RetryAttempts::new(
+        retry_policy,
+        metrics,
+        "create_container_with_retires",
+    );
 
     loop {
-        let result = page_blob.create_container_if_not_exist().await;
-
-        if result.is_ok() {
-            return result;
-        }
-
-        let err = result.err().unwrap();
-
-        match &err {
-            AzureStorageError::HyperError { err: _ } => {
-                println!(
-                    "Can not execute create_container_with_retires because of  {:?}. Attempt {} Retrying",
-                    err, attempt_no
-                );
-                attempt_no += 1;
-
-                if attempt_no > 5 {
-                    return Err(err);
-                }
-
-                tokio::time::sleep(Duration::from_secs(3)).await;
-            }
-            _ => {
-                return Err(err);
-            }
+        match page_blob.create_container_if_not_exist().await {
+            Ok(()) => return Ok(()),
+            Err(err) => attempts.wait_before_retry(err).await?,
         }
     }
 }
 
+// Copies all the pages. Blobs can be of different kinds, e.g. Azure and local file
+pub async fn copy_blob<TSrcPageBlob: MyPageBlob, TDestPageBlob: MyPageBlob>(
+    src: &mut TSrcPageBlob,
+    dest: &mut TDestPageBlob,
+    settings: &AppendPageBlobSettings,
+) -> Result<(), AzureStorageError> {
+    crate::states::copy_blob(
+        src,
+        dest,
+        settings.max_pages_to_write_single_round_trip,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await
+}
+
 pub fn compile_payloads(payloads: &Vec<Vec<u8>>) -> Vec<u8> {
     let mut result = Vec::new();
 
@@ -0,0 +1,5 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadCheckpoint {
+    pub blob_position: usize,
+    pub last_seq: u64,
+}
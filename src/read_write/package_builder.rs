@@ -1,18 +1,160 @@
+use std::{borrow::Cow, ops::Range};
+
+use crate::{
+    compression::Compression,
+    encryption::{self, RecordEncryptor},
+    record_size_field::RecordSizeField,
+    settings::RecordFormat,
+    PageBlobAppendError,
+};
+
 pub struct PackageBuilder {
     pub buffer: Vec<u8>,
+    page_prefix_size: usize,
+    payloads_size: usize,
+    record_format: RecordFormat,
+    compression: Compression,
+    encryptor: Option<RecordEncryptor>,
+    max_payload_size: u32,
+    first_seq: u64,
+    next_seq: u64,
 }
 
 impl PackageBuilder {
-    pub fn new() -> Self {
-        Self { buffer: Vec::new() }
+    pub fn new(record_format: RecordFormat, first_seq: u64) -> Self {
+        Self {
+            buffer: Vec::new(),
+            page_prefix_size: 0,
+            payloads_size: 0,
+            record_format,
+            compression: Compression::None,
+            encryptor: None,
+            max_payload_size: RecordSizeField::MAX_SIZE,
+            first_seq,
+            next_seq: first_seq,
+        }
+    }
+
+    // Package is written from the start of the page the previous one ended at. The written part of the page
+    // goes first, so the buffer is built once and sent as is
+    pub fn with_page_prefix(mut self, page_prefix: &[u8]) -> Self {
+        self.buffer.extend_from_slice(page_prefix);
+        self.page_prefix_size = page_prefix.len();
+        self
+    }
+
+    // Blob has to have FLAG_COMPRESSION in the header before compressed records are written
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
     }
 
-    pub fn add_payload(&mut self, payload: &[u8]) {
-        let size = payload.len() as i32;
+    // Blob has to have FLAG_ENCRYPTION in the header before encrypted records are written
+    pub fn with_encryptor(mut self, encryptor: Option<RecordEncryptor>) -> Self {
+        self.encryptor = encryptor;
+        self
+    }
+
+    // Reader refuses records larger than its max_payload_size_protection, so they are not written.
+    // Both the payload and the stored payload have to fit
+    pub fn with_max_payload_size(mut self, max_payload_size: u32) -> Self {
+        self.max_payload_size = max_payload_size.min(RecordSizeField::MAX_SIZE);
+        self
+    }
+
+    pub fn add_payload(&mut self, payload: &[u8]) -> Result<(), PageBlobAppendError> {
+        let seq = self.next_seq;
+        let payload_size = payload.len();
+
+        if payload_size > self.max_payload_size as usize {
+            return Err(PageBlobAppendError::PayloadTooLarge(format!(
+                "Record {} has {} bytes. Maximum is {}",
+                seq, payload_size, self.max_payload_size
+            )));
+        }
+
+        let (codec, payload) = self.compression.compress(payload);
+
+        // Compressed payload is encrypted. Checksum covers the stored bytes
+        let payload = match &mut self.encryptor {
+            Some(encryptor) => {
+                let seq = self.record_format.has_sequence_number().then_some(seq);
+                Cow::Owned(encryptor.encrypt(&payload, &encryption::get_aad(codec, seq)))
+            }
+            None => payload,
+        };
+
+        // Reader checks the stored size. It also must not reach the codec and encryption bits
+        if payload.len() > self.max_payload_size as usize {
+            return Err(PageBlobAppendError::PayloadTooLarge(format!(
+                "Record {} takes {} bytes when stored. Maximum is {}",
+                seq,
+                payload.len(),
+                self.max_payload_size
+            )));
+        }
+
+        self.next_seq += 1;
+        self.payloads_size += payload_size;
+
+        let size = RecordSizeField {
+            codec,
+            encrypted: self.encryptor.is_some(),
+            size: payload.len() as u32,
+        }
+        .serialize();
 
         let size_as_bytes = size.to_le_bytes();
         self.buffer.extend_from_slice(&size_as_bytes);
-        self.buffer.extend_from_slice(payload);
+
+        match self.record_format {
+            RecordFormat::Legacy => {}
+            RecordFormat::Crc32c => {
+                let crc = crc32c::crc32c(&payload);
+                self.buffer.extend_from_slice(&crc.to_le_bytes());
+            }
+            RecordFormat::Crc32cWithSequence => {
+                let seq_as_bytes = seq.to_le_bytes();
+                let crc = crc32c::crc32c_append(crc32c::crc32c(&seq_as_bytes), &payload);
+                self.buffer.extend_from_slice(&crc.to_le_bytes());
+                self.buffer.extend_from_slice(&seq_as_bytes);
+            }
+        }
+
+        self.buffer.extend_from_slice(&payload);
+
+        Ok(())
+    }
+
+    // Keeps the seq the record already has. Seqs still have to grow within the blob
+    pub fn add_payload_with_seq(
+        &mut self,
+        seq: u64,
+        payload: &[u8],
+    ) -> Result<(), PageBlobAppendError> {
+        if self.get_records_size() == 0 {
+            self.first_seq = seq;
+        }
+
+        self.next_seq = seq;
+        self.add_payload(payload)
+    }
+
+    pub fn get_page_prefix_size(&self) -> usize {
+        self.page_prefix_size
+    }
+
+    pub fn get_records_size(&self) -> usize {
+        self.buffer.len() - self.page_prefix_size
+    }
+
+    // Size of the payloads before they are compressed and encrypted
+    pub fn get_payloads_size(&self) -> usize {
+        self.payloads_size
+    }
+
+    pub fn get_seq_range(&self) -> Range<u64> {
+        self.first_seq..self.next_seq
     }
 
     pub fn get_result(mut self) -> Vec<u8> {
@@ -20,3 +162,55 @@ impl PackageBuilder {
         self.buffer
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32c_record_layout() {
+        let mut builder = PackageBuilder::new(RecordFormat::Crc32c, 1);
+        builder.add_payload(&[1u8, 2u8, 3u8]).unwrap();
+
+        let result = builder.get_result();
+
+        let crc = crc32c::crc32c(&[1u8, 2u8, 3u8]).to_le_bytes();
+
+        assert_eq!(&[3u8, 0, 0, 0], &result[..4]);
+        assert_eq!(&crc, &result[4..8]);
+        assert_eq!(&[1u8, 2u8, 3u8], &result[8..11]);
+        assert_eq!(&[0u8, 0, 0, 0], &result[11..]);
+    }
+
+    #[test]
+    fn test_sequence_numbers_are_assigned() {
+        let mut builder = PackageBuilder::new(RecordFormat::Crc32cWithSequence, 5);
+        builder.add_payload(&[1u8, 2u8, 3u8]).unwrap();
+        builder.add_payload(&[4u8]).unwrap();
+
+        assert_eq!(5..7, builder.get_seq_range());
+
+        let result = builder.get_result();
+
+        assert_eq!(&5u64.to_le_bytes(), &result[8..16]);
+        assert_eq!(&[1u8, 2u8, 3u8], &result[16..19]);
+        assert_eq!(&6u64.to_le_bytes(), &result[27..35]);
+        assert_eq!(&[4u8], &result[35..36]);
+    }
+
+    #[test]
+    fn test_page_prefix_goes_first() {
+        let mut builder =
+            PackageBuilder::new(RecordFormat::Legacy, 1).with_page_prefix(&[9u8, 9u8]);
+        builder.add_payload(&[1u8]).unwrap();
+
+        assert_eq!(2, builder.get_page_prefix_size());
+        assert_eq!(5, builder.get_records_size());
+        assert_eq!(1, builder.get_payloads_size());
+
+        assert_eq!(
+            vec![9u8, 9u8, 1u8, 0, 0, 0, 1u8, 0, 0, 0, 0],
+            builder.get_result()
+        );
+    }
+}
@@ -1,7 +1,10 @@
+use bytes::Bytes;
 use my_azure_page_blob::MyPageBlob;
 use my_azure_storage_sdk::page_blob::consts::BLOB_PAGE_SIZE;
 use my_azure_storage_sdk::AzureStorageError;
 
+use crate::{AppendPageBlobSettings, PageBlobMetrics, RetryPolicy};
+
 use super::read_cache::ReadCache;
 
 pub struct PageBlobSequenceReader<TPageBlob: MyPageBlob> {
@@ -11,6 +14,8 @@ pub struct PageBlobSequenceReader<TPageBlob: MyPageBlob> {
     pub blob_size: Option<usize>,
     pub capacity_in_pages: usize,
     pub blob_size_in_pages: usize,
+    pub retry_policy: RetryPolicy,
+    pub metrics: PageBlobMetrics,
 }
 
 impl<TPageBlob: MyPageBlob> PageBlobSequenceReader<TPageBlob> {
@@ -22,6 +27,46 @@ impl<TPageBlob: MyPageBlob> PageBlobSequenceReader<TPageBlob> {
             read_cache: ReadCache::new(BLOB_PAGE_SIZE),
             blob_size: None,
             blob_size_in_pages: 0,
+            retry_policy: RetryPolicy::default(),
+            metrics: PageBlobMetrics::disabled(),
+        }
+    }
+
+    pub fn starting_from_page(
+        page_blob: TPageBlob,
+        settings: &AppendPageBlobSettings,
+        page_no: usize,
+    ) -> Self {
+        Self {
+            page_blob,
+            capacity_in_pages: settings.cache_capacity_in_pages,
+            current_page: page_no,
+            read_cache: ReadCache::starting_from_page(BLOB_PAGE_SIZE, page_no),
+            blob_size: None,
+            blob_size_in_pages: 0,
+            retry_policy: settings.retry_policy,
+            metrics: settings.metrics.clone(),
+        }
+    }
+
+    pub fn starting_from_position(
+        page_blob: TPageBlob,
+        settings: &AppendPageBlobSettings,
+        position: usize,
+        first_page: Vec<u8>,
+    ) -> Self {
+        let page_no = super::utils::get_page_no_from_page_blob_position(position, BLOB_PAGE_SIZE);
+        let pages_in_first_buffer = first_page.len() / BLOB_PAGE_SIZE;
+
+        Self {
+            page_blob,
+            capacity_in_pages: settings.cache_capacity_in_pages,
+            current_page: page_no + pages_in_first_buffer,
+            read_cache: ReadCache::starting_from_position(BLOB_PAGE_SIZE, position, first_page),
+            blob_size: None,
+            blob_size_in_pages: 0,
+            retry_policy: settings.retry_policy,
+            metrics: settings.metrics.clone(),
         }
     }
 
@@ -29,9 +74,12 @@ impl<TPageBlob: MyPageBlob> PageBlobSequenceReader<TPageBlob> {
         loop {
             return match self.blob_size {
                 None => {
-                    self.blob_size_in_pages =
-                        crate::with_retries::get_available_pages_amount(&mut self.page_blob)
-                            .await?;
+                    self.blob_size_in_pages = crate::with_retries::get_available_pages_amount(
+                        &mut self.page_blob,
+                        &self.retry_policy,
+                        &self.metrics,
+                    )
+                    .await?;
 
                     let blob_size = self.blob_size_in_pages * BLOB_PAGE_SIZE;
                     self.blob_size = Some(blob_size);
@@ -46,6 +94,13 @@ impl<TPageBlob: MyPageBlob> PageBlobSequenceReader<TPageBlob> {
         self.read_cache.read_blob_position
     }
 
+    // first_page is the content of the page which contains the position
+    pub fn reposition(&mut self, position: usize, first_page: Vec<u8>) {
+        let page_no = super::utils::get_page_no_from_page_blob_position(position, BLOB_PAGE_SIZE);
+        self.current_page = page_no + first_page.len() / BLOB_PAGE_SIZE;
+        self.read_cache = ReadCache::starting_from_position(BLOB_PAGE_SIZE, position, first_page);
+    }
+
     pub async fn read(&mut self, out_buffer: &mut [u8]) -> Result<bool, AzureStorageError> {
         let blob_size = self.get_blob_size().await?;
 
@@ -57,19 +112,7 @@ impl<TPageBlob: MyPageBlob> PageBlobSequenceReader<TPageBlob> {
 
         loop {
             if self.read_cache.available_to_read_size() == 0 {
-                let pages_to_download =
-                    if self.current_page + self.capacity_in_pages > self.blob_size_in_pages {
-                        self.blob_size_in_pages - self.current_page
-                    } else {
-                        self.capacity_in_pages
-                    };
-                let buf = self
-                    .page_blob
-                    .get(self.current_page, pages_to_download)
-                    .await?;
-
-                self.read_cache.upload(buf);
-                self.current_page += pages_to_download;
+                self.download_next_pages().await?;
             }
 
             let copied = self.read_cache.copy_to(&mut out_buffer[out_position..]);
@@ -81,6 +124,56 @@ impl<TPageBlob: MyPageBlob> PageBlobSequenceReader<TPageBlob> {
             }
         }
     }
+
+    // Data which is inside one downloaded buffer is returned as a slice of it.
+    // Only data crossing the end of the buffer is copied
+    pub async fn read_bytes(&mut self, size: usize) -> Result<Option<Bytes>, AzureStorageError> {
+        let blob_size = self.get_blob_size().await?;
+
+        if self.read_cache.read_blob_position + size >= blob_size {
+            return Ok(None);
+        }
+
+        if self.read_cache.available_to_read_size() == 0 {
+            self.download_next_pages().await?;
+        }
+
+        if let Some(result) = self.read_cache.read_bytes(size) {
+            return Ok(Some(result));
+        }
+
+        let mut result = vec![0u8; size];
+
+        if !self.read(&mut result).await? {
+            return Ok(None);
+        }
+
+        Ok(Some(Bytes::from(result)))
+    }
+
+    async fn download_next_pages(&mut self) -> Result<(), AzureStorageError> {
+        let pages_to_download =
+            if self.current_page + self.capacity_in_pages > self.blob_size_in_pages {
+                self.blob_size_in_pages - self.current_page
+            } else {
+                self.capacity_in_pages
+            };
+        let buf = crate::with_retries::read_pages(
+            &mut self.page_blob,
+            self.current_page,
+            pages_to_download,
+            &self.retry_policy,
+            &self.metrics,
+        )
+        .await?;
+
+        self.metrics.pages_downloaded(pages_to_download);
+
+        self.read_cache.upload(buf);
+        self.current_page += pages_to_download;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -88,7 +181,7 @@ mod tests {
 
     use my_azure_page_blob::{MyPageBlob, MyPageBlobMock};
 
-    use crate::read_write::PackageBuilder;
+    use crate::{read_write::PackageBuilder, RecordFormat};
 
     use super::*;
 
@@ -115,9 +208,9 @@ mod tests {
 
         page_blob.create_if_not_exists(0).await.unwrap();
 
-        let mut builder = PackageBuilder::new();
-        builder.add_payload(&[1u8, 1u8, 1u8]);
-        builder.add_payload(&[2u8, 2u8, 2u8, 2u8]);
+        let mut builder = PackageBuilder::new(RecordFormat::Legacy, 1);
+        builder.add_payload(&[1u8, 1u8, 1u8]).unwrap();
+        builder.add_payload(&[2u8, 2u8, 2u8, 2u8]).unwrap();
 
         page_blob
             .auto_ressize_and_save_pages(0, 10, builder.get_result(), 1)
@@ -180,8 +273,8 @@ mod tests {
 
         page_blob.create_if_not_exists(0).await.unwrap();
 
-        let mut builder = PackageBuilder::new();
-        builder.add_payload(&[3u8; 508]);
+        let mut builder = PackageBuilder::new(RecordFormat::Legacy, 1);
+        builder.add_payload(&[3u8; 508]).unwrap();
 
         page_blob
             .auto_ressize_and_save_pages(0, 10, builder.get_result(), 1)
@@ -228,8 +321,8 @@ mod tests {
 
         page_blob.create_if_not_exists(0).await.unwrap();
 
-        let mut builder = PackageBuilder::new();
-        builder.add_payload(&[3u8; MSG_SIZE as usize]);
+        let mut builder = PackageBuilder::new(RecordFormat::Legacy, 1);
+        builder.add_payload(&[3u8; MSG_SIZE as usize]).unwrap();
 
         page_blob
             .auto_ressize_and_save_pages(0, 10, builder.get_result(), 1)
@@ -1,7 +1,10 @@
 use my_azure_page_blob::MyPageBlob;
 use my_azure_storage_sdk::{page_blob::consts::BLOB_PAGE_SIZE, AzureStorageError};
 
-use crate::settings::AppendPageBlobSettings;
+use crate::{
+    settings::{AppendPageBlobSettings, RecordFormat},
+    PageBlobMetrics, RetryPolicy,
+};
 
 use super::{PackageBuilder, PageBlobSequenceReader, WriteCache};
 
@@ -10,15 +13,23 @@ pub struct PageBlobSequenceWriter<TPageBlob: MyPageBlob> {
     pub write_cache: WriteCache,
     pub max_pages_to_write: usize,
     blob_autoressize_in_pages: usize,
+    retry_policy: RetryPolicy,
+    metrics: PageBlobMetrics,
 }
 
 impl<TPageBlob: MyPageBlob> PageBlobSequenceWriter<TPageBlob> {
-    pub fn brand_new(page_blob: TPageBlob, settings: &AppendPageBlobSettings) -> Self {
+    pub fn brand_new(
+        page_blob: TPageBlob,
+        settings: &AppendPageBlobSettings,
+        write_position: usize,
+    ) -> Self {
         Self {
             page_blob: page_blob,
             max_pages_to_write: 4000,
             blob_autoressize_in_pages: settings.blob_auto_resize_in_pages,
-            write_cache: WriteCache::new(BLOB_PAGE_SIZE, None, 0),
+            retry_policy: settings.retry_policy,
+            metrics: settings.metrics.clone(),
+            write_cache: WriteCache::new(BLOB_PAGE_SIZE, None, write_position),
         }
     }
 
@@ -32,6 +43,8 @@ impl<TPageBlob: MyPageBlob> PageBlobSequenceWriter<TPageBlob> {
             page_blob: page_blob,
             max_pages_to_write: 4000,
             blob_autoressize_in_pages: settings.blob_auto_resize_in_pages,
+            retry_policy: settings.retry_policy,
+            metrics: settings.metrics.clone(),
             write_cache: WriteCache::new(BLOB_PAGE_SIZE, last_page, pos),
         }
     }
@@ -47,19 +60,28 @@ impl<TPageBlob: MyPageBlob> PageBlobSequenceWriter<TPageBlob> {
             page_blob: reader.page_blob,
             max_pages_to_write: 4000,
             blob_autoressize_in_pages: settings.blob_auto_resize_in_pages,
+            retry_policy: settings.retry_policy,
+            metrics: settings.metrics.clone(),
             write_cache: WriteCache::new(BLOB_PAGE_SIZE, last_page, write_position),
         }
     }
 
+    // Package has to be appended before the next one is started
+    pub fn new_package(&self, record_format: RecordFormat, first_seq: u64) -> PackageBuilder {
+        PackageBuilder::new(record_format, first_seq)
+            .with_page_prefix(self.write_cache.get_last_page_prefix())
+    }
+
     pub async fn append(&mut self, package: PackageBuilder) -> Result<(), AzureStorageError> {
+        debug_assert_eq!(
+            self.write_cache.get_last_page_prefix().len(),
+            package.get_page_prefix_size()
+        );
+
         let payload_to_write = package.get_result();
 
         self.write_cache.start_increasing_blob(&payload_to_write);
 
-        let payload_to_write = self
-            .write_cache
-            .concat_with_current_cache(&payload_to_write);
-
         let page_no = super::utils::get_page_no_from_page_blob_position(
             self.write_cache.write_position,
             BLOB_PAGE_SIZE,
@@ -71,6 +93,8 @@ impl<TPageBlob: MyPageBlob> PageBlobSequenceWriter<TPageBlob> {
             self.max_pages_to_write,
             self.blob_autoressize_in_pages,
             payload_to_write,
+            &self.retry_policy,
+            &self.metrics,
         )
         .await?;
 
@@ -78,12 +102,31 @@ impl<TPageBlob: MyPageBlob> PageBlobSequenceWriter<TPageBlob> {
 
         Ok(())
     }
+
+    // Blob ends with the page of the end marker. Records left after it could make
+    // an old checkpoint look like the end of data
+    pub async fn drop_pages_after_end_marker(&mut self) -> Result<(), AzureStorageError> {
+        let pages_amount = crate::page_blob_utils::get_pages_amount_by_size(
+            self.write_cache.write_position + crate::read_write::utils::END_MARKER.len(),
+            BLOB_PAGE_SIZE,
+        );
+
+        crate::with_retries::resize_page_blob(
+            &mut self.page_blob,
+            pages_amount,
+            &self.retry_policy,
+            &self.metrics,
+        )
+        .await
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use my_azure_page_blob::MyPageBlobMock;
 
+    use crate::RecordFormat;
+
     use super::*;
 
     #[tokio::test]
@@ -101,14 +144,16 @@ mod tests {
             cache_capacity_in_pages: 1,
             max_pages_to_write_single_round_trip: 4000,
             max_payload_size_protection: 1,
+            record_format: RecordFormat::Legacy,
+            ..Default::default()
         };
 
         let mut seq_writer = PageBlobSequenceWriter::from_reading(reader, &settings);
 
-        let mut package_builder = PackageBuilder::new();
+        let mut package_builder = PackageBuilder::new(RecordFormat::Legacy, 1);
 
-        package_builder.add_payload(&[1u8, 1u8, 1u8]);
-        package_builder.add_payload(&[2u8, 2u8, 2u8, 2u8]);
+        package_builder.add_payload(&[1u8, 1u8, 1u8]).unwrap();
+        package_builder.add_payload(&[2u8, 2u8, 2u8, 2u8]).unwrap();
 
         seq_writer.append(package_builder).await.unwrap();
 
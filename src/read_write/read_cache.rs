@@ -1,6 +1,8 @@
+use bytes::Bytes;
+
 pub struct ReadCache {
-    buffer: Option<Vec<u8>>,
-    prev_buffer_last_page: Option<Vec<u8>>,
+    buffer: Option<Bytes>,
+    prev_buffer_last_page: Option<Bytes>,
     read_position: usize,
     page_size: usize,
     pub read_blob_position: usize,
@@ -22,6 +24,33 @@ impl ReadCache {
         result
     }
 
+    pub fn starting_from_page(page_size: usize, page_no: usize) -> Self {
+        Self {
+            buffer: None,
+            prev_buffer_last_page: None,
+            read_position: 0,
+            read_blob_position: page_no * page_size,
+            page_size,
+            first_page_no: page_no,
+            pages_in_buffer: 0,
+        }
+    }
+
+    pub fn starting_from_position(page_size: usize, position: usize, first_page: Vec<u8>) -> Self {
+        let page_no = super::utils::get_page_no_from_page_blob_position(position, page_size);
+
+        let mut result = Self::starting_from_page(page_size, page_no);
+        result.upload(first_page);
+
+        let position_within_page = super::utils::get_position_within_page(position, page_size);
+
+        if position_within_page > 0 {
+            result.advance_position(position_within_page);
+        }
+
+        result
+    }
+
     pub fn get_page_from_buffer(&self, negative_offset: usize) -> &[u8] {
         let buffer = self.buffer.as_ref().unwrap();
 
@@ -99,7 +128,7 @@ impl ReadCache {
         self.first_page_no += self.pages_in_buffer;
         self.pages_in_buffer = buffer.len() / self.page_size;
 
-        self.buffer = Some(buffer);
+        self.buffer = Some(Bytes::from(buffer));
     }
 
     #[inline]
@@ -113,14 +142,35 @@ impl ReadCache {
         self.read_blob_position += size;
         self.read_position += size;
         if self.read_position == buffer_size {
-            self.prev_buffer_last_page =
-                Some(self.buffer.as_ref().unwrap()[buffer_size - self.page_size..].to_vec());
+            self.prev_buffer_last_page = Some(
+                self.buffer
+                    .as_ref()
+                    .unwrap()
+                    .slice(buffer_size - self.page_size..),
+            );
 
             self.buffer = None;
             self.read_position = 0;
         }
     }
 
+    // Slice shares the downloaded buffer, so nothing is copied. None if the data crosses the buffer end
+    pub fn read_bytes(&mut self, size: usize) -> Option<Bytes> {
+        if size == 0 || self.available_to_read_size() < size {
+            return None;
+        }
+
+        let result = self
+            .buffer
+            .as_ref()
+            .unwrap()
+            .slice(self.read_position..self.read_position + size);
+
+        self.advance_position(size);
+
+        Some(result)
+    }
+
     pub fn copy_to(&mut self, data: &mut [u8]) -> usize {
         if self.buffer.is_none() {
             panic!("We can not read from Empty Buffer");
@@ -151,6 +201,26 @@ impl ReadCache {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_read_bytes_within_buffer() {
+        let mut buffer = ReadCache::new(8);
+
+        buffer.upload(vec![0u8, 1u8, 2u8, 3u8, 4u8, 5u8, 6u8, 7u8]);
+
+        assert_eq!(&[0u8, 1u8, 2u8][..], &buffer.read_bytes(3).unwrap()[..]);
+        assert_eq!(3, buffer.read_blob_position);
+
+        // Crosses the end of the buffer
+        assert!(buffer.read_bytes(6).is_none());
+        assert_eq!(3, buffer.read_blob_position);
+
+        assert_eq!(
+            &[3u8, 4u8, 5u8, 6u8, 7u8][..],
+            &buffer.read_bytes(5).unwrap()[..]
+        );
+        assert_eq!(0, buffer.available_to_read_size());
+    }
+
     #[test]
     fn test_if_we_have_enough_to_copy() {
         let mut buffer = ReadCache::new(8);
@@ -246,6 +316,25 @@ mod tests {
         assert_eq!(buffer.read_blob_position, 8);
     }
 
+    #[test]
+    fn test_starting_from_position() {
+        let mut read_cache = ReadCache::starting_from_position(4, 6, vec![4u8, 5u8, 6u8, 7u8]);
+
+        assert_eq!(6, read_cache.read_blob_position);
+        assert_eq!(2, read_cache.available_to_read_size());
+
+        let (pos, remaining) = read_cache.get_last_page_remaining_content(0);
+
+        assert_eq!(6, pos);
+        assert_eq!(vec![4u8, 5u8], remaining.unwrap());
+
+        let mut dest = [0u8; 2];
+        read_cache.copy_to(&mut dest);
+
+        assert_eq!([6u8, 7u8], dest);
+        assert_eq!(8, read_cache.read_blob_position);
+    }
+
     #[test]
     fn test_remaining_conten_on_previous_payload() {
         let mut reade_cache = ReadCache::new(4);
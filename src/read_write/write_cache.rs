@@ -17,20 +17,21 @@ impl WriteCache {
         }
     }
 
-    pub fn concat_with_current_cache(&self, payload: &[u8]) -> Vec<u8> {
-        let mut result = Vec::new();
-
+    // Part of the last page which is already written. Next package is written starting from it
+    pub fn get_last_page_prefix(&self) -> &[u8] {
         let position_within_page =
             super::utils::get_position_within_page(self.write_position, self.page_size);
 
-        if position_within_page > 0 {
-            if let Some(last_page) = &self.last_page {
-                result.extend(&last_page[..position_within_page])
-            }
+        match &self.last_page {
+            Some(last_page) if position_within_page > 0 => &last_page[..position_within_page],
+            _ => &[],
         }
+    }
 
+    #[cfg(test)]
+    pub fn concat_with_current_cache(&self, payload: &[u8]) -> Vec<u8> {
+        let mut result = self.get_last_page_prefix().to_vec();
         result.extend(payload);
-
         result
     }
 
@@ -0,0 +1,63 @@
+// High bits of the record size say how the payload is stored:
+// [31..30] compression codec, [29] payload is encrypted, [28..0] size of the stored payload.
+// They are taken into account only if the blob header has FLAG_COMPRESSION or FLAG_ENCRYPTION.
+// Records written before such a flag was set have these bits 0, so they stay readable
+const CODEC_SHIFT: u32 = 30;
+const ENCRYPTED_BIT: u32 = 1 << 29;
+const SIZE_MASK: u32 = ENCRYPTED_BIT - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordSizeField {
+    pub codec: u32,
+    pub encrypted: bool,
+    pub size: u32,
+}
+
+impl RecordSizeField {
+    // Stored payload has to fit below the high bits
+    pub const MAX_SIZE: u32 = SIZE_MASK;
+
+    pub fn plain(size: u32) -> Self {
+        Self {
+            codec: crate::compression::CODEC_NONE,
+            encrypted: false,
+            size,
+        }
+    }
+
+    pub fn parse(src: u32) -> Self {
+        Self {
+            codec: src >> CODEC_SHIFT,
+            encrypted: src & ENCRYPTED_BIT == ENCRYPTED_BIT,
+            size: src & SIZE_MASK,
+        }
+    }
+
+    pub fn serialize(&self) -> u32 {
+        let encrypted = if self.encrypted { ENCRYPTED_BIT } else { 0 };
+        (self.codec << CODEC_SHIFT) | encrypted | self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::{CODEC_NONE, CODEC_ZSTD};
+
+    #[test]
+    fn test_serialize_parse() {
+        let field = RecordSizeField {
+            codec: CODEC_ZSTD,
+            encrypted: true,
+            size: 12345,
+        };
+
+        assert_eq!(field, RecordSizeField::parse(field.serialize()));
+
+        // Records written without compression and encryption
+        let field = RecordSizeField::parse(12345);
+        assert_eq!(CODEC_NONE, field.codec);
+        assert!(!field.encrypted);
+        assert_eq!(12345, field.size);
+    }
+}
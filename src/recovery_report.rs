@@ -0,0 +1,29 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DroppedRange {
+    pub from_position: usize,
+    pub to_position: usize,
+    // Sequence number of the last record before the dropped range
+    pub last_good_seq: u64,
+    pub reason: String,
+}
+
+impl DroppedRange {
+    pub fn get_size(&self) -> usize {
+        self.to_position - self.from_position
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    pub dropped: Vec<DroppedRange>,
+}
+
+impl RecoveryReport {
+    pub fn is_empty(&self) -> bool {
+        self.dropped.is_empty()
+    }
+
+    pub fn get_dropped_bytes(&self) -> usize {
+        self.dropped.iter().map(|range| range.get_size()).sum()
+    }
+}
@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use my_azure_storage_sdk::AzureStorageError;
+
+#[derive(Clone, Copy)]
+pub struct RetryPolicy {
+    // None - retry until the deadline or forever if there is no deadline
+    pub max_attempts: Option<usize>,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub backoff_multiplier: u32,
+    // Delay is picked randomly between the half and the full backoff delay
+    pub jitter: bool,
+    // Time since the first attempt after which we give up
+    pub deadline: Option<Duration>,
+    pub is_retryable: fn(&AzureStorageError) -> bool,
+}
+
+impl RetryPolicy {
+    pub fn no_retries() -> Self {
+        Self {
+            max_attempts: Some(1),
+            ..Default::default()
+        }
+    }
+
+    // attempt_no starts from 1
+    pub fn get_delay(&self, attempt_no: usize) -> Duration {
+        let mut delay = self.initial_delay;
+
+        for _ in 1..attempt_no {
+            delay = delay.saturating_mul(self.backoff_multiplier);
+
+            if delay >= self.max_delay {
+                delay = self.max_delay;
+                break;
+            }
+        }
+
+        if delay > self.max_delay {
+            delay = self.max_delay;
+        }
+
+        if self.jitter {
+            let half = delay / 2;
+            let random = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.subsec_nanos())
+                .unwrap_or(0);
+
+            return half + half.mul_f64(random as f64 / 1_000_000_000.0);
+        }
+
+        delay
+    }
+
+    // Returns the delay before the next attempt or None if we have to give up
+    pub fn get_delay_before_retry(
+        &self,
+        err: &AzureStorageError,
+        attempt_no: usize,
+        elapsed: Duration,
+    ) -> Option<Duration> {
+        if !(self.is_retryable)(err) {
+            return None;
+        }
+
+        if let Some(max_attempts) = self.max_attempts {
+            if attempt_no >= max_attempts {
+                return None;
+            }
+        }
+
+        let delay = self.get_delay(attempt_no);
+
+        if let Some(deadline) = self.deadline {
+            if elapsed + delay > deadline {
+                return None;
+            }
+        }
+
+        Some(delay)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: None,
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+            backoff_multiplier: 2,
+            jitter: true,
+            deadline: None,
+            is_retryable: is_transport_error,
+        }
+    }
+}
+
+pub fn is_transport_error(err: &AzureStorageError) -> bool {
+    matches!(err, AzureStorageError::HyperError { .. })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exponential_backoff() {
+        let policy = RetryPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            jitter: false,
+            ..Default::default()
+        };
+
+        assert_eq!(Duration::from_millis(100), policy.get_delay(1));
+        assert_eq!(Duration::from_millis(200), policy.get_delay(2));
+        assert_eq!(Duration::from_millis(800), policy.get_delay(4));
+        assert_eq!(Duration::from_millis(1000), policy.get_delay(5));
+        assert_eq!(Duration::from_millis(1000), policy.get_delay(100));
+    }
+
+    #[test]
+    fn test_jitter_keeps_delay_within_bounds() {
+        let policy = RetryPolicy {
+            initial_delay: Duration::from_millis(100),
+            ..Default::default()
+        };
+
+        let delay = policy.get_delay(1);
+        assert!(delay >= Duration::from_millis(50));
+        assert!(delay <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_give_up() {
+        let policy = RetryPolicy {
+            max_attempts: Some(3),
+            deadline: Some(Duration::from_secs(5)),
+            initial_delay: Duration::from_secs(1),
+            jitter: false,
+            is_retryable: |err| matches!(err, AzureStorageError::UnknownError { .. }),
+            ..Default::default()
+        };
+
+        let err = AzureStorageError::UnknownError {
+            msg: "Timeout".to_string(),
+        };
+
+        assert!(policy
+            .get_delay_before_retry(&err, 1, Duration::ZERO)
+            .is_some());
+        assert!(policy
+            .get_delay_before_retry(&err, 3, Duration::ZERO)
+            .is_none());
+        assert!(policy
+            .get_delay_before_retry(&err, 1, Duration::from_millis(4500))
+            .is_none());
+        assert!(policy
+            .get_delay_before_retry(&AzureStorageError::BlobNotFound, 1, Duration::ZERO)
+            .is_none());
+    }
+}
@@ -0,0 +1,9 @@
+mod retention_policy;
+mod segment_settings;
+mod segment_storage;
+mod segmented_page_blob_append;
+
+pub use retention_policy::{RetentionPolicy, SegmentArchiver};
+pub use segment_settings::SegmentSettings;
+pub use segment_storage::{FileSegmentStorage, SegmentStorage};
+pub use segmented_page_blob_append::{SegmentInfo, SegmentedPageBlobAppend};
@@ -0,0 +1,26 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use my_azure_page_blob::MyPageBlob;
+use my_azure_storage_sdk::AzureStorageError;
+
+use super::SegmentInfo;
+
+// Oldest segments are removed while any of the limits is exceeded. None - no limit.
+// Segment is older than max_segment_age when the next segment was created earlier than that
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetentionPolicy {
+    pub max_total_size_in_bytes: Option<usize>,
+    pub max_segments: Option<usize>,
+    pub max_segment_age: Option<Duration>,
+}
+
+// Called before the segment is deleted. Segment is kept if archiving fails
+#[async_trait]
+pub trait SegmentArchiver<TMyPageBlob: MyPageBlob>: Send + Sync {
+    async fn archive(
+        &self,
+        segment: &SegmentInfo,
+        page_blob: &mut TMyPageBlob,
+    ) -> Result<(), AzureStorageError>;
+}
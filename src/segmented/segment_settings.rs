@@ -0,0 +1,10 @@
+use std::time::Duration;
+
+// New segment is started before the append which would exceed any of the limits. None - no limit.
+// Size of the append is estimated before compression. Age limit needs a record format with the header
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SegmentSettings {
+    pub max_segment_size_in_bytes: Option<usize>,
+    pub max_records_per_segment: Option<usize>,
+    pub max_segment_age: Option<Duration>,
+}
@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use my_azure_page_blob::MyPageBlob;
+use my_azure_storage_sdk::AzureStorageError;
+
+use crate::FilePageBlob;
+
+// Segments are blobs of the same container. MyPageBlob works with a single blob,
+// so listing the container is up to the storage
+#[async_trait]
+pub trait SegmentStorage<TMyPageBlob: MyPageBlob>: Send + Sync {
+    fn get_page_blob(&self, blob_name: &str) -> TMyPageBlob;
+    async fn get_blob_names(&self) -> Result<Vec<String>, AzureStorageError>;
+}
+
+pub struct FileSegmentStorage {
+    root_folder: PathBuf,
+    container_name: String,
+}
+
+impl FileSegmentStorage {
+    pub fn new(root_folder: impl Into<PathBuf>, container_name: &str) -> Self {
+        Self {
+            root_folder: root_folder.into(),
+            container_name: container_name.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl SegmentStorage<FilePageBlob> for FileSegmentStorage {
+    fn get_page_blob(&self, blob_name: &str) -> FilePageBlob {
+        FilePageBlob::new(self.root_folder.clone(), &self.container_name, blob_name)
+    }
+
+    async fn get_blob_names(&self) -> Result<Vec<String>, AzureStorageError> {
+        let container_path = self.root_folder.join(&self.container_name);
+
+        let mut read_dir = match tokio::fs::read_dir(container_path).await {
+            Ok(read_dir) => read_dir,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(crate::file_page_blob::io_error(err)),
+        };
+
+        let mut result = Vec::new();
+
+        while let Some(entry) = read_dir
+            .next_entry()
+            .await
+            .map_err(crate::file_page_blob::io_error)?
+        {
+            let file_type = entry
+                .file_type()
+                .await
+                .map_err(crate::file_page_blob::io_error)?;
+
+            if file_type.is_file() {
+                result.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,876 @@
+use std::{ops::Range, time::Duration};
+
+use my_azure_page_blob::MyPageBlob;
+use my_azure_storage_sdk::page_blob::consts::BLOB_PAGE_SIZE;
+use rust_extensions::date_time::DateTimeAsMicroseconds;
+
+use crate::{AppendPageBlobSettings, BlobHeader, PageBlobAppend, PageBlobAppendError};
+
+use super::{RetentionPolicy, SegmentArchiver, SegmentSettings, SegmentStorage};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentInfo {
+    pub segment_no: u64,
+    pub blob_name: String,
+}
+
+// Log is stored as blobs <name>-0000000000, <name>-0000000001, ...
+// Segments are read in order. Records are appended to the last one.
+// Seqs continue across segments, so the record format has to store them
+pub struct SegmentedPageBlobAppend<
+    TMyPageBlob: MyPageBlob,
+    TSegmentStorage: SegmentStorage<TMyPageBlob>,
+> {
+    storage: TSegmentStorage,
+    name: String,
+    settings: AppendPageBlobSettings,
+    segment_settings: SegmentSettings,
+    segments: Option<Vec<u64>>,
+    current_index: usize,
+    current: Option<PageBlobAppend<TMyPageBlob>>,
+    writing: bool,
+    last_seq: u64,
+    records_in_last_segment: usize,
+    last_segment_created: DateTimeAsMicroseconds,
+    retention_policy: Option<RetentionPolicy>,
+    archiver: Option<Box<dyn SegmentArchiver<TMyPageBlob>>>,
+    oldest_segment_in_use: Option<u64>,
+}
+
+impl<TMyPageBlob: MyPageBlob, TSegmentStorage: SegmentStorage<TMyPageBlob>>
+    SegmentedPageBlobAppend<TMyPageBlob, TSegmentStorage>
+{
+    pub fn new(
+        storage: TSegmentStorage,
+        name: &str,
+        settings: AppendPageBlobSettings,
+        segment_settings: SegmentSettings,
+    ) -> Result<Self, PageBlobAppendError> {
+        if !settings.record_format.has_sequence_number() {
+            return Err(PageBlobAppendError::UnsupportedFormat(format!(
+                "Segmented log {} requires a record format with sequence numbers. Format is {:?}",
+                name, settings.record_format
+            )));
+        }
+
+        Ok(Self {
+            storage,
+            name: name.to_string(),
+            settings,
+            segment_settings,
+            segments: None,
+            current_index: 0,
+            current: None,
+            writing: false,
+            last_seq: 0,
+            records_in_last_segment: 0,
+            last_segment_created: DateTimeAsMicroseconds::now(),
+            retention_policy: None,
+            archiver: None,
+            oldest_segment_in_use: None,
+        })
+    }
+
+    // Retention is applied after every roll over and can be applied manually with apply_retention
+    pub fn set_retention_policy(
+        &mut self,
+        retention_policy: RetentionPolicy,
+        archiver: Option<Box<dyn SegmentArchiver<TMyPageBlob>>>,
+    ) {
+        self.retention_policy = Some(retention_policy);
+        self.archiver = archiver;
+    }
+
+    // Segments starting from this one are never removed. Lets other readers protect their segments
+    pub fn set_oldest_segment_in_use(&mut self, segment_no: Option<u64>) {
+        self.oldest_segment_in_use = segment_no;
+    }
+
+    pub fn get_segment_blob_name(name: &str, segment_no: u64) -> String {
+        format!("{}-{:010}", name, segment_no)
+    }
+
+    fn parse_segment_no(&self, blob_name: &str) -> Option<u64> {
+        let segment_no = blob_name.strip_prefix(&self.name)?.strip_prefix('-')?;
+
+        if segment_no.len() != 10 || !segment_no.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        segment_no.parse().ok()
+    }
+
+    // Empty until the first read or write
+    pub fn get_segments(&self) -> Vec<SegmentInfo> {
+        match &self.segments {
+            Some(segments) => segments
+                .iter()
+                .map(|segment_no| SegmentInfo {
+                    segment_no: *segment_no,
+                    blob_name: Self::get_segment_blob_name(&self.name, *segment_no),
+                })
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    // Segment which is being read or written
+    pub fn get_current_segment_no(&self) -> Option<u64> {
+        let segments = self.segments.as_ref()?;
+        segments.get(self.current_index).copied()
+    }
+
+    pub async fn get_next_payload(
+        &mut self,
+    ) -> Result<Option<(u64, Vec<u8>)>, PageBlobAppendError> {
+        self.init_if_required().await?;
+
+        loop {
+            let is_last_segment = self.is_last_segment();
+
+            let result = self.current.as_mut().unwrap().get_next_payload().await?;
+
+            match result {
+                Some(result) => {
+                    if is_last_segment {
+                        self.records_in_last_segment += 1;
+                    }
+
+                    self.last_seq = result.0;
+
+                    return Ok(Some(result));
+                }
+                None => {
+                    if is_last_segment {
+                        self.continue_numbering()?;
+                        self.writing = true;
+                        return Ok(None);
+                    }
+
+                    self.current_index += 1;
+                    self.open_current_segment().await?;
+                }
+            }
+        }
+    }
+
+    pub async fn append_and_write(
+        &mut self,
+        payloads: &Vec<Vec<u8>>,
+    ) -> Result<Range<u64>, PageBlobAppendError> {
+        self.init_if_required().await?;
+
+        if !self.writing {
+            return Err(PageBlobAppendError::NotInitialized);
+        }
+
+        if self.should_roll(payloads) {
+            self.roll().await?;
+
+            if let Err(err) = self.apply_retention().await {
+                crate::diagnostics::retention_failed(&self.name, &err);
+            }
+        }
+
+        let seq_range = self
+            .current
+            .as_mut()
+            .unwrap()
+            .append_and_write(payloads)
+            .await?;
+
+        self.records_in_last_segment += payloads.len();
+
+        Ok(seq_range)
+    }
+
+    // Returns removed segments. The segment being written and the segments
+    // which are not read yet are never removed
+    pub async fn apply_retention(&mut self) -> Result<Vec<SegmentInfo>, PageBlobAppendError> {
+        self.init_if_required().await?;
+
+        let mut removed = Vec::new();
+
+        let retention_policy = match self.retention_policy {
+            Some(retention_policy) => retention_policy,
+            None => return Ok(removed),
+        };
+
+        let mut sizes = match retention_policy.max_total_size_in_bytes {
+            Some(_) => self.get_segment_sizes().await?,
+            None => vec![],
+        };
+
+        while self.can_remove_first_segment()
+            && self
+                .is_first_segment_expired(&retention_policy, &sizes)
+                .await?
+        {
+            removed.push(self.remove_first_segment().await?);
+
+            if !sizes.is_empty() {
+                sizes.remove(0);
+            }
+        }
+
+        Ok(removed)
+    }
+
+    fn can_remove_first_segment(&self) -> bool {
+        if self.current_index == 0 {
+            return false;
+        }
+
+        match self.oldest_segment_in_use {
+            Some(oldest_segment_in_use) => {
+                self.segments.as_ref().unwrap()[0] < oldest_segment_in_use
+            }
+            None => true,
+        }
+    }
+
+    async fn is_first_segment_expired(
+        &self,
+        retention_policy: &RetentionPolicy,
+        sizes: &[usize],
+    ) -> Result<bool, PageBlobAppendError> {
+        let segments = self.segments.as_ref().unwrap();
+
+        if let Some(max_segments) = retention_policy.max_segments {
+            if segments.len() > max_segments {
+                return Ok(true);
+            }
+        }
+
+        if let Some(max_total_size) = retention_policy.max_total_size_in_bytes {
+            if sizes.iter().sum::<usize>() > max_total_size {
+                return Ok(true);
+            }
+        }
+
+        if let Some(max_age) = retention_policy.max_segment_age {
+            let next_segment_created = if segments.len() == 2 {
+                self.last_segment_created
+            } else {
+                let blob_name = Self::get_segment_blob_name(&self.name, segments[1]);
+                let mut page_blob = self.storage.get_page_blob(&blob_name);
+                self.get_segment_created(&mut page_blob).await?
+            };
+
+            if get_age(next_segment_created) >= max_age {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    async fn remove_first_segment(&mut self) -> Result<SegmentInfo, PageBlobAppendError> {
+        let segment_no = self.segments.as_ref().unwrap()[0];
+
+        let segment = SegmentInfo {
+            segment_no,
+            blob_name: Self::get_segment_blob_name(&self.name, segment_no),
+        };
+
+        let mut page_blob = self.storage.get_page_blob(&segment.blob_name);
+
+        if let Some(archiver) = &self.archiver {
+            archiver.archive(&segment, &mut page_blob).await?;
+        }
+
+        crate::with_retries::delete_blob_if_exists(
+            &mut page_blob,
+            &self.settings.retry_policy,
+            &self.settings.metrics,
+        )
+        .await?;
+
+        self.segments.as_mut().unwrap().remove(0);
+        self.current_index -= 1;
+
+        Ok(segment)
+    }
+
+    async fn get_segment_sizes(&self) -> Result<Vec<usize>, PageBlobAppendError> {
+        let mut result = Vec::new();
+
+        for segment_no in self.segments.as_ref().unwrap() {
+            let blob_name = Self::get_segment_blob_name(&self.name, *segment_no);
+            let mut page_blob = self.storage.get_page_blob(&blob_name);
+
+            let pages_amount = crate::with_retries::get_available_pages_amount(
+                &mut page_blob,
+                &self.settings.retry_policy,
+                &self.settings.metrics,
+            )
+            .await?;
+
+            result.push(pages_amount * BLOB_PAGE_SIZE);
+        }
+
+        Ok(result)
+    }
+
+    fn is_last_segment(&self) -> bool {
+        self.current_index + 1 == self.segments.as_ref().unwrap().len()
+    }
+
+    // Segment has at least one append even if it alone exceeds the limits
+    fn should_roll(&self, payloads: &[Vec<u8>]) -> bool {
+        if self.records_in_last_segment == 0 {
+            return false;
+        }
+
+        if let Some(max_records) = self.segment_settings.max_records_per_segment {
+            if self.records_in_last_segment + payloads.len() > max_records {
+                return true;
+            }
+        }
+
+        if let Some(max_size) = self.segment_settings.max_segment_size_in_bytes {
+            let record_header_size = self.settings.record_format.get_record_header_size();
+            let append_size: usize = payloads
+                .iter()
+                .map(|payload| record_header_size + payload.len())
+                .sum();
+
+            if self.current.as_ref().unwrap().get_blob_position() + append_size > max_size {
+                return true;
+            }
+        }
+
+        if let Some(max_age) = self.segment_settings.max_segment_age {
+            if get_age(self.last_segment_created) >= max_age {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    // Last segment is empty if the process stopped right after the roll over.
+    // Its numbering continues the records of the previous segments
+    fn continue_numbering(&mut self) -> Result<(), PageBlobAppendError> {
+        if self.last_seq == 0 {
+            return Ok(());
+        }
+
+        let current = self.current.as_mut().unwrap();
+
+        match current.get_checkpoint() {
+            Some(checkpoint) if checkpoint.last_seq == 0 => current.set_next_seq(self.last_seq + 1),
+            _ => Ok(()),
+        }
+    }
+
+    async fn init_if_required(&mut self) -> Result<(), PageBlobAppendError> {
+        if self.segments.is_some() {
+            return Ok(());
+        }
+
+        let blob_names = self.storage.get_blob_names().await?;
+
+        let mut segments: Vec<u64> = blob_names
+            .iter()
+            .filter_map(|blob_name| self.parse_segment_no(blob_name))
+            .collect();
+
+        segments.sort();
+
+        if segments.is_empty() {
+            self.segments = Some(vec![]);
+            return self.create_segment(0, 1).await;
+        }
+
+        self.segments = Some(segments);
+        self.current_index = 0;
+        self.open_current_segment().await
+    }
+
+    async fn open_current_segment(&mut self) -> Result<(), PageBlobAppendError> {
+        let segment_no = self.segments.as_ref().unwrap()[self.current_index];
+        let blob_name = Self::get_segment_blob_name(&self.name, segment_no);
+
+        let mut page_blob_append = PageBlobAppend::new(
+            self.storage.get_page_blob(&blob_name),
+            self.settings.clone(),
+        );
+
+        if self.is_last_segment() {
+            self.records_in_last_segment = 0;
+            self.last_segment_created = self
+                .get_segment_created(page_blob_append.get_page_blob_mut())
+                .await?;
+        }
+
+        self.current = Some(page_blob_append);
+
+        Ok(())
+    }
+
+    async fn create_segment(
+        &mut self,
+        segment_no: u64,
+        next_seq: u64,
+    ) -> Result<(), PageBlobAppendError> {
+        let blob_name = Self::get_segment_blob_name(&self.name, segment_no);
+
+        let mut page_blob_append = PageBlobAppend::new(
+            self.storage.get_page_blob(&blob_name),
+            self.settings.clone(),
+        );
+
+        page_blob_append.init_blob(None).await?;
+
+        if next_seq > 1 {
+            page_blob_append.set_next_seq(next_seq)?;
+        }
+
+        let segments = self.segments.as_mut().unwrap();
+        segments.push(segment_no);
+        self.current_index = segments.len() - 1;
+
+        self.current = Some(page_blob_append);
+        self.writing = true;
+        self.records_in_last_segment = 0;
+        self.last_segment_created = DateTimeAsMicroseconds::now();
+
+        Ok(())
+    }
+
+    async fn roll(&mut self) -> Result<(), PageBlobAppendError> {
+        let next_seq = match self.current.as_ref().unwrap().get_checkpoint() {
+            Some(checkpoint) => checkpoint.last_seq + 1,
+            None => 1,
+        };
+
+        let segment_no = self.segments.as_ref().unwrap().last().unwrap() + 1;
+
+        self.create_segment(segment_no, next_seq).await
+    }
+
+    // Creation time is taken from the header. Only Legacy segments and the segments
+    // which are not initialized yet have no header
+    async fn get_segment_created(
+        &self,
+        page_blob: &mut TMyPageBlob,
+    ) -> Result<DateTimeAsMicroseconds, PageBlobAppendError> {
+        let pages_amount = crate::with_retries::get_available_pages_amount(
+            page_blob,
+            &self.settings.retry_policy,
+            &self.settings.metrics,
+        )
+        .await?;
+
+        if pages_amount > 0 {
+            let first_page = crate::with_retries::read_pages(
+                page_blob,
+                0,
+                1,
+                &self.settings.retry_policy,
+                &self.settings.metrics,
+            )
+            .await?;
+
+            if BlobHeader::is_header(&first_page) {
+                if let Ok(header) = BlobHeader::deserialize(&first_page) {
+                    return Ok(DateTimeAsMicroseconds::new(
+                        header.created_unix_microseconds,
+                    ));
+                }
+            }
+        }
+
+        Ok(DateTimeAsMicroseconds::now())
+    }
+}
+
+// Clock can go back, so the age is never negative
+fn get_age(created: DateTimeAsMicroseconds) -> Duration {
+    let age = DateTimeAsMicroseconds::now()
+        .unix_microseconds
+        .saturating_sub(created.unix_microseconds);
+
+    Duration::from_micros(age.max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use super::*;
+    use crate::{test_utils::get_settings, FilePageBlob, FileSegmentStorage, RecordFormat};
+
+    fn get_test_root_folder(test_name: &str) -> PathBuf {
+        let result = std::env::temp_dir().join(format!(
+            "page-blob-append-segmented-{}-{}",
+            test_name,
+            std::process::id()
+        ));
+
+        let _ = std::fs::remove_dir_all(&result);
+
+        result
+    }
+
+    fn create_log(
+        root_folder: &PathBuf,
+        segment_settings: SegmentSettings,
+    ) -> SegmentedPageBlobAppend<FilePageBlob, FileSegmentStorage> {
+        SegmentedPageBlobAppend::new(
+            FileSegmentStorage::new(root_folder, "container"),
+            "log",
+            get_settings(RecordFormat::Crc32cWithSequence),
+            segment_settings,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rolls_by_records_count() {
+        let root_folder = get_test_root_folder("records");
+
+        let segment_settings = SegmentSettings {
+            max_records_per_segment: Some(2),
+            ..Default::default()
+        };
+
+        let mut log = create_log(&root_folder, segment_settings);
+        assert!(log.get_next_payload().await.unwrap().is_none());
+
+        for i in 1..=5u8 {
+            log.append_and_write(&vec![vec![i]]).await.unwrap();
+        }
+
+        let segments: Vec<String> = log
+            .get_segments()
+            .into_iter()
+            .map(|segment| segment.blob_name)
+            .collect();
+
+        assert_eq!(
+            vec!["log-0000000000", "log-0000000001", "log-0000000002"],
+            segments
+        );
+
+        let mut log = create_log(&root_folder, segment_settings);
+
+        for i in 1..=5u8 {
+            let (seq, payload) = log.get_next_payload().await.unwrap().unwrap();
+            assert_eq!(i as u64, seq);
+            assert_eq!(vec![i], payload);
+        }
+
+        assert!(log.get_next_payload().await.unwrap().is_none());
+        assert_eq!(Some(2), log.get_current_segment_no());
+
+        // The last segment has one record, so the next one still goes there
+        let seq_range = log.append_and_write(&vec![vec![6u8]]).await.unwrap();
+        assert_eq!(6..7, seq_range);
+        assert_eq!(3, log.get_segments().len());
+
+        let seq_range = log.append_and_write(&vec![vec![7u8]]).await.unwrap();
+        assert_eq!(7..8, seq_range);
+        assert_eq!(4, log.get_segments().len());
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+
+    #[tokio::test]
+    async fn test_rolls_by_size_and_age() {
+        let root_folder = get_test_root_folder("size");
+
+        let segment_settings = SegmentSettings {
+            max_segment_size_in_bytes: Some(1200),
+            ..Default::default()
+        };
+
+        let mut log = create_log(&root_folder, segment_settings);
+        assert!(log.get_next_payload().await.unwrap().is_none());
+
+        // Every record is 16 bytes of the header plus the payload after the header page
+        log.append_and_write(&vec![vec![1u8; 300]]).await.unwrap();
+        log.append_and_write(&vec![vec![2u8; 300]]).await.unwrap();
+        assert_eq!(1, log.get_segments().len());
+
+        // 1144 + 316 exceeds the limit, so the record goes to the next segment
+        log.append_and_write(&vec![vec![3u8; 300]]).await.unwrap();
+        assert_eq!(2, log.get_segments().len());
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+
+        let root_folder = get_test_root_folder("age");
+
+        let segment_settings = SegmentSettings {
+            max_segment_age: Some(Duration::ZERO),
+            ..Default::default()
+        };
+
+        let mut log = create_log(&root_folder, segment_settings);
+        assert!(log.get_next_payload().await.unwrap().is_none());
+
+        log.append_and_write(&vec![vec![1u8]]).await.unwrap();
+        log.append_and_write(&vec![vec![2u8]]).await.unwrap();
+        assert_eq!(2, log.get_segments().len());
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+
+    #[tokio::test]
+    async fn test_batch_is_counted_by_records_limit() {
+        let root_folder = get_test_root_folder("batch");
+
+        let segment_settings = SegmentSettings {
+            max_records_per_segment: Some(3),
+            ..Default::default()
+        };
+
+        let mut log = create_log(&root_folder, segment_settings);
+        assert!(log.get_next_payload().await.unwrap().is_none());
+
+        log.append_and_write(&vec![vec![1u8], vec![2u8]])
+            .await
+            .unwrap();
+        log.append_and_write(&vec![vec![3u8], vec![4u8]])
+            .await
+            .unwrap();
+        assert_eq!(2, log.get_segments().len());
+
+        let mut log = create_log(&root_folder, segment_settings);
+
+        for i in 1..=4u8 {
+            let (seq, payload) = log.get_next_payload().await.unwrap().unwrap();
+            assert_eq!(i as u64, seq);
+            assert_eq!(vec![i], payload);
+        }
+
+        assert!(log.get_next_payload().await.unwrap().is_none());
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+
+    #[tokio::test]
+    async fn test_empty_last_segment_continues_seqs_after_restart() {
+        let root_folder = get_test_root_folder("empty-last");
+
+        let mut log = create_log(&root_folder, SegmentSettings::default());
+        assert!(log.get_next_payload().await.unwrap().is_none());
+        log.append_and_write(&vec![vec![1u8], vec![2u8]])
+            .await
+            .unwrap();
+
+        // Process stopped right after the next segment was created
+        let storage = FileSegmentStorage::new(&root_folder, "container");
+        let mut segment = PageBlobAppend::new(
+            storage.get_page_blob(
+                &SegmentedPageBlobAppend::<FilePageBlob, FileSegmentStorage>::get_segment_blob_name(
+                    "log", 1,
+                ),
+            ),
+            get_settings(RecordFormat::Crc32cWithSequence),
+        );
+        segment.init_blob(None).await.unwrap();
+
+        let mut log = create_log(&root_folder, SegmentSettings::default());
+        assert_eq!(1, log.get_next_payload().await.unwrap().unwrap().0);
+        assert_eq!(2, log.get_next_payload().await.unwrap().unwrap().0);
+        assert!(log.get_next_payload().await.unwrap().is_none());
+        assert_eq!(Some(1), log.get_current_segment_no());
+
+        let seq_range = log.append_and_write(&vec![vec![3u8]]).await.unwrap();
+        assert_eq!(3..4, seq_range);
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+
+    #[test]
+    fn test_formats_without_seqs_are_refused() {
+        let root_folder = get_test_root_folder("no-seqs");
+
+        for record_format in [RecordFormat::Legacy, RecordFormat::Crc32c] {
+            let mut settings = get_settings(RecordFormat::Crc32cWithSequence);
+            settings.record_format = record_format;
+
+            let result = SegmentedPageBlobAppend::<FilePageBlob, FileSegmentStorage>::new(
+                FileSegmentStorage::new(&root_folder, "container"),
+                "log",
+                settings,
+                SegmentSettings::default(),
+            );
+
+            assert!(result.err().unwrap().is_unsupported_format());
+        }
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+
+    #[tokio::test]
+    async fn test_append_before_reading_is_refused() {
+        let root_folder = get_test_root_folder("refused");
+
+        let mut log = create_log(&root_folder, SegmentSettings::default());
+        assert!(log.get_next_payload().await.unwrap().is_none());
+        log.append_and_write(&vec![vec![1u8]]).await.unwrap();
+
+        let mut log = create_log(&root_folder, SegmentSettings::default());
+        let err = log.append_and_write(&vec![vec![2u8]]).await.err().unwrap();
+        assert!(matches!(err, PageBlobAppendError::NotInitialized));
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+
+    struct NamesArchiver {
+        archived: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl SegmentArchiver<FilePageBlob> for NamesArchiver {
+        async fn archive(
+            &self,
+            segment: &SegmentInfo,
+            page_blob: &mut FilePageBlob,
+        ) -> Result<(), my_azure_storage_sdk::AzureStorageError> {
+            assert!(page_blob.download().await.unwrap().len() > 0);
+            self.archived
+                .lock()
+                .unwrap()
+                .push(segment.blob_name.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retention_by_segments_count() {
+        let root_folder = get_test_root_folder("retention-count");
+
+        let segment_settings = SegmentSettings {
+            max_records_per_segment: Some(1),
+            ..Default::default()
+        };
+
+        let archived = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let mut log = create_log(&root_folder, segment_settings);
+        log.set_retention_policy(
+            crate::RetentionPolicy {
+                max_segments: Some(2),
+                ..Default::default()
+            },
+            Some(Box::new(NamesArchiver {
+                archived: archived.clone(),
+            })),
+        );
+
+        assert!(log.get_next_payload().await.unwrap().is_none());
+
+        for i in 1..=4u8 {
+            log.append_and_write(&vec![vec![i]]).await.unwrap();
+        }
+
+        let segments: Vec<u64> = log
+            .get_segments()
+            .into_iter()
+            .map(|segment| segment.segment_no)
+            .collect();
+        assert_eq!(vec![2, 3], segments);
+        assert_eq!(
+            vec!["log-0000000000", "log-0000000001"],
+            *archived.lock().unwrap()
+        );
+
+        let mut log = create_log(&root_folder, segment_settings);
+        assert_eq!(
+            (3, vec![3u8]),
+            log.get_next_payload().await.unwrap().unwrap()
+        );
+        assert_eq!(
+            (4, vec![4u8]),
+            log.get_next_payload().await.unwrap().unwrap()
+        );
+        assert!(log.get_next_payload().await.unwrap().is_none());
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+
+    #[tokio::test]
+    async fn test_retention_keeps_segments_which_are_not_read() {
+        let root_folder = get_test_root_folder("retention-protected");
+
+        let segment_settings = SegmentSettings {
+            max_records_per_segment: Some(1),
+            ..Default::default()
+        };
+
+        let mut log = create_log(&root_folder, segment_settings);
+        assert!(log.get_next_payload().await.unwrap().is_none());
+
+        for i in 1..=3u8 {
+            log.append_and_write(&vec![vec![i]]).await.unwrap();
+        }
+
+        let retention_policy = crate::RetentionPolicy {
+            max_segment_age: Some(Duration::ZERO),
+            ..Default::default()
+        };
+
+        let mut log = create_log(&root_folder, segment_settings);
+        log.set_retention_policy(retention_policy, None);
+
+        assert!(log.apply_retention().await.unwrap().is_empty());
+
+        log.get_next_payload().await.unwrap().unwrap();
+        log.get_next_payload().await.unwrap().unwrap();
+        log.get_next_payload().await.unwrap().unwrap();
+        log.set_oldest_segment_in_use(Some(1));
+
+        let removed = log.apply_retention().await.unwrap();
+        assert_eq!(1, removed.len());
+        assert_eq!(0, removed[0].segment_no);
+
+        log.set_oldest_segment_in_use(None);
+
+        let removed = log.apply_retention().await.unwrap();
+        assert_eq!(1, removed.len());
+        assert_eq!(
+            vec![2],
+            log.get_segments()
+                .iter()
+                .map(|s| s.segment_no)
+                .collect::<Vec<u64>>()
+        );
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+
+    #[tokio::test]
+    async fn test_retention_by_total_size() {
+        let root_folder = get_test_root_folder("retention-size");
+
+        let segment_settings = SegmentSettings {
+            max_records_per_segment: Some(1),
+            ..Default::default()
+        };
+
+        let mut log = create_log(&root_folder, segment_settings);
+
+        // Every segment is the header page plus one page of data
+        log.set_retention_policy(
+            crate::RetentionPolicy {
+                max_total_size_in_bytes: Some(BLOB_PAGE_SIZE * 5),
+                ..Default::default()
+            },
+            None,
+        );
+
+        assert!(log.get_next_payload().await.unwrap().is_none());
+
+        for i in 1..=5u8 {
+            log.append_and_write(&vec![vec![i]]).await.unwrap();
+        }
+
+        assert_eq!(2, log.get_segments().len());
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+}
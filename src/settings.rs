@@ -1,7 +1,103 @@
-#[derive(Clone, Copy)]
+use crate::{
+    blob_header::{FLAG_COMPRESSION, FLAG_ENCRYPTION},
+    Compression, Encryption, PageBlobMetrics, RetryPolicy,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    // [len: u32 LE][payload]
+    Legacy,
+    // [len: u32 LE][crc32c of payload: u32 LE][payload]
+    Crc32c,
+    // [len: u32 LE][crc32c of seq and payload: u32 LE][seq: u64 LE][payload]
+    Crc32cWithSequence,
+}
+
+impl RecordFormat {
+    pub fn has_checksum(&self) -> bool {
+        match self {
+            RecordFormat::Legacy => false,
+            RecordFormat::Crc32c => true,
+            RecordFormat::Crc32cWithSequence => true,
+        }
+    }
+
+    pub fn has_sequence_number(&self) -> bool {
+        match self {
+            RecordFormat::Legacy => false,
+            RecordFormat::Crc32c => false,
+            RecordFormat::Crc32cWithSequence => true,
+        }
+    }
+
+    // Bytes before the payload
+    pub fn get_record_header_size(&self) -> usize {
+        match self {
+            RecordFormat::Legacy => 4,
+            RecordFormat::Crc32c => 8,
+            RecordFormat::Crc32cWithSequence => 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryMode {
+    // Stay in the Corrupted mode until init_blob is called
+    Fail,
+    // Everything starting from the broken position is dropped. Writing resumes there
+    TruncateAtLastGood,
+    // Scan forward for the next record with a valid checksum and continue reading from it.
+    // Falls back to TruncateAtLastGood if there is no valid record. Works as Fail for the Legacy format
+    SkipAndResync,
+}
+
+#[derive(Clone)]
 pub struct AppendPageBlobSettings {
     pub max_payload_size_protection: u32,
     pub blob_auto_resize_in_pages: usize,
     pub cache_capacity_in_pages: usize,
     pub max_pages_to_write_single_round_trip: usize,
+    pub record_format: RecordFormat,
+    pub retry_policy: RetryPolicy,
+    pub metrics: PageBlobMetrics,
+    pub recovery_mode: RecoveryMode,
+    // Needs a record format with the header. Legacy blobs have nowhere to record the codec
+    pub compression: Compression,
+    // Same as compression, needs a record format with the header
+    pub encryption: Option<Encryption>,
+}
+
+impl Default for AppendPageBlobSettings {
+    // Blobs written before the header was introduced are opened only with the Legacy format set explicitly
+    fn default() -> Self {
+        Self {
+            max_payload_size_protection: 1024 * 1024 * 100,
+            blob_auto_resize_in_pages: 1,
+            cache_capacity_in_pages: 8000,
+            max_pages_to_write_single_round_trip: 4000,
+            record_format: RecordFormat::Crc32cWithSequence,
+            retry_policy: RetryPolicy::default(),
+            metrics: PageBlobMetrics::disabled(),
+            recovery_mode: RecoveryMode::Fail,
+            compression: Compression::None,
+            encryption: None,
+        }
+    }
+}
+
+impl AppendPageBlobSettings {
+    // Flags the blob header has to have before records are written with these settings
+    pub fn get_required_header_flags(&self) -> u32 {
+        let mut result = 0;
+
+        if self.compression.is_enabled() {
+            result |= FLAG_COMPRESSION;
+        }
+
+        if self.encryption.is_some() {
+            result |= FLAG_ENCRYPTION;
+        }
+
+        result
+    }
 }
@@ -0,0 +1,435 @@
+use my_azure_page_blob::MyPageBlob;
+use my_azure_storage_sdk::{page_blob::consts::BLOB_PAGE_SIZE, AzureStorageError};
+
+use crate::{AppendPageBlobSettings, PageBlobAppendError, ReadCheckpoint};
+
+pub const SNAPSHOT_MAGIC: [u8; 8] = *b"PBSNAPS2";
+
+// Snapshot blob has two slots. Page i of slot s is page 2 * i + s of the blob,
+// so a slot grows without touching the other one.
+// New snapshot replaces the older slot, so a torn write never damages the newest valid snapshot.
+// Layout of a slot (all numbers are LE):
+// [0..8] magic, [8..16] payload size, [16..24] generation, [24..32] log position, [32..40] last seq,
+// [40..44] crc32c of [0..40] and the payload, [44..] payload.
+// Position is stored together with the payload, so they are either both valid or the snapshot is rejected
+const SNAPSHOT_CONTENT_SIZE: usize = 40;
+const SNAPSHOT_PREFIX_SIZE: usize = SNAPSHOT_CONTENT_SIZE + 4;
+const SLOTS_AMOUNT: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub checkpoint: ReadCheckpoint,
+    pub payload: Vec<u8>,
+}
+
+impl Snapshot {
+    // Snapshot with the bigger generation is the newer one
+    pub fn serialize(&self, generation: u64) -> Vec<u8> {
+        let size = SNAPSHOT_PREFIX_SIZE + self.payload.len();
+        let mut result = Vec::with_capacity(size.div_ceil(BLOB_PAGE_SIZE) * BLOB_PAGE_SIZE);
+
+        result.extend_from_slice(&SNAPSHOT_MAGIC);
+        result.extend_from_slice(&(self.payload.len() as u64).to_le_bytes());
+        result.extend_from_slice(&generation.to_le_bytes());
+        result.extend_from_slice(&(self.checkpoint.blob_position as u64).to_le_bytes());
+        result.extend_from_slice(&self.checkpoint.last_seq.to_le_bytes());
+
+        let crc = crc32c::crc32c_append(crc32c::crc32c(&result), &self.payload);
+        result.extend_from_slice(&crc.to_le_bytes());
+        result.extend_from_slice(&self.payload);
+
+        result.resize(size.div_ceil(BLOB_PAGE_SIZE) * BLOB_PAGE_SIZE, 0);
+
+        result
+    }
+
+    // Returns the size of the whole snapshot. Used to find out how many pages to read.
+    // Size comes from the blob, so a corrupted one is refused instead of being read
+    pub fn get_size(first_page: &[u8], max_payload_size: u32) -> Result<usize, String> {
+        if first_page.len() < SNAPSHOT_PREFIX_SIZE || first_page[..8] != SNAPSHOT_MAGIC {
+            return Err("Slot does not start with the snapshot header".to_string());
+        }
+
+        let payload_size = read_u64(first_page, 8);
+
+        let size = usize::try_from(payload_size)
+            .ok()
+            .and_then(|payload_size| SNAPSHOT_PREFIX_SIZE.checked_add(payload_size));
+
+        match size {
+            Some(size) if payload_size <= max_payload_size as u64 => Ok(size),
+            _ => Err(format!(
+                "Snapshot payload size {} is bigger than the max payload size {}",
+                payload_size, max_payload_size
+            )),
+        }
+    }
+
+    // Returns the generation together with the snapshot
+    pub fn deserialize(src: &[u8], max_payload_size: u32) -> Result<(u64, Self), String> {
+        let size = Self::get_size(src, max_payload_size)?;
+
+        if src.len() < size {
+            return Err(format!(
+                "Snapshot is incomplete. Expected size: {}. Size: {}",
+                size,
+                src.len()
+            ));
+        }
+
+        let payload = &src[SNAPSHOT_PREFIX_SIZE..size];
+
+        let stored_crc = u32::from_le_bytes([src[40], src[41], src[42], src[43]]);
+        let calculated_crc =
+            crc32c::crc32c_append(crc32c::crc32c(&src[..SNAPSHOT_CONTENT_SIZE]), payload);
+
+        if stored_crc != calculated_crc {
+            return Err(format!(
+                "Snapshot checksum mismatch. Stored: {:08x}. Calculated: {:08x}",
+                stored_crc, calculated_crc
+            ));
+        }
+
+        let snapshot = Self {
+            checkpoint: ReadCheckpoint {
+                blob_position: read_u64(src, 24) as usize,
+                last_seq: read_u64(src, 32),
+            },
+            payload: payload.to_vec(),
+        };
+
+        Ok((read_u64(src, 16), snapshot))
+    }
+}
+
+fn read_u64(src: &[u8], pos: usize) -> u64 {
+    let mut result = [0u8; 8];
+    result.copy_from_slice(&src[pos..pos + 8]);
+    u64::from_le_bytes(result)
+}
+
+enum SlotContent {
+    // Nothing was written to the slot yet
+    Empty,
+    Valid(u64, Snapshot),
+    Rejected(String),
+}
+
+fn get_blob_page_no(slot: usize, page_no: usize) -> usize {
+    page_no * SLOTS_AMOUNT + slot
+}
+
+// Pages of the other slot are written back unchanged, so the whole range is written with one call
+pub async fn write_snapshot<TMyPageBlob: MyPageBlob>(
+    page_blob: &mut TMyPageBlob,
+    snapshot: &Snapshot,
+    settings: &AppendPageBlobSettings,
+) -> Result<(), AzureStorageError> {
+    crate::with_retries::create_container_if_not_exist(
+        page_blob,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+    crate::with_retries::create_blob_if_not_exists(
+        page_blob,
+        0,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+
+    let blob_size_in_pages = crate::with_retries::get_available_pages_amount(
+        page_blob,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+
+    let mut newest: Option<(usize, u64)> = None;
+
+    for slot in 0..SLOTS_AMOUNT {
+        if let SlotContent::Valid(generation, _) =
+            read_slot(page_blob, blob_size_in_pages, slot, settings).await?
+        {
+            if newest.is_none_or(|(_, newest_generation)| generation > newest_generation) {
+                newest = Some((slot, generation));
+            }
+        }
+    }
+
+    let (slot, generation) = match newest {
+        Some((newest_slot, generation)) => ((newest_slot + 1) % SLOTS_AMOUNT, generation + 1),
+        None => (0, 1),
+    };
+
+    let serialized = snapshot.serialize(generation);
+    let pages_amount = serialized.len() / BLOB_PAGE_SIZE;
+    let range_in_pages = get_blob_page_no(slot, pages_amount - 1) + 1;
+
+    let mut content = if blob_size_in_pages > 0 {
+        crate::with_retries::read_pages(
+            page_blob,
+            0,
+            range_in_pages.min(blob_size_in_pages),
+            &settings.retry_policy,
+            &settings.metrics,
+        )
+        .await?
+    } else {
+        Vec::new()
+    };
+
+    content.resize(range_in_pages * BLOB_PAGE_SIZE, 0);
+
+    for (page_no, page) in serialized.chunks(BLOB_PAGE_SIZE).enumerate() {
+        let pos = get_blob_page_no(slot, page_no) * BLOB_PAGE_SIZE;
+        content[pos..pos + BLOB_PAGE_SIZE].copy_from_slice(page);
+    }
+
+    crate::with_retries::auto_ressize_and_save_pages(
+        page_blob,
+        0,
+        settings.max_pages_to_write_single_round_trip,
+        1,
+        content,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+
+    Ok(())
+}
+
+// Valid snapshots, the newest first. Empty if there is no snapshot yet or none was written completely
+pub async fn read_snapshots<TMyPageBlob: MyPageBlob>(
+    page_blob: &mut TMyPageBlob,
+    settings: &AppendPageBlobSettings,
+) -> Result<Vec<Snapshot>, PageBlobAppendError> {
+    let blob_size_in_pages = match crate::with_retries::get_available_pages_amount(
+        page_blob,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await
+    {
+        Ok(blob_size_in_pages) => blob_size_in_pages,
+        Err(AzureStorageError::BlobNotFound) => return Ok(Vec::new()),
+        Err(AzureStorageError::ContainerNotFound) => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut result = Vec::new();
+
+    for slot in 0..SLOTS_AMOUNT {
+        match read_slot(page_blob, blob_size_in_pages, slot, settings).await? {
+            SlotContent::Empty => {}
+            SlotContent::Valid(generation, snapshot) => result.push((generation, snapshot)),
+            SlotContent::Rejected(err) => {
+                crate::diagnostics::snapshot_rejected(
+                    page_blob,
+                    &format!("Slot {}. {}", slot, err),
+                );
+            }
+        }
+    }
+
+    result.sort_by(|a, b| b.0.cmp(&a.0));
+
+    Ok(result.into_iter().map(|(_, snapshot)| snapshot).collect())
+}
+
+async fn read_slot<TMyPageBlob: MyPageBlob>(
+    page_blob: &mut TMyPageBlob,
+    blob_size_in_pages: usize,
+    slot: usize,
+    settings: &AppendPageBlobSettings,
+) -> Result<SlotContent, AzureStorageError> {
+    let first_page_no = get_blob_page_no(slot, 0);
+
+    if first_page_no >= blob_size_in_pages {
+        return Ok(SlotContent::Empty);
+    }
+
+    let first_page = crate::with_retries::read_pages(
+        page_blob,
+        first_page_no,
+        1,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+
+    if first_page.iter().all(|b| *b == 0) {
+        return Ok(SlotContent::Empty);
+    }
+
+    let max_payload_size = settings.max_payload_size_protection;
+
+    let size_in_pages = match Snapshot::get_size(&first_page, max_payload_size) {
+        Ok(size) => size.div_ceil(BLOB_PAGE_SIZE),
+        Err(err) => return Ok(SlotContent::Rejected(err)),
+    };
+
+    let last_page_no = get_blob_page_no(slot, size_in_pages - 1);
+
+    if last_page_no >= blob_size_in_pages {
+        return Ok(SlotContent::Rejected(
+            "Snapshot is bigger than the blob".to_string(),
+        ));
+    }
+
+    let content = if size_in_pages > 1 {
+        let pages = crate::with_retries::read_pages(
+            page_blob,
+            first_page_no,
+            last_page_no - first_page_no + 1,
+            &settings.retry_policy,
+            &settings.metrics,
+        )
+        .await?;
+
+        pages
+            .chunks(BLOB_PAGE_SIZE)
+            .step_by(SLOTS_AMOUNT)
+            .flatten()
+            .copied()
+            .collect()
+    } else {
+        first_page
+    };
+
+    match Snapshot::deserialize(&content, max_payload_size) {
+        Ok((generation, snapshot)) => Ok(SlotContent::Valid(generation, snapshot)),
+        Err(err) => Ok(SlotContent::Rejected(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use my_azure_page_blob::MyPageBlobMock;
+
+    use super::*;
+    use crate::{test_utils::get_settings, RecordFormat};
+
+    const MAX_PAYLOAD_SIZE: u32 = 1024 * 1024;
+
+    #[test]
+    fn test_serialize_deserialize() {
+        let snapshot = Snapshot {
+            checkpoint: ReadCheckpoint {
+                blob_position: 1024,
+                last_seq: 15,
+            },
+            payload: vec![5u8; 600],
+        };
+
+        let serialized = snapshot.serialize(3);
+        assert_eq!(2 * BLOB_PAGE_SIZE, serialized.len());
+
+        assert_eq!(
+            (3, snapshot),
+            Snapshot::deserialize(&serialized, MAX_PAYLOAD_SIZE).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_torn_snapshot_is_rejected() {
+        let snapshot = Snapshot {
+            checkpoint: ReadCheckpoint {
+                blob_position: 1024,
+                last_seq: 15,
+            },
+            payload: vec![5u8; 600],
+        };
+
+        let mut serialized = snapshot.serialize(1);
+
+        // Second page was not written
+        serialized[BLOB_PAGE_SIZE..].fill(0);
+        assert!(Snapshot::deserialize(&serialized, MAX_PAYLOAD_SIZE).is_err());
+
+        assert!(Snapshot::deserialize(&serialized[..BLOB_PAGE_SIZE], MAX_PAYLOAD_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_corrupted_size_is_rejected() {
+        let snapshot = Snapshot {
+            checkpoint: ReadCheckpoint {
+                blob_position: 1024,
+                last_seq: 15,
+            },
+            payload: vec![5u8; 600],
+        };
+
+        let mut serialized = snapshot.serialize(1);
+
+        serialized[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Snapshot::get_size(&serialized, MAX_PAYLOAD_SIZE).is_err());
+
+        serialized[8..16].copy_from_slice(&(MAX_PAYLOAD_SIZE as u64 + 1).to_le_bytes());
+        assert!(Snapshot::get_size(&serialized, MAX_PAYLOAD_SIZE).is_err());
+
+        // Snapshot is valid, but bigger than the limit
+        let serialized = snapshot.serialize(1);
+        assert!(Snapshot::deserialize(&serialized, 100).is_err());
+    }
+
+    fn create_snapshot(last_seq: u64, payload_size: usize) -> Snapshot {
+        Snapshot {
+            checkpoint: ReadCheckpoint {
+                blob_position: 512 + last_seq as usize * 20,
+                last_seq,
+            },
+            payload: vec![last_seq as u8; payload_size],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_torn_write_keeps_previous_snapshot() {
+        let mut page_blob = MyPageBlobMock::new();
+        let settings = get_settings(RecordFormat::Crc32cWithSequence);
+
+        assert!(read_snapshots(&mut page_blob, &settings)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Slots 0 and 1 of different sizes
+        let first = create_snapshot(1, 1000);
+        let second = create_snapshot(2, 10);
+        write_snapshot(&mut page_blob, &first, &settings)
+            .await
+            .unwrap();
+        write_snapshot(&mut page_blob, &second, &settings)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec![second.clone(), first.clone()],
+            read_snapshots(&mut page_blob, &settings).await.unwrap()
+        );
+
+        // Third snapshot goes to slot 0 and only its first page is written
+        let third = create_snapshot(3, 2000);
+        page_blob
+            .save_pages(0, 1, third.serialize(3)[..BLOB_PAGE_SIZE].to_vec())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec![second.clone()],
+            read_snapshots(&mut page_blob, &settings).await.unwrap()
+        );
+
+        // The next write replaces the torn slot and keeps the newest valid one
+        write_snapshot(&mut page_blob, &third, &settings)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec![third, second],
+            read_snapshots(&mut page_blob, &settings).await.unwrap()
+        );
+    }
+}
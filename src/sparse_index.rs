@@ -0,0 +1,362 @@
+use my_azure_page_blob::MyPageBlob;
+use my_azure_storage_sdk::{page_blob::consts::BLOB_PAGE_SIZE, AzureStorageError};
+
+use crate::{PageBlobMetrics, ReadCheckpoint, RetryPolicy};
+
+const ENTRY_SIZE: usize = 16;
+const ENTRIES_PER_PAGE: usize = BLOB_PAGE_SIZE / ENTRY_SIZE;
+const INDEX_AUTO_RESIZE_IN_PAGES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SparseIndexEntry {
+    pub seq: u64,
+    pub blob_position: usize,
+}
+
+impl SparseIndexEntry {
+    // Entry points to the record with seq, so reading resumes right before it
+    pub fn to_checkpoint(&self) -> ReadCheckpoint {
+        ReadCheckpoint {
+            blob_position: self.blob_position,
+            last_seq: self.seq - 1,
+        }
+    }
+
+    fn parse(src: &[u8]) -> Option<Self> {
+        let mut seq = [0u8; 8];
+        seq.copy_from_slice(&src[..8]);
+        let seq = u64::from_le_bytes(seq);
+
+        if seq == 0 {
+            return None;
+        }
+
+        let mut blob_position = [0u8; 8];
+        blob_position.copy_from_slice(&src[8..16]);
+
+        Some(Self {
+            seq,
+            blob_position: u64::from_le_bytes(blob_position) as usize,
+        })
+    }
+
+    fn serialize(&self, dest: &mut [u8]) {
+        dest[..8].copy_from_slice(&self.seq.to_le_bytes());
+        dest[8..16].copy_from_slice(&(self.blob_position as u64).to_le_bytes());
+    }
+}
+
+// Companion blob with fixed size entries [seq: u64 LE][blob_position: u64 LE].
+// Entries are sorted by both seq and blob_position. Empty entry (seq = 0) marks the end.
+pub struct SparseIndex<TMyPageBlob: MyPageBlob> {
+    pub page_blob: TMyPageBlob,
+    pub interval_in_bytes: usize,
+    pub retry_policy: RetryPolicy,
+    pub metrics: PageBlobMetrics,
+    entries_amount: Option<usize>,
+    cached_page: Option<(usize, Vec<u8>)>,
+}
+
+impl<TMyPageBlob: MyPageBlob> SparseIndex<TMyPageBlob> {
+    pub fn new(page_blob: TMyPageBlob, interval_in_bytes: usize) -> Self {
+        Self {
+            page_blob,
+            interval_in_bytes,
+            retry_policy: RetryPolicy::default(),
+            metrics: PageBlobMetrics::disabled(),
+            entries_amount: None,
+            cached_page: None,
+        }
+    }
+
+    pub async fn get_entries_amount(&mut self) -> Result<usize, AzureStorageError> {
+        if let Some(entries_amount) = self.entries_amount {
+            return Ok(entries_amount);
+        }
+
+        let pages_amount = match crate::with_retries::get_available_pages_amount(
+            &mut self.page_blob,
+            &self.retry_policy,
+            &self.metrics,
+        )
+        .await
+        {
+            Ok(pages_amount) => pages_amount,
+            Err(AzureStorageError::BlobNotFound) => {
+                crate::with_retries::create_blob_if_not_exists(
+                    &mut self.page_blob,
+                    0,
+                    &self.retry_policy,
+                    &self.metrics,
+                )
+                .await?;
+                0
+            }
+            Err(err) => return Err(err),
+        };
+
+        let mut from = 0;
+        let mut to = pages_amount * ENTRIES_PER_PAGE;
+
+        while from < to {
+            let mid = (from + to) / 2;
+
+            if self.get_entry(mid).await?.is_none() {
+                to = mid;
+            } else {
+                from = mid + 1;
+            }
+        }
+
+        self.entries_amount = Some(from);
+
+        Ok(from)
+    }
+
+    pub async fn get_entry(
+        &mut self,
+        entry_no: usize,
+    ) -> Result<Option<SparseIndexEntry>, AzureStorageError> {
+        let page_no = entry_no / ENTRIES_PER_PAGE;
+        let offset = entry_no % ENTRIES_PER_PAGE * ENTRY_SIZE;
+
+        let page = self.get_page(page_no).await?;
+
+        Ok(SparseIndexEntry::parse(&page[offset..offset + ENTRY_SIZE]))
+    }
+
+    pub async fn find_by_seq(
+        &mut self,
+        seq: u64,
+    ) -> Result<Option<SparseIndexEntry>, AzureStorageError> {
+        self.find_last_not_greater(seq, |entry| entry.seq).await
+    }
+
+    pub async fn find_by_blob_position(
+        &mut self,
+        blob_position: usize,
+    ) -> Result<Option<SparseIndexEntry>, AzureStorageError> {
+        self.find_last_not_greater(blob_position as u64, |entry| entry.blob_position as u64)
+            .await
+    }
+
+    pub async fn append(&mut self, entry: SparseIndexEntry) -> Result<(), AzureStorageError> {
+        let entry_no = self.get_entries_amount().await?;
+
+        let page_no = entry_no / ENTRIES_PER_PAGE;
+        let offset = entry_no % ENTRIES_PER_PAGE * ENTRY_SIZE;
+
+        let mut page = if offset == 0 {
+            vec![0u8; BLOB_PAGE_SIZE]
+        } else {
+            self.get_page(page_no).await?
+        };
+
+        entry.serialize(&mut page[offset..offset + ENTRY_SIZE]);
+
+        crate::with_retries::auto_ressize_and_save_pages(
+            &mut self.page_blob,
+            page_no,
+            1,
+            INDEX_AUTO_RESIZE_IN_PAGES,
+            page.clone(),
+            &self.retry_policy,
+            &self.metrics,
+        )
+        .await?;
+
+        self.cached_page = Some((page_no, page));
+        self.entries_amount = Some(entry_no + 1);
+
+        Ok(())
+    }
+
+    // Drops the entries which point at blob_position or after it.
+    // Whole pages are dropped first, so an interrupted truncate still leaves the entries sorted
+    pub async fn truncate(&mut self, blob_position: usize) -> Result<(), AzureStorageError> {
+        let entries_amount = self.get_entries_amount().await?;
+
+        let mut from = 0;
+        let mut to = entries_amount;
+
+        while from < to {
+            let mid = (from + to) / 2;
+
+            match self.get_entry(mid).await? {
+                Some(entry) if entry.blob_position < blob_position => from = mid + 1,
+                _ => to = mid,
+            }
+        }
+
+        if from == entries_amount {
+            return Ok(());
+        }
+
+        crate::with_retries::resize_page_blob(
+            &mut self.page_blob,
+            from.div_ceil(ENTRIES_PER_PAGE),
+            &self.retry_policy,
+            &self.metrics,
+        )
+        .await?;
+
+        let offset = from % ENTRIES_PER_PAGE * ENTRY_SIZE;
+
+        if offset > 0 {
+            let page_no = from / ENTRIES_PER_PAGE;
+            let mut page = self.get_page(page_no).await?;
+            page[offset..].fill(0);
+
+            crate::with_retries::write_pages(
+                &mut self.page_blob,
+                page_no,
+                1,
+                page,
+                &self.retry_policy,
+                &self.metrics,
+            )
+            .await?;
+        }
+
+        self.cached_page = None;
+        self.entries_amount = Some(from);
+
+        Ok(())
+    }
+
+    pub(crate) async fn on_appended(&mut self, blob_position: usize, first_seq: u64) {
+        if let Err(err) = self.add_entry_if_due(blob_position, first_seq).await {
+            crate::diagnostics::sparse_index_update_failed(&self.page_blob, &err);
+
+            self.entries_amount = None;
+            self.cached_page = None;
+        }
+    }
+
+    async fn add_entry_if_due(
+        &mut self,
+        blob_position: usize,
+        first_seq: u64,
+    ) -> Result<(), AzureStorageError> {
+        let mut entries_amount = self.get_entries_amount().await?;
+
+        if entries_amount > 0 {
+            if let Some(last_entry) = self.get_entry(entries_amount - 1).await? {
+                // Log was truncated without the index. Entries after the end are not valid anymore
+                if blob_position <= last_entry.blob_position {
+                    self.truncate(blob_position).await?;
+                    entries_amount = self.get_entries_amount().await?;
+                }
+            }
+        }
+
+        if entries_amount > 0 {
+            if let Some(last_entry) = self.get_entry(entries_amount - 1).await? {
+                if first_seq <= last_entry.seq
+                    || blob_position < last_entry.blob_position + self.interval_in_bytes
+                {
+                    return Ok(());
+                }
+            }
+        }
+
+        self.append(SparseIndexEntry {
+            seq: first_seq,
+            blob_position,
+        })
+        .await
+    }
+
+    async fn find_last_not_greater(
+        &mut self,
+        value: u64,
+        get_key: impl Fn(&SparseIndexEntry) -> u64,
+    ) -> Result<Option<SparseIndexEntry>, AzureStorageError> {
+        let entries_amount = self.get_entries_amount().await?;
+
+        let mut from = 0;
+        let mut to = entries_amount;
+        let mut result = None;
+
+        while from < to {
+            let mid = (from + to) / 2;
+
+            match self.get_entry(mid).await? {
+                Some(entry) if get_key(&entry) <= value => {
+                    result = Some(entry);
+                    from = mid + 1;
+                }
+                _ => to = mid,
+            }
+        }
+
+        Ok(result)
+    }
+
+    async fn get_page(&mut self, page_no: usize) -> Result<Vec<u8>, AzureStorageError> {
+        if let Some((cached_page_no, page)) = &self.cached_page {
+            if *cached_page_no == page_no {
+                return Ok(page.clone());
+            }
+        }
+
+        let page = crate::with_retries::read_pages(
+            &mut self.page_blob,
+            page_no,
+            1,
+            &self.retry_policy,
+            &self.metrics,
+        )
+        .await?;
+        self.cached_page = Some((page_no, page.clone()));
+
+        Ok(page)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_append_and_find() {
+        let page_blob = crate::test_utils::create_blob().await;
+
+        let mut index = SparseIndex::new(page_blob, 0);
+
+        for i in 0..100u64 {
+            index
+                .append(SparseIndexEntry {
+                    seq: i * 10 + 1,
+                    blob_position: 512 + i as usize * 1000,
+                })
+                .await
+                .unwrap();
+        }
+
+        let page_blob = crate::test_utils::copy_blob(&mut index.page_blob).await;
+
+        let mut index = SparseIndex::new(page_blob, 0);
+
+        assert_eq!(100, index.get_entries_amount().await.unwrap());
+
+        assert_eq!(None, index.find_by_seq(0).await.unwrap());
+
+        let entry = index.find_by_seq(1).await.unwrap().unwrap();
+        assert_eq!(1, entry.seq);
+
+        let entry = index.find_by_seq(555).await.unwrap().unwrap();
+        assert_eq!(551, entry.seq);
+        assert_eq!(512 + 55 * 1000, entry.blob_position);
+
+        let entry = index.find_by_seq(100_000).await.unwrap().unwrap();
+        assert_eq!(991, entry.seq);
+
+        let entry = index
+            .find_by_blob_position(512 + 1999)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(11, entry.seq);
+    }
+}
@@ -10,3 +10,4 @@ pub use state_data_corrupted::StateDataCorrupted;
 pub use state_data_not_initialized::StateDataNotInitialized;
 pub use state_data_reading::{GetNextPayloadResult, StateDataReading};
 pub use state_data_writing::StateDataWriting;
+pub use utils::{copy_blob, write_header};
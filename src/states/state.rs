@@ -6,7 +6,7 @@ use super::{StateDataCorrupted, StateDataNotInitialized, StateDataReading, State
 
 pub enum PageBlobAppendCacheState<TMyPageBlob: MyPageBlob> {
     NotInitialized(StateDataNotInitialized<TMyPageBlob>),
-    Reading(StateDataReading<TMyPageBlob>),
+    Reading(Box<StateDataReading<TMyPageBlob>>),
     Corrupted(StateDataCorrupted<TMyPageBlob>),
     Writing(StateDataWriting<TMyPageBlob>),
 }
@@ -18,7 +18,7 @@ impl<TMyPageBlob: MyPageBlob> PageBlobAppendCacheState<TMyPageBlob> {
                 StateDataCorrupted::from_not_initialized_state(state, settings, info),
             ),
             PageBlobAppendCacheState::Reading(state) => PageBlobAppendCacheState::Corrupted(
-                StateDataCorrupted::from_reading_state(state, settings, info),
+                StateDataCorrupted::from_reading_state(*state, settings, info),
             ),
             _ => {
                 panic!(
@@ -29,7 +29,16 @@ impl<TMyPageBlob: MyPageBlob> PageBlobAppendCacheState<TMyPageBlob> {
         }
     }
 
-    pub fn as_string_name(&self) -> &str {
+    pub fn into_page_blob(self) -> TMyPageBlob {
+        match self {
+            PageBlobAppendCacheState::NotInitialized(state) => state.page_blob,
+            PageBlobAppendCacheState::Reading(state) => state.seq_reader.page_blob,
+            PageBlobAppendCacheState::Corrupted(state) => state.page_blob,
+            PageBlobAppendCacheState::Writing(state) => state.seq_writer.page_blob,
+        }
+    }
+
+    pub fn as_string_name(&self) -> &'static str {
         match self {
             PageBlobAppendCacheState::NotInitialized(_) => "NotInitialized",
             PageBlobAppendCacheState::Reading(_) => "Reading",
@@ -44,3 +53,13 @@ pub enum ChangeState {
     ToWriteMode,
     ToCorrupted(CorruptedErrorInfo),
 }
+
+impl ChangeState {
+    pub fn as_string_name(&self) -> &'static str {
+        match self {
+            ChangeState::ToReadMode => "Reading",
+            ChangeState::ToWriteMode => "Writing",
+            ChangeState::ToCorrupted(_) => "Corrupted",
+        }
+    }
+}
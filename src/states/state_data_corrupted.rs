@@ -1,7 +1,7 @@
 use my_azure_page_blob::MyPageBlob;
 use my_azure_storage_sdk::AzureStorageError;
 
-use crate::{error::CorruptedErrorInfo, AppendPageBlobSettings, ChangeState};
+use crate::{error::CorruptedErrorInfo, AppendPageBlobSettings, BlobHeader, ChangeState};
 
 use super::{StateDataNotInitialized, StateDataReading};
 
@@ -9,6 +9,8 @@ pub struct StateDataCorrupted<TMyPageBlob: MyPageBlob> {
     pub page_blob: TMyPageBlob,
     settings: AppendPageBlobSettings,
     pub info: CorruptedErrorInfo,
+    pub last_seq: u64,
+    header: Option<BlobHeader>,
 }
 
 impl<TMyPageBlob: MyPageBlob> StateDataCorrupted<TMyPageBlob> {
@@ -21,6 +23,8 @@ impl<TMyPageBlob: MyPageBlob> StateDataCorrupted<TMyPageBlob> {
             page_blob: state.seq_reader.page_blob,
             settings,
             info: info.clone(),
+            last_seq: state.last_seq,
+            header: state.header,
         }
     }
 
@@ -33,6 +37,8 @@ impl<TMyPageBlob: MyPageBlob> StateDataCorrupted<TMyPageBlob> {
             page_blob: state.page_blob,
             settings,
             info: info.clone(),
+            last_seq: 0,
+            header: state.header,
         }
     }
 
@@ -45,10 +51,17 @@ impl<TMyPageBlob: MyPageBlob> StateDataCorrupted<TMyPageBlob> {
                 &mut self.page_blob,
                 backup_blob,
                 self.settings.max_pages_to_write_single_round_trip,
+                &self.settings.retry_policy,
+                &self.settings.metrics,
             )
             .await?;
         }
 
+        if let Some(header) = &mut self.header {
+            super::utils::add_header_flags_if_required(&mut self.page_blob, header, &self.settings)
+                .await?;
+        }
+
         Ok(ChangeState::ToWriteMode)
     }
 }
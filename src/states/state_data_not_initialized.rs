@@ -1,37 +1,222 @@
 use my_azure_page_blob::MyPageBlob;
-use my_azure_storage_sdk::AzureStorageError;
+use my_azure_storage_sdk::{page_blob::consts::BLOB_PAGE_SIZE, AzureStorageError};
 
-use crate::{ChangeState, PageBlobAppendError};
+use crate::{
+    AppendPageBlobSettings, BlobHeader, ChangeState, PageBlobAppendError, ReadCheckpoint,
+    RecordFormat,
+};
 
 pub struct StateDataNotInitialized<TMyPageBlob: MyPageBlob> {
     pub page_blob: TMyPageBlob,
     pub blob_size_in_pages: usize,
+    pub header: Option<BlobHeader>,
+    // Header of an empty blob which is not written yet. It is written when the blob is going to be written
+    header_is_pending: bool,
+    pub checkpoint: Option<ReadCheckpoint>,
+    pub checkpoint_page: Option<Vec<u8>>,
+    settings: AppendPageBlobSettings,
 }
 
 impl<TMyPageBlob: MyPageBlob> StateDataNotInitialized<TMyPageBlob> {
-    pub fn new(page_blob: TMyPageBlob) -> Self {
+    pub fn new(page_blob: TMyPageBlob, settings: AppendPageBlobSettings) -> Self {
         Self {
             page_blob,
             blob_size_in_pages: 0,
+            header: None,
+            header_is_pending: false,
+            checkpoint: None,
+            checkpoint_page: None,
+            settings,
+        }
+    }
+
+    pub fn from_checkpoint(
+        page_blob: TMyPageBlob,
+        settings: AppendPageBlobSettings,
+        checkpoint: ReadCheckpoint,
+    ) -> Self {
+        let mut result = Self::new(page_blob, settings);
+        result.checkpoint = Some(checkpoint);
+        result
+    }
+
+    pub fn get_record_format(&self) -> RecordFormat {
+        match &self.header {
+            Some(header) => header.get_record_format(),
+            None => RecordFormat::Legacy,
+        }
+    }
+
+    pub fn get_data_start_position(&self) -> usize {
+        if self.header.is_some() {
+            BlobHeader::get_data_start_position()
+        } else {
+            0
         }
     }
 
     pub async fn init(&mut self) -> Result<Option<ChangeState>, PageBlobAppendError> {
-        let blob_size_in_pages =
-            crate::with_retries::get_available_pages_amount(&mut self.page_blob).await?;
+        let blob_size_in_pages = crate::with_retries::get_available_pages_amount(
+            &mut self.page_blob,
+            &self.settings.retry_policy,
+            &self.settings.metrics,
+        )
+        .await?;
 
         self.blob_size_in_pages = blob_size_in_pages;
 
         if self.blob_size_in_pages == 0 {
+            self.check_no_checkpoint()?;
+            self.set_pending_header();
             return Ok(Some(ChangeState::ToWriteMode));
-        } else {
+        }
+
+        let first_page = crate::with_retries::read_pages(
+            &mut self.page_blob,
+            0,
+            1,
+            &self.settings.retry_policy,
+            &self.settings.metrics,
+        )
+        .await?;
+
+        if BlobHeader::is_header(&first_page) {
+            let header =
+                BlobHeader::deserialize(&first_page).map_err(|err| self.unsupported_format(err))?;
+
+            header
+                .validate()
+                .map_err(|err| self.unsupported_format(err))?;
+
+            if header.is_compaction_in_progress() {
+                return Err(PageBlobAppendError::CompactionInterrupted(format!(
+                    "Compaction of PageBlobAppend {}/{} was interrupted",
+                    self.page_blob.get_container_name(),
+                    self.page_blob.get_blob_name()
+                )));
+            }
+
+            self.header = Some(header);
+            self.load_checkpoint_page().await?;
+            return Ok(Some(ChangeState::ToReadMode));
+        }
+
+        if self.settings.record_format == RecordFormat::Legacy {
+            self.load_checkpoint_page().await?;
             return Ok(Some(ChangeState::ToReadMode));
         }
+
+        if first_page.iter().all(|b| *b == 0) {
+            self.check_no_checkpoint()?;
+            self.set_pending_header();
+            return Ok(Some(ChangeState::ToWriteMode));
+        }
+
+        Err(self
+            .unsupported_format("Blob does not start with the PageBlobAppend header".to_string()))
     }
 
     pub async fn init_blob(&mut self) -> Result<ChangeState, AzureStorageError> {
-        crate::with_retries::create_container_if_not_exist(&mut self.page_blob).await?;
-        crate::with_retries::create_blob_if_not_exists(&mut self.page_blob, 0).await?;
+        crate::with_retries::create_container_if_not_exist(
+            &mut self.page_blob,
+            &self.settings.retry_policy,
+            &self.settings.metrics,
+        )
+        .await?;
+        crate::with_retries::create_blob_if_not_exists(
+            &mut self.page_blob,
+            0,
+            &self.settings.retry_policy,
+            &self.settings.metrics,
+        )
+        .await?;
+        self.set_pending_header();
+        self.write_header_if_required().await?;
         Ok(ChangeState::ToWriteMode)
     }
+
+    // Legacy blobs have no header. Others get the header with the flags the settings require
+    fn set_pending_header(&mut self) {
+        if self.settings.record_format == RecordFormat::Legacy {
+            return;
+        }
+
+        let mut header = BlobHeader::new(self.settings.record_format);
+        header.flags |= self.settings.get_required_header_flags();
+
+        self.header = Some(header);
+        self.header_is_pending = true;
+    }
+
+    pub async fn write_header_if_required(&mut self) -> Result<(), AzureStorageError> {
+        if !self.header_is_pending {
+            return Ok(());
+        }
+
+        if let Some(header) = &self.header {
+            super::utils::write_header(&mut self.page_blob, header, &self.settings).await?;
+        }
+
+        self.header_is_pending = false;
+
+        Ok(())
+    }
+
+    fn check_no_checkpoint(&self) -> Result<(), PageBlobAppendError> {
+        if let Some(checkpoint) = &self.checkpoint {
+            return Err(PageBlobAppendError::InvalidCheckpoint(format!(
+                "Can not start reading from position {}. Blob {}/{} has no records",
+                checkpoint.blob_position,
+                self.page_blob.get_container_name(),
+                self.page_blob.get_blob_name()
+            )));
+        }
+
+        Ok(())
+    }
+
+    async fn load_checkpoint_page(&mut self) -> Result<(), PageBlobAppendError> {
+        let checkpoint = match &self.checkpoint {
+            Some(checkpoint) => *checkpoint,
+            None => return Ok(()),
+        };
+
+        let data_start_position = self.get_data_start_position();
+        let blob_size = self.blob_size_in_pages * BLOB_PAGE_SIZE;
+
+        if checkpoint.blob_position < data_start_position || checkpoint.blob_position >= blob_size {
+            return Err(PageBlobAppendError::InvalidCheckpoint(format!(
+                "Position {} is out of the data range [{}..{}) of blob {}/{}",
+                checkpoint.blob_position,
+                data_start_position,
+                blob_size,
+                self.page_blob.get_container_name(),
+                self.page_blob.get_blob_name()
+            )));
+        }
+
+        let page_no = checkpoint.blob_position / BLOB_PAGE_SIZE;
+
+        let page = crate::with_retries::read_pages(
+            &mut self.page_blob,
+            page_no,
+            1,
+            &self.settings.retry_policy,
+            &self.settings.metrics,
+        )
+        .await?;
+
+        self.checkpoint_page = Some(page);
+
+        Ok(())
+    }
+
+    fn unsupported_format(&self, msg: String) -> PageBlobAppendError {
+        PageBlobAppendError::UnsupportedFormat(format!(
+            "PageBlobAppend {}/{}. {}",
+            self.page_blob.get_container_name(),
+            self.page_blob.get_blob_name(),
+            msg
+        ))
+    }
 }
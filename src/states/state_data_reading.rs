@@ -1,15 +1,22 @@
+use bytes::Bytes;
 use my_azure_page_blob::MyPageBlob;
-use my_azure_storage_sdk::AzureStorageError;
+use my_azure_storage_sdk::{page_blob::consts::BLOB_PAGE_SIZE, AzureStorageError};
 
 use crate::{
-    error::CorruptedErrorInfo, read_write::PageBlobSequenceReader,
-    settings::AppendPageBlobSettings, PageBlobAppendError,
+    compression::{self, DecompressError},
+    encryption::{self, DecryptError},
+    error::CorruptedErrorInfo,
+    read_write::PageBlobSequenceReader,
+    record_size_field::RecordSizeField,
+    settings::AppendPageBlobSettings,
+    BlobHeader, PageBlobAppendError, ReadCheckpoint, RecordFormat,
 };
 
 use super::{state::ChangeState, StateDataNotInitialized};
 
 pub enum GetNextPayloadResult {
-    NextPayload(Vec<u8>),
+    // Payload is a slice of the downloaded pages unless it had to be copied or decoded
+    NextPayload(u64, Bytes),
     ChangeState(ChangeState),
 }
 
@@ -18,6 +25,13 @@ pub struct StateDataReading<TMyPageBlob: MyPageBlob> {
     pub pages_have_read: usize,
     pub settings: AppendPageBlobSettings,
     pub blob_size_in_pages: usize,
+    pub header: Option<BlobHeader>,
+    pub last_seq: u64,
+    validating_checkpoint: bool,
+    invalid_checkpoint: Option<String>,
+    undecryptable_record: Option<String>,
+    keep_undecoded_payloads: bool,
+    undecoded_reason: Option<String>,
 }
 
 impl<TMyPageBlob: MyPageBlob> StateDataReading<TMyPageBlob> {
@@ -25,22 +39,194 @@ impl<TMyPageBlob: MyPageBlob> StateDataReading<TMyPageBlob> {
         not_initialized: StateDataNotInitialized<TMyPageBlob>,
         settings: AppendPageBlobSettings,
     ) -> Self {
+        let start_page_no = not_initialized.get_data_start_position() / BLOB_PAGE_SIZE;
+
+        let (seq_reader, last_seq, validating_checkpoint) =
+            match (not_initialized.checkpoint, not_initialized.checkpoint_page) {
+                (Some(checkpoint), Some(checkpoint_page)) => (
+                    PageBlobSequenceReader::starting_from_position(
+                        not_initialized.page_blob,
+                        &settings,
+                        checkpoint.blob_position,
+                        checkpoint_page,
+                    ),
+                    checkpoint.last_seq,
+                    true,
+                ),
+                _ => (
+                    PageBlobSequenceReader::starting_from_page(
+                        not_initialized.page_blob,
+                        &settings,
+                        start_page_no,
+                    ),
+                    0,
+                    false,
+                ),
+            };
+
         Self {
-            seq_reader: PageBlobSequenceReader::new(
-                not_initialized.page_blob,
-                settings.cache_capacity_in_pages,
-            ),
+            seq_reader,
             pages_have_read: 0,
 
             settings,
             blob_size_in_pages: not_initialized.blob_size_in_pages,
+            header: not_initialized.header,
+            last_seq,
+            validating_checkpoint,
+            invalid_checkpoint: None,
+            undecryptable_record: None,
+            keep_undecoded_payloads: false,
+            undecoded_reason: None,
         }
     }
 
+    // Record which can not be decrypted or decompressed is returned as stored instead of failing.
+    // Its framing and checksum are still checked
+    pub fn keep_undecoded_payloads(&mut self) {
+        self.keep_undecoded_payloads = true;
+    }
+
+    // Why the last returned payload is the stored one. None if it was decoded
+    pub fn get_undecoded_reason(&self) -> Option<&str> {
+        self.undecoded_reason.as_deref()
+    }
+
     pub fn get_blob_position(&self) -> usize {
         self.seq_reader.get_blob_position()
     }
 
+    // Reading started from a checkpoint and no record has been read yet
+    pub fn is_validating_checkpoint(&self) -> bool {
+        self.validating_checkpoint
+    }
+
+    // Reading stays refused, the same way as for a checkpoint which is not a record boundary
+    pub fn refuse_checkpoint(&mut self, msg: String) -> PageBlobAppendError {
+        self.invalid_checkpoint = Some(msg.clone());
+        PageBlobAppendError::InvalidCheckpoint(msg)
+    }
+
+    // Returns why the end marker at the checkpoint is not the end of data.
+    // Zeros are also read inside a payload, so the marker is accepted only if the record
+    // with the last seq of the checkpoint ends right before it.
+    // Pages are read backwards by cache_capacity_in_pages until that record is found,
+    // but not further than the longest record can take
+    pub async fn check_end_of_data(
+        &mut self,
+        checkpoint: ReadCheckpoint,
+    ) -> Result<Option<String>, AzureStorageError> {
+        let (record_format, data_start_position) = match &self.header {
+            Some(header) => (
+                header.get_record_format(),
+                BlobHeader::get_data_start_position(),
+            ),
+            None => (RecordFormat::Legacy, 0),
+        };
+
+        let end = checkpoint.blob_position;
+
+        if end <= data_start_position {
+            return Ok(None);
+        }
+
+        let header_size = record_format.get_record_header_size();
+        let max_record_size = header_size + self.settings.max_payload_size_protection as usize;
+
+        let lowest_record_start = end.saturating_sub(max_record_size).max(data_start_position);
+        let lowest_page_no = lowest_record_start / BLOB_PAGE_SIZE;
+
+        // Record starts at or after it are checked already
+        let mut checked_from = (end + 1).saturating_sub(header_size);
+
+        let mut first_page_no = (end - 1) / BLOB_PAGE_SIZE + 1;
+        let mut data = Vec::new();
+
+        while first_page_no > lowest_page_no {
+            let pages_amount =
+                (first_page_no - lowest_page_no).min(self.settings.cache_capacity_in_pages.max(1));
+            first_page_no -= pages_amount;
+
+            let mut pages = crate::with_retries::read_pages(
+                &mut self.seq_reader.page_blob,
+                first_page_no,
+                pages_amount,
+                &self.settings.retry_policy,
+                &self.settings.metrics,
+            )
+            .await?;
+
+            pages.extend_from_slice(&data);
+            data = pages;
+
+            let offset = first_page_no * BLOB_PAGE_SIZE;
+            let window_start = lowest_record_start.max(offset);
+
+            for record_start in (window_start..checked_from).rev() {
+                let record = &data[record_start - offset..end - offset];
+
+                if self.is_record_with_seq(record, record_format, checkpoint.last_seq) {
+                    return Ok(None);
+                }
+            }
+
+            checked_from = checked_from.min(window_start);
+        }
+
+        Ok(Some(format!(
+            "No record with seq {} ends there",
+            checkpoint.last_seq
+        )))
+    }
+
+    // Record has to take the whole data
+    fn is_record_with_seq(&self, data: &[u8], record_format: RecordFormat, seq: u64) -> bool {
+        let header_size = record_format.get_record_header_size();
+
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(&data[..4]);
+        let mut payload_size = u32::from_le_bytes(buf);
+
+        if self.has_extended_size_field() {
+            payload_size = RecordSizeField::parse(payload_size).size;
+        }
+
+        if payload_size == 0 || payload_size as usize + header_size != data.len() {
+            return false;
+        }
+
+        if record_format == RecordFormat::Legacy {
+            return true;
+        }
+
+        let check = check_record(
+            data,
+            record_format,
+            self.has_extended_size_field(),
+            self.settings.max_payload_size_protection,
+            seq.saturating_sub(1),
+        );
+
+        if !matches!(check, RecordCheck::Valid) {
+            return false;
+        }
+
+        if record_format.has_sequence_number() {
+            let mut record_seq = [0u8; 8];
+            record_seq.copy_from_slice(&data[8..16]);
+            return u64::from_le_bytes(record_seq) == seq;
+        }
+
+        true
+    }
+
+    // High bits of the record size are used only if the header says so
+    fn has_extended_size_field(&self) -> bool {
+        match &self.header {
+            Some(header) => header.has_compression() || header.has_encryption(),
+            None => false,
+        }
+    }
+
     async fn get_message_size(&mut self) -> Result<u32, PageBlobAppendError> {
         let mut buf = [0u8; 4];
 
@@ -60,14 +246,49 @@ impl<TMyPageBlob: MyPageBlob> StateDataReading<TMyPageBlob> {
         }
     }
 
-    async fn get_payload(&mut self, msg_size: u32) -> Result<Vec<u8>, PageBlobAppendError> {
-        let msg_size = msg_size as usize;
-        let mut buf: Vec<u8> = vec![0; msg_size];
+    async fn get_checksum(&mut self) -> Result<u32, PageBlobAppendError> {
+        let mut buf = [0u8; 4];
+
+        let read = self.seq_reader.read(&mut buf).await?;
+
+        if read {
+            Ok(u32::from_le_bytes(buf))
+        } else {
+            Err(PageBlobAppendError::Corrupted(CorruptedErrorInfo {
+                broken_pos: 0,
+                last_page: None,
+                msg: format!(
+                    "Can not read next payload checksum. Blob is corrupted. Pos:{}",
+                    self.seq_reader.get_blob_position()
+                ),
+            }))
+        }
+    }
+
+    async fn get_sequence_number(&mut self) -> Result<u64, PageBlobAppendError> {
+        let mut buf = [0u8; 8];
 
-        let read_result = self.seq_reader.read(&mut buf).await?;
+        let read = self.seq_reader.read(&mut buf).await?;
 
-        if read_result {
-            Ok(buf)
+        if read {
+            Ok(u64::from_le_bytes(buf))
+        } else {
+            Err(PageBlobAppendError::Corrupted(CorruptedErrorInfo {
+                broken_pos: 0,
+                last_page: None,
+                msg: format!(
+                    "Can not read next payload sequence number. Blob is corrupted. Pos:{}",
+                    self.seq_reader.get_blob_position()
+                ),
+            }))
+        }
+    }
+
+    async fn get_payload(&mut self, msg_size: u32) -> Result<Bytes, PageBlobAppendError> {
+        let read_result = self.seq_reader.read_bytes(msg_size as usize).await?;
+
+        if let Some(payload) = read_result {
+            Ok(payload)
         } else {
             return Err(PageBlobAppendError::Corrupted(CorruptedErrorInfo {
                 last_page: None,
@@ -81,25 +302,59 @@ impl<TMyPageBlob: MyPageBlob> StateDataReading<TMyPageBlob> {
     }
 
     pub async fn get_next_payload(&mut self) -> Result<GetNextPayloadResult, PageBlobAppendError> {
+        if let Some(msg) = &self.invalid_checkpoint {
+            return Err(PageBlobAppendError::InvalidCheckpoint(msg.clone()));
+        }
+
+        // Record which can not be decrypted is not skipped. Reading stops at it
+        if let Some(msg) = &self.undecryptable_record {
+            return Err(PageBlobAppendError::DecryptionFailed(msg.clone()));
+        }
+
+        if !self.validating_checkpoint {
+            return self.read_next_record().await;
+        }
+
+        // The first record after a checkpoint proves the position is a record boundary
+        let start_pos = self.get_blob_position();
+
+        match self.read_next_record().await {
+            Ok(result) => {
+                self.validating_checkpoint = false;
+                Ok(result)
+            }
+            Err(PageBlobAppendError::Corrupted(info)) => {
+                let msg = format!(
+                    "Position {} is not a record boundary. {}",
+                    start_pos, info.msg
+                );
+                self.invalid_checkpoint = Some(msg.clone());
+                Err(PageBlobAppendError::InvalidCheckpoint(msg))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn read_next_record(&mut self) -> Result<GetNextPayloadResult, PageBlobAppendError> {
+        self.undecoded_reason = None;
+
         let (start_pos, last_page) = self
             .seq_reader
             .read_cache
             .get_last_page_remaining_content(0);
 
-        let payload_size = self.get_message_size().await;
-
-        if let Err(err) = payload_size {
-            if let PageBlobAppendError::Corrupted(mut info) = err {
-                info.broken_pos = start_pos;
-                info.last_page = last_page;
+        let size_field = match self.get_message_size().await {
+            Ok(size_field) => size_field,
+            Err(err) => return Err(set_broken_pos(err, start_pos, last_page)),
+        };
 
-                return Err(PageBlobAppendError::Corrupted(info));
-            } else {
-                return Err(err);
-            }
-        }
+        let size_field = if self.has_extended_size_field() {
+            RecordSizeField::parse(size_field)
+        } else {
+            RecordSizeField::plain(size_field)
+        };
 
-        let payload_size = payload_size.unwrap();
+        let payload_size = size_field.size;
 
         if payload_size > self.settings.max_payload_size_protection {
             return Err(PageBlobAppendError::Corrupted(CorruptedErrorInfo {
@@ -116,20 +371,206 @@ impl<TMyPageBlob: MyPageBlob> StateDataReading<TMyPageBlob> {
             return Ok(GetNextPayloadResult::ChangeState(ChangeState::ToWriteMode));
         }
 
-        let payload = self.get_payload(payload_size).await;
+        let record_format = self.settings.record_format;
 
-        if let Err(err) = payload {
-            if let PageBlobAppendError::Corrupted(mut info) = err {
-                info.broken_pos = start_pos;
-                info.last_page = last_page;
+        let checksum = if record_format.has_checksum() {
+            match self.get_checksum().await {
+                Ok(checksum) => Some(checksum),
+                Err(err) => return Err(set_broken_pos(err, start_pos, last_page)),
+            }
+        } else {
+            None
+        };
 
-                return Err(PageBlobAppendError::Corrupted(info));
-            } else {
-                return Err(err);
+        let stored_seq = if record_format.has_sequence_number() {
+            match self.get_sequence_number().await {
+                Ok(seq) => Some(seq),
+                Err(err) => return Err(set_broken_pos(err, start_pos, last_page)),
             }
+        } else {
+            None
+        };
+
+        let payload = match self.get_payload(payload_size).await {
+            Ok(payload) => payload,
+            Err(err) => return Err(set_broken_pos(err, start_pos, last_page)),
+        };
+
+        if let Some(checksum) = checksum {
+            let calculated = match stored_seq {
+                Some(seq) => crc32c::crc32c_append(crc32c::crc32c(&seq.to_le_bytes()), &payload),
+                None => crc32c::crc32c(&payload),
+            };
+
+            if calculated != checksum {
+                return Err(PageBlobAppendError::Corrupted(CorruptedErrorInfo {
+                    broken_pos: start_pos,
+                    last_page,
+                    msg: format!(
+                        "Payload checksum mismatch. Stored: {:08x}. Calculated: {:08x}. Pos:{}",
+                        checksum, calculated, start_pos
+                    ),
+                }));
+            }
+        }
+
+        let seq = stored_seq.unwrap_or(self.last_seq + 1);
+
+        if seq <= self.last_seq {
+            return Err(PageBlobAppendError::Corrupted(CorruptedErrorInfo {
+                broken_pos: start_pos,
+                last_page,
+                msg: format!(
+                    "Sequence number {} is not greater than previous one {}. Pos:{}",
+                    seq, self.last_seq, start_pos
+                ),
+            }));
         }
 
-        return Ok(GetNextPayloadResult::NextPayload(payload.unwrap()));
+        let payload = if size_field.encrypted {
+            let aad = encryption::get_aad(size_field.codec, stored_seq);
+
+            match encryption::decrypt(self.settings.encryption.as_ref(), &payload, &aad) {
+                Ok(payload) => Bytes::from(payload),
+                Err(DecryptError::UnsupportedAlgorithm(msg)) | Err(DecryptError::Failed(msg))
+                    if self.keep_undecoded_payloads =>
+                {
+                    return Ok(self.return_undecoded(seq, payload, msg));
+                }
+                Err(DecryptError::UnsupportedAlgorithm(msg)) => {
+                    return Err(PageBlobAppendError::UnsupportedFormat(format!(
+                        "{}. Pos:{}",
+                        msg, start_pos
+                    )))
+                }
+                Err(DecryptError::Failed(msg)) => {
+                    let msg = format!("Can not decrypt record {}. {}. Pos:{}", seq, msg, start_pos);
+                    self.undecryptable_record = Some(msg.clone());
+                    return Err(PageBlobAppendError::DecryptionFailed(msg));
+                }
+            }
+        } else {
+            payload
+        };
+
+        let max_payload_size = self.settings.max_payload_size_protection as usize;
+
+        let payload =
+            match compression::decompress(size_field.codec, payload.clone(), max_payload_size) {
+                Ok(payload) => payload,
+                Err(DecompressError::UnsupportedCodec(msg)) if self.keep_undecoded_payloads => {
+                    return Ok(self.return_undecoded(seq, payload, msg));
+                }
+                Err(DecompressError::UnsupportedCodec(msg)) => {
+                    return Err(PageBlobAppendError::UnsupportedFormat(format!(
+                        "{}. Pos:{}",
+                        msg, start_pos
+                    )))
+                }
+                Err(DecompressError::InvalidData(msg)) => {
+                    return Err(PageBlobAppendError::Corrupted(CorruptedErrorInfo {
+                        broken_pos: start_pos,
+                        last_page,
+                        msg: format!("Can not decompress payload. {}. Pos:{}", msg, start_pos),
+                    }))
+                }
+            };
+
+        self.last_seq = seq;
+
+        Ok(GetNextPayloadResult::NextPayload(seq, payload))
+    }
+
+    fn return_undecoded(
+        &mut self,
+        seq: u64,
+        payload: Bytes,
+        reason: String,
+    ) -> GetNextPayloadResult {
+        self.undecoded_reason = Some(reason);
+        self.last_seq = seq;
+
+        GetNextPayloadResult::NextPayload(seq, payload)
+    }
+
+    // Looks for the next record with a valid checksum after broken_pos and continues reading from it.
+    // Blob is read in windows of max_pages_to_write_single_round_trip pages. Record which does not fit
+    // into the window is checked with the next one, so at most one record is carried over
+    pub async fn resync(&mut self, broken_pos: usize) -> Result<Option<usize>, AzureStorageError> {
+        let record_format = self.settings.record_format;
+
+        if !record_format.has_checksum() {
+            return Ok(None);
+        }
+
+        let blob_size_in_pages = crate::with_retries::get_available_pages_amount(
+            &mut self.seq_reader.page_blob,
+            &self.settings.retry_policy,
+            &self.settings.metrics,
+        )
+        .await?;
+
+        let window_in_pages = self.settings.max_pages_to_write_single_round_trip.max(1);
+        let has_extended_size_field = self.has_extended_size_field();
+
+        let mut next_page_no = broken_pos / BLOB_PAGE_SIZE;
+        let mut data_start = next_page_no * BLOB_PAGE_SIZE;
+        let mut data = Vec::new();
+        let mut position = broken_pos + 1;
+
+        while next_page_no < blob_size_in_pages {
+            let pages_amount = window_in_pages.min(blob_size_in_pages - next_page_no);
+
+            let pages = crate::with_retries::read_pages(
+                &mut self.seq_reader.page_blob,
+                next_page_no,
+                pages_amount,
+                &self.settings.retry_policy,
+                &self.settings.metrics,
+            )
+            .await?;
+
+            data.extend_from_slice(&pages);
+            next_page_no += pages_amount;
+
+            let is_last_window = next_page_no >= blob_size_in_pages;
+
+            while position < data_start + data.len() {
+                let check_result = check_record(
+                    &data[position - data_start..],
+                    record_format,
+                    has_extended_size_field,
+                    self.settings.max_payload_size_protection,
+                    self.last_seq,
+                );
+
+                match check_result {
+                    RecordCheck::Valid => {
+                        let page_offset = (position - data_start) / BLOB_PAGE_SIZE * BLOB_PAGE_SIZE;
+                        let first_page = data[page_offset..page_offset + BLOB_PAGE_SIZE].to_vec();
+
+                        self.seq_reader.reposition(position, first_page);
+
+                        return Ok(Some(position));
+                    }
+                    RecordCheck::Invalid => position += 1,
+                    RecordCheck::Incomplete => {
+                        if !is_last_window {
+                            break;
+                        }
+
+                        position += 1;
+                    }
+                }
+            }
+
+            // Pages before the page of the next position are not needed anymore
+            let keep_from = position / BLOB_PAGE_SIZE * BLOB_PAGE_SIZE;
+            data.drain(..(keep_from - data_start).min(data.len()));
+            data_start = keep_from;
+        }
+
+        Ok(None)
     }
 
     pub async fn init_blob(
@@ -141,12 +582,125 @@ impl<TMyPageBlob: MyPageBlob> StateDataReading<TMyPageBlob> {
                 &mut self.seq_reader.page_blob,
                 backup_blob,
                 self.settings.max_pages_to_write_single_round_trip,
+                &self.settings.retry_policy,
+                &self.settings.metrics,
             )
             .await?;
         }
 
-        crate::with_retries::resize_page_blob(&mut self.seq_reader.page_blob, 0).await?;
+        crate::with_retries::resize_page_blob(
+            &mut self.seq_reader.page_blob,
+            0,
+            &self.settings.retry_policy,
+            &self.settings.metrics,
+        )
+        .await?;
+
+        if let Some(header) = &mut self.header {
+            header.flags |= self.settings.get_required_header_flags();
+            super::utils::write_header(&mut self.seq_reader.page_blob, header, &self.settings)
+                .await?;
+        }
 
         Ok(ChangeState::ToWriteMode)
     }
+
+    // Called only when the blob is going to be written, so reading it changes nothing
+    pub async fn add_header_flags_if_required(&mut self) -> Result<(), AzureStorageError> {
+        match &mut self.header {
+            Some(header) => {
+                super::utils::add_header_flags_if_required(
+                    &mut self.seq_reader.page_blob,
+                    header,
+                    &self.settings,
+                )
+                .await
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+enum RecordCheck {
+    Valid,
+    Invalid,
+    // Record may be valid, but the data ends before it does
+    Incomplete,
+}
+
+// Checksum is calculated only if the size and the seq are plausible
+fn check_record(
+    data: &[u8],
+    record_format: RecordFormat,
+    has_extended_size_field: bool,
+    max_payload_size: u32,
+    last_seq: u64,
+) -> RecordCheck {
+    let header_size = record_format.get_record_header_size();
+
+    if data.len() < 4 {
+        return RecordCheck::Incomplete;
+    }
+
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&data[..4]);
+    let mut payload_size = u32::from_le_bytes(buf);
+
+    if has_extended_size_field {
+        payload_size = RecordSizeField::parse(payload_size).size;
+    }
+
+    if payload_size == 0 || payload_size > max_payload_size {
+        return RecordCheck::Invalid;
+    }
+
+    if data.len() < header_size {
+        return RecordCheck::Incomplete;
+    }
+
+    if record_format.has_sequence_number() {
+        let mut seq = [0u8; 8];
+        seq.copy_from_slice(&data[8..16]);
+
+        if u64::from_le_bytes(seq) <= last_seq {
+            return RecordCheck::Invalid;
+        }
+    }
+
+    let payload_size = payload_size as usize;
+
+    if data.len() < header_size + payload_size {
+        return RecordCheck::Incomplete;
+    }
+
+    buf.copy_from_slice(&data[4..8]);
+    let checksum = u32::from_le_bytes(buf);
+
+    let payload = &data[header_size..header_size + payload_size];
+
+    let calculated = if record_format.has_sequence_number() {
+        crc32c::crc32c_append(crc32c::crc32c(&data[8..16]), payload)
+    } else {
+        crc32c::crc32c(payload)
+    };
+
+    if calculated == checksum {
+        RecordCheck::Valid
+    } else {
+        RecordCheck::Invalid
+    }
+}
+
+fn set_broken_pos(
+    err: PageBlobAppendError,
+    broken_pos: usize,
+    last_page: Option<Vec<u8>>,
+) -> PageBlobAppendError {
+    if let PageBlobAppendError::Corrupted(mut info) = err {
+        info.broken_pos = broken_pos;
+        info.last_page = last_page;
+        PageBlobAppendError::Corrupted(info)
+    } else {
+        err
+    }
 }
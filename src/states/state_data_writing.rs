@@ -1,8 +1,12 @@
+use std::ops::Range;
+
 use my_azure_page_blob::MyPageBlob;
 
 use crate::{
-    read_write::{PackageBuilder, PageBlobSequenceWriter},
-    settings::AppendPageBlobSettings,
+    compression::Compression,
+    encryption::Encryption,
+    read_write::PageBlobSequenceWriter,
+    settings::{AppendPageBlobSettings, RecordFormat},
     PageBlobAppendError,
 };
 
@@ -10,6 +14,11 @@ use super::{StateDataCorrupted, StateDataNotInitialized, StateDataReading};
 
 pub struct StateDataWriting<TMyPageBlob: MyPageBlob> {
     pub seq_writer: PageBlobSequenceWriter<TMyPageBlob>,
+    record_format: RecordFormat,
+    compression: Compression,
+    encryption: Option<Encryption>,
+    max_payload_size: u32,
+    next_seq: u64,
 }
 
 impl<TMyPageBlob: MyPageBlob> StateDataWriting<TMyPageBlob> {
@@ -18,7 +27,12 @@ impl<TMyPageBlob: MyPageBlob> StateDataWriting<TMyPageBlob> {
         settings: &AppendPageBlobSettings,
     ) -> Self {
         Self {
+            next_seq: src.last_seq + 1,
             seq_writer: PageBlobSequenceWriter::from_reading(src.seq_reader, settings),
+            record_format: settings.record_format,
+            compression: settings.compression,
+            encryption: settings.encryption.clone(),
+            max_payload_size: settings.max_payload_size_protection,
         }
     }
 
@@ -26,8 +40,14 @@ impl<TMyPageBlob: MyPageBlob> StateDataWriting<TMyPageBlob> {
         src: StateDataNotInitialized<TMyPageBlob>,
         settings: &AppendPageBlobSettings,
     ) -> Self {
+        let write_position = src.get_data_start_position();
         Self {
-            seq_writer: PageBlobSequenceWriter::brand_new(src.page_blob, settings),
+            seq_writer: PageBlobSequenceWriter::brand_new(src.page_blob, settings, write_position),
+            record_format: settings.record_format,
+            compression: settings.compression,
+            encryption: settings.encryption.clone(),
+            max_payload_size: settings.max_payload_size_protection,
+            next_seq: 1,
         }
     }
 
@@ -42,6 +62,11 @@ impl<TMyPageBlob: MyPageBlob> StateDataWriting<TMyPageBlob> {
                 src.info.last_page,
                 src.info.broken_pos,
             ),
+            record_format: settings.record_format,
+            compression: settings.compression,
+            encryption: settings.encryption.clone(),
+            max_payload_size: settings.max_payload_size_protection,
+            next_seq: src.last_seq + 1,
         }
     }
 
@@ -49,18 +74,52 @@ impl<TMyPageBlob: MyPageBlob> StateDataWriting<TMyPageBlob> {
         self.seq_writer.write_cache.write_position
     }
 
-    pub async fn append_and_write<'s>(
+    pub fn get_next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    pub fn set_next_seq(&mut self, next_seq: u64) {
+        self.next_seq = next_seq;
+    }
+
+    // Returns the seqs of the records and the size of the payloads.
+    // Batch with an empty or too large payload is refused as a whole, so the seqs are not taken
+    pub async fn append_and_write<TPayload: AsRef<[u8]>>(
         &mut self,
-        payloads: &Vec<Vec<u8>>,
-    ) -> Result<(), PageBlobAppendError> {
-        let mut builder = PackageBuilder::new();
+        payloads: impl IntoIterator<Item = TPayload>,
+    ) -> Result<(Range<u64>, usize), PageBlobAppendError> {
+        let encryptor = match &self.encryption {
+            Some(encryption) => Some(encryption.get_encryptor()?),
+            None => None,
+        };
 
-        for payload in payloads {
-            builder.add_payload(payload);
+        let mut builder = self
+            .seq_writer
+            .new_package(self.record_format, self.next_seq)
+            .with_compression(self.compression)
+            .with_encryptor(encryptor)
+            .with_max_payload_size(self.max_payload_size);
+
+        for (index, payload) in payloads.into_iter().enumerate() {
+            let payload = payload.as_ref();
+
+            if payload.is_empty() {
+                return Err(PageBlobAppendError::EmptyPayload(format!(
+                    "Payload #{} is empty",
+                    index
+                )));
+            }
+
+            builder.add_payload(payload)?;
         }
 
+        let seq_range = builder.get_seq_range();
+        let payloads_size = builder.get_payloads_size();
+
         self.seq_writer.append(builder).await?;
 
-        Ok(())
+        self.next_seq = seq_range.end;
+
+        Ok((seq_range, payloads_size))
     }
 }
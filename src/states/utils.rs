@@ -1,16 +1,22 @@
 use my_azure_page_blob::MyPageBlob;
-use my_azure_storage_sdk::AzureStorageError;
+use my_azure_storage_sdk::{page_blob::consts::BLOB_PAGE_SIZE, AzureStorageError};
 
-pub async fn copy_blob<TMyPageBlob: MyPageBlob>(
-    src: &mut TMyPageBlob,
-    dest: &mut TMyPageBlob,
+use crate::{AppendPageBlobSettings, BlobHeader, PageBlobMetrics, RetryPolicy};
+
+pub async fn copy_blob<TSrcPageBlob: MyPageBlob, TDestPageBlob: MyPageBlob>(
+    src: &mut TSrcPageBlob,
+    dest: &mut TDestPageBlob,
     max_pages_per_write: usize,
+    retry_policy: &RetryPolicy,
+    metrics: &PageBlobMetrics,
 ) -> Result<(), AzureStorageError> {
-    let src_pages_amount = crate::with_retries::get_available_pages_amount(src).await?;
+    let src_pages_amount =
+        crate::with_retries::get_available_pages_amount(src, retry_policy, metrics).await?;
 
-    crate::with_retries::create_container_if_not_exist(dest).await?;
-    crate::with_retries::create_blob_if_not_exists(dest, src_pages_amount).await?;
-    crate::with_retries::resize_page_blob(dest, src_pages_amount).await?;
+    crate::with_retries::create_container_if_not_exist(dest, retry_policy, metrics).await?;
+    crate::with_retries::create_blob_if_not_exists(dest, src_pages_amount, retry_policy, metrics)
+        .await?;
+    crate::with_retries::resize_page_blob(dest, src_pages_amount, retry_policy, metrics).await?;
 
     let mut page_no: usize = 0;
 
@@ -23,11 +29,74 @@ pub async fn copy_blob<TMyPageBlob: MyPageBlob>(
             remain_pages
         };
 
-        let payload = crate::with_retries::read_pages(src, page_no, pages_to_copy).await?;
-        crate::with_retries::write_pages(dest, page_no, max_pages_per_write, payload).await?;
+        let payload =
+            crate::with_retries::read_pages(src, page_no, pages_to_copy, retry_policy, metrics)
+                .await?;
+        crate::with_retries::write_pages(
+            dest,
+            page_no,
+            max_pages_per_write,
+            payload,
+            retry_policy,
+            metrics,
+        )
+        .await?;
 
         page_no += pages_to_copy;
     }
 
     Ok(())
 }
+
+// Records written before the flags are set have codec and encryption bits 0,
+// so they are read as uncompressed and not encrypted
+pub async fn add_header_flags_if_required<TMyPageBlob: MyPageBlob>(
+    page_blob: &mut TMyPageBlob,
+    header: &mut BlobHeader,
+    settings: &AppendPageBlobSettings,
+) -> Result<(), AzureStorageError> {
+    let required_flags = settings.get_required_header_flags();
+
+    if header.flags & required_flags == required_flags {
+        return Ok(());
+    }
+
+    header.flags |= required_flags;
+
+    crate::with_retries::write_pages(
+        page_blob,
+        0,
+        settings.max_pages_to_write_single_round_trip,
+        header.serialize(),
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await
+}
+
+// Header page is followed by an empty page, so the blob reads as an empty log
+pub async fn write_header<TMyPageBlob: MyPageBlob>(
+    page_blob: &mut TMyPageBlob,
+    header: &BlobHeader,
+    settings: &AppendPageBlobSettings,
+) -> Result<(), AzureStorageError> {
+    let get_payload = || {
+        let mut payload = header.serialize();
+        payload.extend_from_slice(&[0u8; BLOB_PAGE_SIZE]);
+        payload
+    };
+
+    crate::with_retries::auto_ressize_and_save_pages(
+        page_blob,
+        0,
+        settings.max_pages_to_write_single_round_trip,
+        settings.blob_auto_resize_in_pages,
+        get_payload(),
+        get_payload,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use async_trait::async_trait;
+use my_azure_page_blob::{MyPageBlob, MyPageBlobMock};
+use my_azure_storage_sdk::AzureStorageError;
+
+use crate::{AppendPageBlobSettings, RecordFormat};
+
+// Small cache and payload limit, so the tests cross the buffer boundaries with a few records
+pub fn get_settings(record_format: RecordFormat) -> AppendPageBlobSettings {
+    AppendPageBlobSettings {
+        blob_auto_resize_in_pages: 1,
+        cache_capacity_in_pages: 10,
+        max_pages_to_write_single_round_trip: 1000,
+        max_payload_size_protection: 1024 * 1024,
+        record_format,
+        ..Default::default()
+    }
+}
+
+pub async fn create_blob() -> MyPageBlobMock {
+    let mut page_blob = MyPageBlobMock::new();
+    page_blob.create_container_if_not_exist().await.unwrap();
+    page_blob.create_if_not_exists(0).await.unwrap();
+    page_blob
+}
+
+pub async fn create_blob_with_content(content: Vec<u8>) -> MyPageBlobMock {
+    let mut page_blob = create_blob().await;
+
+    if !content.is_empty() {
+        page_blob
+            .auto_ressize_and_save_pages(0, 10, content, 1)
+            .await
+            .unwrap();
+    }
+
+    page_blob
+}
+
+// Lets a new instance read what another one wrote while the first one still owns its blob
+pub async fn copy_blob<TMyPageBlob: MyPageBlob>(page_blob: &mut TMyPageBlob) -> MyPageBlobMock {
+    let content = page_blob.download().await.unwrap();
+    create_blob_with_content(content).await
+}
+
+// Counts the reads, so a test can check how much of the blob is downloaded.
+// Writes fail while fail_writes is set
+pub struct CountingPageBlob {
+    pub inner: MyPageBlobMock,
+    pub get_calls: usize,
+    pub fail_writes: bool,
+}
+
+impl CountingPageBlob {
+    pub fn new(inner: MyPageBlobMock) -> Self {
+        Self {
+            inner,
+            get_calls: 0,
+            fail_writes: false,
+        }
+    }
+
+    fn check_write(&self) -> Result<(), AzureStorageError> {
+        if self.fail_writes {
+            return Err(AzureStorageError::UnknownError {
+                msg: "Writes are failed by the test".to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MyPageBlob for CountingPageBlob {
+    fn get_container_name(&self) -> &str {
+        self.inner.get_container_name()
+    }
+
+    fn get_blob_name(&self) -> &str {
+        self.inner.get_blob_name()
+    }
+
+    async fn create_container_if_not_exist(&mut self) -> Result<(), AzureStorageError> {
+        self.inner.create_container_if_not_exist().await
+    }
+
+    async fn create_if_not_exists(
+        &mut self,
+        init_pages_amounts: usize,
+    ) -> Result<(), AzureStorageError> {
+        self.inner.create_if_not_exists(init_pages_amounts).await
+    }
+
+    async fn get_available_pages_amount(&mut self) -> Result<usize, AzureStorageError> {
+        self.inner.get_available_pages_amount().await
+    }
+
+    async fn get(
+        &mut self,
+        start_page_no: usize,
+        pages_to_read: usize,
+    ) -> Result<Vec<u8>, AzureStorageError> {
+        self.get_calls += 1;
+        self.inner.get(start_page_no, pages_to_read).await
+    }
+
+    async fn resize(&mut self, pages_amount: usize) -> Result<(), AzureStorageError> {
+        self.inner.resize(pages_amount).await
+    }
+
+    async fn save_pages(
+        &mut self,
+        start_page_no: usize,
+        max_pages_to_write_per_round_trip: usize,
+        payload: Vec<u8>,
+    ) -> Result<(), AzureStorageError> {
+        self.check_write()?;
+        self.inner
+            .save_pages(start_page_no, max_pages_to_write_per_round_trip, payload)
+            .await
+    }
+
+    async fn auto_ressize_and_save_pages(
+        &mut self,
+        start_page_no: usize,
+        max_pages_to_write_per_round_trip: usize,
+        payload: Vec<u8>,
+        resize_pages_ration: usize,
+    ) -> Result<usize, AzureStorageError> {
+        self.check_write()?;
+        self.inner
+            .auto_ressize_and_save_pages(
+                start_page_no,
+                max_pages_to_write_per_round_trip,
+                payload,
+                resize_pages_ration,
+            )
+            .await
+    }
+
+    async fn download(&mut self) -> Result<Vec<u8>, AzureStorageError> {
+        self.inner.download().await
+    }
+
+    async fn delete(&mut self) -> Result<(), AzureStorageError> {
+        self.inner.delete().await
+    }
+
+    async fn delete_if_exists(&mut self) -> Result<(), AzureStorageError> {
+        self.inner.delete_if_exists().await
+    }
+}
@@ -0,0 +1,108 @@
+// Errors are returned as text. TypedPageBlobAppend adds the seq and position of the record
+pub trait Codec<T> {
+    fn encode(&self, item: &T) -> Result<Vec<u8>, String>;
+    fn decode(&self, payload: &[u8]) -> Result<T, String>;
+}
+
+#[cfg(feature = "serde_json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+#[cfg(feature = "serde_json")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for JsonCodec {
+    fn encode(&self, item: &T) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(item).map_err(|err| err.to_string())
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<T, String> {
+        serde_json::from_slice(payload).map_err(|err| err.to_string())
+    }
+}
+
+// Uses the standard bincode configuration. Changing it makes the records already written unreadable
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for BincodeCodec {
+    fn encode(&self, item: &T) -> Result<Vec<u8>, String> {
+        bincode::serde::encode_to_vec(item, bincode::config::standard())
+            .map_err(|err| err.to_string())
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<T, String> {
+        let (result, read) =
+            bincode::serde::decode_from_slice(payload, bincode::config::standard())
+                .map_err(|err| err.to_string())?;
+
+        if read != payload.len() {
+            return Err(format!(
+                "{} bytes are left after the item is decoded",
+                payload.len() - read
+            ));
+        }
+
+        Ok(result)
+    }
+}
+
+// Structs are written as maps, so fields can be added later
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MsgPackCodec;
+
+#[cfg(feature = "msgpack")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for MsgPackCodec {
+    fn encode(&self, item: &T) -> Result<Vec<u8>, String> {
+        rmp_serde::to_vec_named(item).map_err(|err| err.to_string())
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<T, String> {
+        rmp_serde::from_slice(payload).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(all(
+    test,
+    any(feature = "serde_json", feature = "bincode", feature = "msgpack")
+))]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_json_codec() {
+        let item = (5u64, "five".to_string());
+        let encoded = JsonCodec.encode(&item).unwrap();
+
+        assert_eq!(b"[5,\"five\"]".to_vec(), encoded);
+        assert_eq!(item, JsonCodec.decode(&encoded).unwrap());
+
+        let result: Result<(u64, String), String> = JsonCodec.decode(b"[5,");
+        assert!(result.is_err());
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode_codec() {
+        let item = (5u64, "five".to_string());
+        let mut encoded = BincodeCodec.encode(&item).unwrap();
+        assert_eq!(item, BincodeCodec.decode(&encoded).unwrap());
+
+        encoded.push(0);
+        let result: Result<(u64, String), String> = BincodeCodec.decode(&encoded);
+        assert!(result.is_err());
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack_codec() {
+        let item = (5u64, "five".to_string());
+        let encoded = MsgPackCodec.encode(&item).unwrap();
+        assert_eq!(item, MsgPackCodec.decode(&encoded).unwrap());
+
+        let result: Result<(u64, String), String> = MsgPackCodec.decode(&encoded[..2]);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,11 @@
+mod codec;
+mod typed_page_blob_append;
+
+#[cfg(feature = "bincode")]
+pub use codec::BincodeCodec;
+pub use codec::Codec;
+#[cfg(feature = "serde_json")]
+pub use codec::JsonCodec;
+#[cfg(feature = "msgpack")]
+pub use codec::MsgPackCodec;
+pub use typed_page_blob_append::TypedPageBlobAppend;
@@ -0,0 +1,166 @@
+use std::{marker::PhantomData, ops::Range};
+
+use my_azure_page_blob::MyPageBlob;
+
+use crate::{error::DecodeErrorInfo, PageBlobAppend, PageBlobAppendError};
+
+use super::Codec;
+
+pub struct TypedPageBlobAppend<TMyPageBlob: MyPageBlob, T, TCodec: Codec<T>> {
+    page_blob_append: PageBlobAppend<TMyPageBlob>,
+    codec: TCodec,
+    item: PhantomData<fn() -> T>,
+}
+
+impl<TMyPageBlob: MyPageBlob, T, TCodec: Codec<T>> TypedPageBlobAppend<TMyPageBlob, T, TCodec> {
+    pub fn new(page_blob_append: PageBlobAppend<TMyPageBlob>, codec: TCodec) -> Self {
+        Self {
+            page_blob_append,
+            codec,
+            item: PhantomData,
+        }
+    }
+
+    // Nothing is written if any of the items can not be encoded
+    pub async fn append(&mut self, items: &[T]) -> Result<Range<u64>, PageBlobAppendError> {
+        let mut payloads = Vec::with_capacity(items.len());
+
+        for item in items {
+            let payload = self
+                .codec
+                .encode(item)
+                .map_err(PageBlobAppendError::EncodeFailed)?;
+
+            payloads.push(payload);
+        }
+
+        self.page_blob_append.append_and_write(&payloads).await
+    }
+
+    // Record which can not be decoded is reported with its seq and position.
+    // The next call continues with the following record
+    pub async fn next(&mut self) -> Result<Option<T>, PageBlobAppendError> {
+        let (blob_position, seq, payload) = match self
+            .page_blob_append
+            .get_next_payload_with_position()
+            .await?
+        {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+
+        match self.codec.decode(&payload) {
+            Ok(item) => Ok(Some(item)),
+            Err(msg) => Err(PageBlobAppendError::DecodeFailed(DecodeErrorInfo {
+                seq,
+                blob_position,
+                msg,
+            })),
+        }
+    }
+
+    pub fn get_page_blob_append(&self) -> &PageBlobAppend<TMyPageBlob> {
+        &self.page_blob_append
+    }
+
+    pub fn get_page_blob_append_mut(&mut self) -> &mut PageBlobAppend<TMyPageBlob> {
+        &mut self.page_blob_append
+    }
+
+    pub fn into_page_blob_append(self) -> PageBlobAppend<TMyPageBlob> {
+        self.page_blob_append
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{copy_blob, create_blob, get_settings},
+        RecordFormat,
+    };
+
+    struct U32Codec;
+
+    impl Codec<u32> for U32Codec {
+        fn encode(&self, item: &u32) -> Result<Vec<u8>, String> {
+            if *item == u32::MAX {
+                return Err("u32::MAX is reserved".to_string());
+            }
+
+            Ok(item.to_le_bytes().to_vec())
+        }
+
+        fn decode(&self, payload: &[u8]) -> Result<u32, String> {
+            let bytes: [u8; 4] = payload
+                .try_into()
+                .map_err(|_| format!("Expected 4 bytes. Got {}", payload.len()))?;
+
+            Ok(u32::from_le_bytes(bytes))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_append_and_read_items() {
+        let mut typed = TypedPageBlobAppend::new(
+            PageBlobAppend::new(
+                create_blob().await,
+                get_settings(RecordFormat::Crc32cWithSequence),
+            ),
+            U32Codec,
+        );
+        assert!(typed.next().await.unwrap().is_none());
+
+        assert_eq!(1..3, typed.append(&[5, 6]).await.unwrap());
+
+        let err = typed.append(&[7, u32::MAX]).await.unwrap_err();
+        assert!(matches!(err, PageBlobAppendError::EncodeFailed(_)));
+
+        let page_blob = copy_blob(typed.get_page_blob_append_mut().get_page_blob_mut()).await;
+
+        let mut typed = TypedPageBlobAppend::new(
+            PageBlobAppend::new(page_blob, get_settings(RecordFormat::Crc32cWithSequence)),
+            U32Codec,
+        );
+        assert_eq!(Some(5), typed.next().await.unwrap());
+        assert_eq!(Some(6), typed.next().await.unwrap());
+        assert!(typed.next().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_bad_record_is_reported_and_skipped() {
+        let mut page_blob_append = PageBlobAppend::new(
+            create_blob().await,
+            get_settings(RecordFormat::Crc32cWithSequence),
+        );
+        assert!(page_blob_append.get_next_payload().await.unwrap().is_none());
+        page_blob_append
+            .append_and_write(&vec![
+                5u32.to_le_bytes().to_vec(),
+                vec![1u8; 3],
+                7u32.to_le_bytes().to_vec(),
+            ])
+            .await
+            .unwrap();
+
+        let page_blob = copy_blob(page_blob_append.get_page_blob_mut()).await;
+
+        let mut typed = TypedPageBlobAppend::new(
+            PageBlobAppend::new(page_blob, get_settings(RecordFormat::Crc32cWithSequence)),
+            U32Codec,
+        );
+        assert_eq!(Some(5), typed.next().await.unwrap());
+
+        match typed.next().await.unwrap_err() {
+            PageBlobAppendError::DecodeFailed(info) => {
+                assert_eq!(2, info.seq);
+                // Header page + the first record of 16 + 4 bytes
+                assert_eq!(532, info.blob_position);
+            }
+            err => panic!("Unexpected error {:?}", err),
+        }
+
+        assert_eq!(Some(7), typed.next().await.unwrap());
+        assert!(typed.next().await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,430 @@
+use my_azure_page_blob::MyPageBlob;
+use my_azure_storage_sdk::page_blob::consts::BLOB_PAGE_SIZE;
+
+use crate::{
+    read_write::utils::END_MARKER,
+    states::{GetNextPayloadResult, StateDataNotInitialized, StateDataReading},
+    AppendPageBlobSettings, BlobHeader, ChangeState, PageBlobAppendError, RecordFormat,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedRecord {
+    pub position: usize,
+    pub seq: u64,
+    pub payload_size: usize,
+    // Payload is given as stored if the key or the codec is not available
+    pub is_decoded: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyCorruption {
+    pub position: usize,
+    pub msg: String,
+    // Hex dump of the page which contains the broken position
+    pub page_dump: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    pub blob_size_in_pages: usize,
+    pub header: Option<BlobHeader>,
+    pub record_format: RecordFormat,
+    pub records_count: usize,
+    pub payload_bytes: usize,
+    pub last_seq: u64,
+    pub end_marker_position: Option<usize>,
+    // Bytes after the end marker which are allocated but not used
+    pub wasted_tail_bytes: usize,
+    pub first_corruption: Option<VerifyCorruption>,
+    // Records which were not decrypted or decompressed. Only their framing and checksums are verified
+    pub undecoded_records: usize,
+    pub first_undecoded_reason: Option<String>,
+    // Blob size is expected to be rounded up by blob_auto_resize_in_pages and no bigger than that
+    pub blob_size_is_consistent: bool,
+}
+
+impl VerifyReport {
+    pub fn is_healthy(&self) -> bool {
+        self.first_corruption.is_none() && self.blob_size_is_consistent
+    }
+}
+
+pub struct ReadOnlyBlob<TMyPageBlob: MyPageBlob> {
+    pub blob_size_in_pages: usize,
+    pub header: Option<BlobHeader>,
+    pub record_format: RecordFormat,
+    pub state: StateDataReading<TMyPageBlob>,
+}
+
+// Reading state which never switches to writing, so the blob is not changed.
+// Record format is taken from the header. Blob without the header is read with the format of the settings
+pub async fn open_read_only<TMyPageBlob: MyPageBlob>(
+    mut page_blob: TMyPageBlob,
+    settings: &AppendPageBlobSettings,
+) -> Result<ReadOnlyBlob<TMyPageBlob>, PageBlobAppendError> {
+    let blob_size_in_pages = crate::with_retries::get_available_pages_amount(
+        &mut page_blob,
+        &settings.retry_policy,
+        &settings.metrics,
+    )
+    .await?;
+
+    let mut header = None;
+
+    if blob_size_in_pages > 0 {
+        let first_page = crate::with_retries::read_pages(
+            &mut page_blob,
+            0,
+            1,
+            &settings.retry_policy,
+            &settings.metrics,
+        )
+        .await?;
+
+        if BlobHeader::is_header(&first_page) {
+            let blob_header = BlobHeader::deserialize(&first_page)
+                .map_err(PageBlobAppendError::UnsupportedFormat)?;
+            blob_header
+                .validate()
+                .map_err(PageBlobAppendError::UnsupportedFormat)?;
+
+            if blob_header.is_compaction_in_progress() {
+                return Err(PageBlobAppendError::CompactionInterrupted(format!(
+                    "Compaction of PageBlobAppend {}/{} was interrupted",
+                    page_blob.get_container_name(),
+                    page_blob.get_blob_name()
+                )));
+            }
+
+            header = Some(blob_header);
+        }
+    }
+
+    let record_format = match &header {
+        Some(header) => header.get_record_format(),
+        None => settings.record_format,
+    };
+
+    let mut settings = settings.clone();
+    settings.record_format = record_format;
+
+    let mut not_initialized = StateDataNotInitialized::new(page_blob, settings.clone());
+    not_initialized.blob_size_in_pages = blob_size_in_pages;
+    not_initialized.header = header.clone();
+
+    Ok(ReadOnlyBlob {
+        blob_size_in_pages,
+        header,
+        record_format,
+        state: StateDataReading::from_not_initialized(not_initialized, settings),
+    })
+}
+
+// Walks the blob without changing it. on_record is called for every record boundary.
+// Record which needs a key or a codec that is not available is counted and given as stored
+pub async fn verify_blob<TMyPageBlob: MyPageBlob, TOnRecord: FnMut(&VerifiedRecord, &[u8])>(
+    page_blob: TMyPageBlob,
+    settings: &AppendPageBlobSettings,
+    mut on_record: TOnRecord,
+) -> Result<VerifyReport, PageBlobAppendError> {
+    let ReadOnlyBlob {
+        blob_size_in_pages,
+        header,
+        record_format,
+        mut state,
+    } = open_read_only(page_blob, settings).await?;
+
+    state.keep_undecoded_payloads();
+
+    let mut report = VerifyReport {
+        blob_size_in_pages,
+        header,
+        record_format,
+        records_count: 0,
+        payload_bytes: 0,
+        last_seq: 0,
+        end_marker_position: None,
+        wasted_tail_bytes: 0,
+        first_corruption: None,
+        undecoded_records: 0,
+        first_undecoded_reason: None,
+        blob_size_is_consistent: true,
+    };
+
+    if blob_size_in_pages == 0 {
+        return Ok(report);
+    }
+
+    loop {
+        let position = state.get_blob_position();
+
+        match state.get_next_payload().await {
+            Ok(GetNextPayloadResult::NextPayload(seq, payload)) => {
+                report.records_count += 1;
+                report.payload_bytes += payload.len();
+                report.last_seq = seq;
+
+                let undecoded_reason = state.get_undecoded_reason();
+
+                if let Some(reason) = undecoded_reason {
+                    report.undecoded_records += 1;
+
+                    if report.first_undecoded_reason.is_none() {
+                        report.first_undecoded_reason = Some(format!("Record {}. {}", seq, reason));
+                    }
+                }
+
+                on_record(
+                    &VerifiedRecord {
+                        position,
+                        seq,
+                        payload_size: payload.len(),
+                        is_decoded: undecoded_reason.is_none(),
+                    },
+                    &payload,
+                );
+            }
+            Ok(GetNextPayloadResult::ChangeState(ChangeState::ToWriteMode)) => {
+                report.end_marker_position = Some(position);
+                break;
+            }
+            Ok(GetNextPayloadResult::ChangeState(_)) => break,
+            Err(PageBlobAppendError::Corrupted(info)) => {
+                let page_no = info.broken_pos / BLOB_PAGE_SIZE;
+
+                let page = crate::with_retries::read_pages(
+                    &mut state.seq_reader.page_blob,
+                    page_no,
+                    1,
+                    &settings.retry_policy,
+                    &settings.metrics,
+                )
+                .await?;
+
+                report.first_corruption = Some(VerifyCorruption {
+                    position: info.broken_pos,
+                    msg: info.msg,
+                    page_dump: hex_dump(&page, page_no * BLOB_PAGE_SIZE),
+                });
+                break;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    let blob_size = blob_size_in_pages * BLOB_PAGE_SIZE;
+    let resize_in_pages = settings.blob_auto_resize_in_pages.max(1);
+
+    report.blob_size_is_consistent = match report.end_marker_position {
+        Some(end_marker_position) => {
+            let used_bytes = end_marker_position + END_MARKER.len();
+            report.wasted_tail_bytes = blob_size.saturating_sub(used_bytes);
+
+            let expected_pages = used_bytes
+                .div_ceil(BLOB_PAGE_SIZE)
+                .div_ceil(resize_in_pages)
+                * resize_in_pages;
+            blob_size_in_pages == expected_pages
+        }
+        None => blob_size_in_pages.is_multiple_of(resize_in_pages),
+    };
+
+    Ok(report)
+}
+
+// 16 bytes per line: "00000200: 03 00 00 00 ..."
+pub fn hex_dump(data: &[u8], start_position: usize) -> String {
+    let mut result = String::new();
+
+    for (line_no, line) in data.chunks(16).enumerate() {
+        result.push_str(&format!("{:08x}:", start_position + line_no * 16));
+
+        for b in line {
+            result.push_str(&format!(" {:02x}", b));
+        }
+
+        result.push('\n');
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{create_blob, create_blob_with_content, get_settings},
+        PageBlobAppend,
+    };
+
+    async fn write_records(records: Vec<Vec<u8>>) -> Vec<u8> {
+        let mut writer = PageBlobAppend::new(
+            create_blob().await,
+            get_settings(RecordFormat::Crc32cWithSequence),
+        );
+        assert!(writer.get_next_payload().await.unwrap().is_none());
+        writer.append_and_write(&records).await.unwrap();
+        writer.get_page_blob_mut().download().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_healthy_blob() {
+        let content = write_records(vec![vec![1u8; 3], vec![2u8; 4], vec![3u8; 5]]).await;
+
+        let mut records = Vec::new();
+        let report = verify_blob(
+            create_blob_with_content(content).await,
+            &get_settings(RecordFormat::Crc32cWithSequence),
+            |record, _| records.push(record.clone()),
+        )
+        .await
+        .unwrap();
+
+        assert!(report.is_healthy());
+        assert_eq!(3, report.records_count);
+        assert_eq!(12, report.payload_bytes);
+        assert_eq!(3, report.last_seq);
+        assert_eq!(Some(572), report.end_marker_position);
+        assert_eq!(1024 - 576, report.wasted_tail_bytes);
+
+        let positions: Vec<usize> = records.iter().map(|record| record.position).collect();
+        assert_eq!(vec![512, 531, 551], positions);
+    }
+
+    #[tokio::test]
+    async fn test_corruption_is_reported() {
+        let mut content = write_records(vec![vec![1u8; 3], vec![2u8; 4], vec![3u8; 5]]).await;
+        content[547] = 7;
+
+        let report = verify_blob(
+            create_blob_with_content(content.clone()).await,
+            &get_settings(RecordFormat::Crc32cWithSequence),
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+
+        assert!(!report.is_healthy());
+        assert_eq!(1, report.records_count);
+        assert_eq!(None, report.end_marker_position);
+
+        let corruption = report.first_corruption.unwrap();
+        assert_eq!(531, corruption.position);
+        assert!(corruption.page_dump.starts_with("00000200: 03 00 00 00"));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_blob_is_inconsistent() {
+        let mut content = write_records(vec![vec![1u8; 3]]).await;
+        content.extend_from_slice(&[0u8; BLOB_PAGE_SIZE * 2]);
+
+        let report = verify_blob(
+            create_blob_with_content(content).await,
+            &get_settings(RecordFormat::Crc32cWithSequence),
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+
+        assert!(report.first_corruption.is_none());
+        assert!(!report.blob_size_is_consistent);
+    }
+
+    #[tokio::test]
+    async fn test_blob_without_header_is_read_with_format_of_settings() {
+        let mut builder = crate::read_write::PackageBuilder::new(RecordFormat::Crc32c, 1);
+        builder.add_payload(&[1u8; 3]).unwrap();
+        builder.add_payload(&[2u8; 4]).unwrap();
+
+        let mut settings = get_settings(RecordFormat::Crc32cWithSequence);
+        settings.record_format = RecordFormat::Crc32c;
+
+        let mut payloads = Vec::new();
+        let report = verify_blob(
+            create_blob_with_content(builder.get_result()).await,
+            &settings,
+            |_, payload| payloads.push(payload.to_vec()),
+        )
+        .await
+        .unwrap();
+
+        assert!(report.header.is_none());
+        assert_eq!(RecordFormat::Crc32c, report.record_format);
+        assert!(report.first_corruption.is_none());
+        assert_eq!(vec![vec![1u8; 3], vec![2u8; 4]], payloads);
+        assert_eq!(Some(23), report.end_marker_position);
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[tokio::test]
+    async fn test_encrypted_blob_is_verified_without_key() {
+        let mut encrypted_settings = get_settings(RecordFormat::Crc32cWithSequence);
+        encrypted_settings.encryption = Some(crate::Encryption::new(
+            crate::EncryptionAlgorithm::Aes256Gcm,
+            std::sync::Arc::new(crate::StaticKeyProvider::new(1, [1u8; crate::KEY_SIZE])),
+        ));
+
+        let mut writer = PageBlobAppend::new(create_blob().await, encrypted_settings.clone());
+        assert!(writer.get_next_payload().await.unwrap().is_none());
+        writer
+            .append_and_write(&vec![b"secret one".to_vec(), b"secret two".to_vec()])
+            .await
+            .unwrap();
+        let mut content = writer.get_page_blob_mut().download().await.unwrap();
+
+        let mut records = Vec::new();
+        let report = verify_blob(
+            create_blob_with_content(content.clone()).await,
+            &get_settings(RecordFormat::Crc32cWithSequence),
+            |record, _| records.push(record.clone()),
+        )
+        .await
+        .unwrap();
+
+        assert!(report.is_healthy());
+        assert_eq!(2, report.records_count);
+        assert_eq!(2, report.undecoded_records);
+        assert!(report
+            .first_undecoded_reason
+            .unwrap()
+            .contains("encryption is not configured"));
+        assert!(records.iter().all(|record| !record.is_decoded));
+
+        let mut payloads = Vec::new();
+        let report = verify_blob(
+            create_blob_with_content(content.clone()).await,
+            &encrypted_settings,
+            |_, payload| payloads.push(payload.to_vec()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(0, report.undecoded_records);
+        assert_eq!(
+            vec![b"secret one".to_vec(), b"secret two".to_vec()],
+            payloads
+        );
+
+        // Checksum is of the stored bytes, so a broken record is found without the key
+        content[600] ^= 1;
+
+        let report = verify_blob(
+            create_blob_with_content(content).await,
+            &get_settings(RecordFormat::Crc32cWithSequence),
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+
+        assert!(report.first_corruption.is_some());
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let dump = hex_dump(&[0u8; 20], 512);
+        assert_eq!(
+            "00000200: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00\n00000210: 00 00 00 00\n",
+            dump
+        );
+    }
+}
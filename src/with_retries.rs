@@ -1,30 +1,99 @@
-use std::time::Duration;
+use std::{ops::Range, time::Instant};
 
 use my_azure_page_blob::MyPageBlob;
-use my_azure_storage_sdk::AzureStorageError;
+use my_azure_storage_sdk::{page_blob::consts::BLOB_PAGE_SIZE, AzureStorageError};
 
-pub async fn create_container_if_not_exist<TMyPageBlob: MyPageBlob>(
-    my_page_blob: &mut TMyPageBlob,
-) -> Result<(), AzureStorageError> {
-    let mut attemt_no = 0;
-    loop {
-        attemt_no += 1;
-        let result = my_page_blob.create_container_if_not_exist().await;
+use crate::{PageBlobMetrics, RetryPolicy};
+
+pub struct RetryAttempts<'s> {
+    retry_policy: &'s RetryPolicy,
+    metrics: &'s PageBlobMetrics,
+    operation: &'static str,
+    pages: Option<Range<usize>>,
+    attempt_no: usize,
+    started: Instant,
+}
 
-        if result.is_ok() {
-            return Ok(());
+impl<'s> RetryAttempts<'s> {
+    pub fn new(
+        retry_policy: &'s RetryPolicy,
+        metrics: &'s PageBlobMetrics,
+        operation: &'static str,
+    ) -> Self {
+        Self {
+            retry_policy,
+            metrics,
+            operation,
+            pages: None,
+            attempt_no: 1,
+            started: Instant::now(),
         }
+    }
 
-        let err = result.err().unwrap();
+    pub fn for_pages(
+        retry_policy: &'s RetryPolicy,
+        metrics: &'s PageBlobMetrics,
+        operation: &'static str,
+        start_page: usize,
+        pages_amount: usize,
+    ) -> Self {
+        let mut result = Self::new(retry_policy, metrics, operation);
+        result.pages = Some(start_page..start_page + pages_amount);
+        result
+    }
 
-        match &err {
-            my_azure_storage_sdk::AzureStorageError::HyperError { err } => {
-                println!(
-                    "We have problem on HTTP Level. Attempt: {} Err: {:?}",
-                    attemt_no, err
+    // Gives the error back if the policy says we have to give up
+    pub async fn wait_before_retry(
+        &mut self,
+        err: AzureStorageError,
+    ) -> Result<(), AzureStorageError> {
+        let delay =
+            self.retry_policy
+                .get_delay_before_retry(&err, self.attempt_no, self.started.elapsed());
+
+        match delay {
+            Some(delay) => {
+                crate::diagnostics::retry_attempt(
+                    self.operation,
+                    &self.pages,
+                    self.attempt_no,
+                    delay,
+                    &err,
                 );
+
+                self.metrics.retry(self.operation, &err);
+
+                self.attempt_no += 1;
+                tokio::time::sleep(delay).await;
+                Ok(())
+            }
+            None => {
+                if (self.retry_policy.is_retryable)(&err) {
+                    crate::diagnostics::retries_exhausted(
+                        self.operation,
+                        &self.pages,
+                        self.attempt_no,
+                        &err,
+                    );
+                }
+
+                Err(err)
             }
-            _ => return Err(err),
+        }
+    }
+}
+
+pub async fn create_container_if_not_exist<TMyPageBlob: MyPageBlob>(
+    my_page_blob: &mut TMyPageBlob,
+    retry_policy: &RetryPolicy,
+    metrics: &PageBlobMetrics,
+) -> Result<(), AzureStorageError> {
+    let mut attempts = RetryAttempts::new(retry_policy, metrics, "create_container_if_not_exist");
+
+    loop {
+        match my_page_blob.create_container_if_not_exist().await {
+            Ok(()) => return Ok(()),
+            Err(err) => attempts.wait_before_retry(err).await?,
         }
     }
 }
@@ -32,60 +101,53 @@ pub async fn create_container_if_not_exist<TMyPageBlob: MyPageBlob>(
 pub async fn create_blob_if_not_exists<TMyPageBlob: MyPageBlob>(
     my_page_blob: &mut TMyPageBlob,
     init_page_size: usize,
+    retry_policy: &RetryPolicy,
+    metrics: &PageBlobMetrics,
 ) -> Result<(), AzureStorageError> {
-    let mut attemt_no = 0;
-    loop {
-        attemt_no += 1;
-        let result = my_page_blob.create_if_not_exists(init_page_size).await;
+    let mut attempts = RetryAttempts::new(retry_policy, metrics, "create_blob_if_not_exists");
 
-        if result.is_ok() {
-            return Ok(());
+    loop {
+        match my_page_blob.create_if_not_exists(init_page_size).await {
+            Ok(()) => return Ok(()),
+            Err(err) => attempts.wait_before_retry(err).await?,
         }
+    }
+}
 
-        let err = result.err().unwrap();
+pub async fn delete_blob_if_exists<TMyPageBlob: MyPageBlob>(
+    my_page_blob: &mut TMyPageBlob,
+    retry_policy: &RetryPolicy,
+    metrics: &PageBlobMetrics,
+) -> Result<(), AzureStorageError> {
+    let mut attempts = RetryAttempts::new(retry_policy, metrics, "delete_blob_if_exists");
 
-        match &err {
-            my_azure_storage_sdk::AzureStorageError::HyperError { err } => {
-                println!(
-                    "We have problem on HTTP Level. Attempt: {} Err: {:?}",
-                    attemt_no, err
-                );
-            }
-            _ => return Err(err),
+    loop {
+        match my_page_blob.delete_if_exists().await {
+            Ok(()) => return Ok(()),
+            Err(err) => attempts.wait_before_retry(err).await?,
         }
     }
 }
 
 pub async fn get_available_pages_amount<TMyPageBlob: MyPageBlob>(
     page_blob: &mut TMyPageBlob,
+    retry_policy: &RetryPolicy,
+    metrics: &PageBlobMetrics,
 ) -> Result<usize, AzureStorageError> {
-    let mut attempt_no = 1;
+    let mut attempts = RetryAttempts::new(retry_policy, metrics, "get_available_pages_amount");
 
     loop {
-        let result = page_blob.get_available_pages_amount().await;
-
-        if result.is_ok() {
-            return result;
-        }
-
-        let err = result.err().unwrap();
-
-        match &err {
-            AzureStorageError::ContainerNotFound => {
-                crate::page_blob_utils::create_container_with_retires(page_blob).await?;
-            }
-            AzureStorageError::HyperError { err: _ } => {
-                println!(
-                    "Can not execute get_available_pages_amount because of  {:?}. Attempt {} Retrying",
-                    err, attempt_no
-                );
-                attempt_no += 1;
-
-                tokio::time::sleep(Duration::from_secs(3)).await;
-            }
-            _ => {
-                return Err(err);
+        match page_blob.get_available_pages_amount().await {
+            Ok(result) => return Ok(result),
+            Err(AzureStorageError::ContainerNotFound) => {
+                crate::page_blob_utils::create_container_with_retires(
+                    page_blob,
+                    retry_policy,
+                    metrics,
+                )
+                .await?;
             }
+            Err(err) => attempts.wait_before_retry(err).await?,
         }
     }
 }
@@ -93,34 +155,23 @@ pub async fn get_available_pages_amount<TMyPageBlob: MyPageBlob>(
 pub async fn resize_page_blob<TMyPageBlob: MyPageBlob>(
     page_blob: &mut TMyPageBlob,
     pages_amount: usize,
+    retry_policy: &RetryPolicy,
+    metrics: &PageBlobMetrics,
 ) -> Result<(), AzureStorageError> {
-    let mut attempt_no = 1;
+    let mut attempts = RetryAttempts::new(retry_policy, metrics, "resize_page_blob");
 
     loop {
-        let result = page_blob.resize(pages_amount).await;
-
-        if result.is_ok() {
-            return Ok(());
-        }
-
-        let err = result.err().unwrap();
-
-        match &err {
-            AzureStorageError::ContainerNotFound => {
-                crate::page_blob_utils::create_container_with_retires(page_blob).await?;
-            }
-            AzureStorageError::HyperError { err: _ } => {
-                println!(
-                    "Can not execute resize_page_blob because of  {:?}. Attempt {} Retrying",
-                    err, attempt_no
-                );
-                attempt_no += 1;
-
-                tokio::time::sleep(Duration::from_secs(3)).await;
-            }
-            _ => {
-                return Err(err);
+        match page_blob.resize(pages_amount).await {
+            Ok(()) => return Ok(()),
+            Err(AzureStorageError::ContainerNotFound) => {
+                crate::page_blob_utils::create_container_with_retires(
+                    page_blob,
+                    retry_policy,
+                    metrics,
+                )
+                .await?;
             }
+            Err(err) => attempts.wait_before_retry(err).await?,
         }
     }
 }
@@ -129,34 +180,29 @@ pub async fn read_pages<TMyPageBlob: MyPageBlob>(
     page_blob: &mut TMyPageBlob,
     start_page: usize,
     pages_amount: usize,
+    retry_policy: &RetryPolicy,
+    metrics: &PageBlobMetrics,
 ) -> Result<Vec<u8>, AzureStorageError> {
-    let mut attempt_no = 1;
+    let mut attempts = RetryAttempts::for_pages(
+        retry_policy,
+        metrics,
+        "read_pages",
+        start_page,
+        pages_amount,
+    );
 
     loop {
-        let result = page_blob.get(start_page, pages_amount).await;
-
-        if let Ok(result) = result {
-            return Ok(result);
-        }
-
-        let err = result.err().unwrap();
-
-        match &err {
-            AzureStorageError::ContainerNotFound => {
-                crate::page_blob_utils::create_container_with_retires(page_blob).await?;
-            }
-            AzureStorageError::HyperError { err: _ } => {
-                println!(
-                    "Can not execute read_pages because of  {:?}. Attempt {} Retrying",
-                    err, attempt_no
-                );
-                attempt_no += 1;
-
-                tokio::time::sleep(Duration::from_secs(3)).await;
-            }
-            _ => {
-                return Err(err);
+        match page_blob.get(start_page, pages_amount).await {
+            Ok(result) => return Ok(result),
+            Err(AzureStorageError::ContainerNotFound) => {
+                crate::page_blob_utils::create_container_with_retires(
+                    page_blob,
+                    retry_policy,
+                    metrics,
+                )
+                .await?;
             }
+            Err(err) => attempts.wait_before_retry(err).await?,
         }
     }
 }
@@ -166,37 +212,35 @@ pub async fn write_pages<TMyPageBlob: MyPageBlob>(
     start_page: usize,
     max_pages_to_write: usize,
     payload: Vec<u8>,
+    retry_policy: &RetryPolicy,
+    metrics: &PageBlobMetrics,
 ) -> Result<(), AzureStorageError> {
-    let mut attempt_no = 1;
+    let mut attempts = RetryAttempts::for_pages(
+        retry_policy,
+        metrics,
+        "write_pages",
+        start_page,
+        payload.len() / BLOB_PAGE_SIZE,
+    );
+
+    let payloads = PayloadAttempts::new(payload);
 
     loop {
-        let payload_to_write = payload.to_vec();
         let result = page_blob
-            .save_pages(start_page, max_pages_to_write, payload_to_write)
+            .save_pages(start_page, max_pages_to_write, payloads.next())
             .await;
 
-        if result.is_ok() {
-            return Ok(());
-        }
-
-        let err = result.err().unwrap();
-
-        match &err {
-            AzureStorageError::ContainerNotFound => {
-                crate::page_blob_utils::create_container_with_retires(page_blob).await?;
-            }
-            AzureStorageError::HyperError { err: _ } => {
-                println!(
-                    "Can not execute write_pages because of  {:?}. Attempt {} Retrying",
-                    err, attempt_no
-                );
-                attempt_no += 1;
-
-                tokio::time::sleep(Duration::from_secs(3)).await;
-            }
-            _ => {
-                return Err(err);
+        match result {
+            Ok(()) => return Ok(()),
+            Err(AzureStorageError::ContainerNotFound) => {
+                crate::page_blob_utils::create_container_with_retires(
+                    page_blob,
+                    retry_policy,
+                    metrics,
+                )
+                .await?;
             }
+            Err(err) => attempts.wait_before_retry(err).await?,
         }
     }
 }
@@ -207,43 +251,138 @@ pub async fn auto_ressize_and_save_pages<TMyPageBlob: MyPageBlob>(
     max_pages_to_write: usize,
     blob_autoressize_in_pages: usize,
     payload: Vec<u8>,
+    retry_policy: &RetryPolicy,
+    metrics: &PageBlobMetrics,
 ) -> Result<usize, AzureStorageError> {
-    let mut attempt_no = 1;
+    let mut attempts = RetryAttempts::for_pages(
+        retry_policy,
+        metrics,
+        "auto_ressize_and_save_pages",
+        start_page_no,
+        payload.len().div_ceil(BLOB_PAGE_SIZE),
+    );
+
+    let payloads = PayloadAttempts::new(payload);
 
     loop {
-        let payload_to_write = payload.to_vec();
+        let started = Instant::now();
 
         let result = page_blob
             .auto_ressize_and_save_pages(
                 start_page_no,
                 max_pages_to_write,
-                payload_to_write,
+                payloads.next(),
                 blob_autoressize_in_pages,
             )
             .await;
 
-        if let Ok(result) = result {
-            return Ok(result);
+        metrics.write_round_trip(started.elapsed(), result.is_ok());
+
+        match result {
+            Ok(result) => return Ok(result),
+            Err(AzureStorageError::ContainerNotFound) => {
+                crate::page_blob_utils::create_container_with_retires(
+                    page_blob,
+                    retry_policy,
+                    metrics,
+                )
+                .await?;
+            }
+            Err(err) => attempts.wait_before_retry(err).await?,
         }
+    }
+}
 
-        let err = result.err().unwrap();
+// MyPageBlob takes the payload by value, so every attempt sends a copy of the kept buffer.
+// The buffer is built once and never built again for a retry
+struct PayloadAttempts {
+    payload: Vec<u8>,
+}
 
-        match &err {
-            AzureStorageError::ContainerNotFound => {
-                crate::page_blob_utils::create_container_with_retires(page_blob).await?;
-            }
-            AzureStorageError::HyperError { err: _ } => {
-                println!(
-                    "Can not execute auto_ressize_and_save_pages because of  {:?}. Attempt {} Retrying",
-                    err, attempt_no
-                );
-                attempt_no += 1;
+impl PayloadAttempts {
+    fn new(payload: Vec<u8>) -> Self {
+        Self { payload }
+    }
 
-                tokio::time::sleep(Duration::from_secs(3)).await;
-            }
-            _ => {
-                return Err(err);
-            }
+    fn next(&self) -> Vec<u8> {
+        self.payload.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use my_azure_page_blob::MyPageBlobMock;
+
+    use super::*;
+    use crate::FaultInjectingPageBlob;
+
+    fn retry_everything_policy(max_attempts: Option<usize>) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_delay: Duration::ZERO,
+            is_retryable: |_| true,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_until_success() {
+        let inner = crate::test_utils::create_blob().await;
+
+        let mut page_blob = FaultInjectingPageBlob::new(inner, 3, 2);
+
+        let retry_policy = retry_everything_policy(None);
+
+        for page_no in 0..10 {
+            auto_ressize_and_save_pages(
+                &mut page_blob,
+                page_no,
+                10,
+                1,
+                vec![page_no as u8; 512],
+                &retry_policy,
+                &PageBlobMetrics::disabled(),
+            )
+            .await
+            .unwrap();
         }
+
+        for page_no in 0..10 {
+            let page = read_pages(
+                &mut page_blob,
+                page_no,
+                1,
+                &retry_policy,
+                &PageBlobMetrics::disabled(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(vec![page_no as u8; 512], page);
+        }
+
+        assert!(!page_blob.get_injected_faults().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let mut inner = MyPageBlobMock::new();
+        inner.create_container_if_not_exist().await.unwrap();
+        inner.create_if_not_exists(1).await.unwrap();
+
+        let mut page_blob = FaultInjectingPageBlob::new(inner, 3, 1);
+
+        let result = read_pages(
+            &mut page_blob,
+            0,
+            1,
+            &retry_everything_policy(Some(4)),
+            &PageBlobMetrics::disabled(),
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(4, page_blob.get_injected_faults().len());
     }
 }
@@ -0,0 +1,426 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use my_azure_storage_sdk::AzureStorageError;
+use rust_extensions::date_time::DateTimeAsMicroseconds;
+
+use crate::{file_page_blob::io_error, FilePageBlob, PageBlobAppendError};
+
+// Lease which lets only one process append to the blob.
+// Lease which is not renewed during get_lease_duration can be taken by another owner.
+// MyPageBlob has no lease operations, so the Azure blob lease (acquire, renew and release
+// with a fixed lease id) is implemented next to the storage client which has them
+#[async_trait]
+pub trait WriterLock: Send + Sync {
+    fn get_lease_duration(&self) -> Duration;
+
+    // false if the lease is held by another owner
+    async fn acquire(&mut self) -> Result<bool, AzureStorageError>;
+
+    // false if the lease expired and was taken by another owner
+    async fn renew(&mut self) -> Result<bool, AzureStorageError>;
+
+    async fn release(&mut self) -> Result<(), AzureStorageError>;
+}
+
+// Lease is kept in the <blob>.lock file next to the blob file as "<owner_id>\n<expires unix microseconds>".
+// Lease is checked and changed only while the OS lock of the <blob>.lock.guard file is held,
+// so two owners never take the same expired lease. OS releases the guard if the process stops
+pub struct FileWriterLock {
+    lock_file: PathBuf,
+    guard_file: PathBuf,
+    owner_id: String,
+    lease_duration: Duration,
+}
+
+impl FileWriterLock {
+    pub fn new(page_blob: &FilePageBlob, owner_id: &str, lease_duration: Duration) -> Self {
+        let mut lock_file = page_blob.get_file_path().into_os_string();
+        lock_file.push(".lock");
+
+        let mut guard_file = lock_file.clone();
+        guard_file.push(".guard");
+
+        Self {
+            lock_file: lock_file.into(),
+            guard_file: guard_file.into(),
+            owner_id: owner_id.to_string(),
+            lease_duration,
+        }
+    }
+
+    // Guard is held until the returned file is dropped
+    async fn lock_guard(&self) -> Result<std::fs::File, AzureStorageError> {
+        let guard_file = self.guard_file.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(guard_file)?;
+
+            file.lock()?;
+
+            Ok(file)
+        })
+        .await;
+
+        match result {
+            Ok(result) => result.map_err(io_error),
+            Err(err) => Err(io_error(std::io::Error::other(err))),
+        }
+    }
+
+    async fn read_lease(&self) -> Result<Option<(String, i64)>, AzureStorageError> {
+        let content = match tokio::fs::read_to_string(&self.lock_file).await {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(io_error(err)),
+        };
+
+        // Broken lock file is treated as an expired lease
+        let mut lines = content.lines();
+
+        let owner_id = lines.next().unwrap_or_default().to_string();
+        let expires = lines
+            .next()
+            .and_then(|expires| expires.parse().ok())
+            .unwrap_or(0);
+
+        Ok(Some((owner_id, expires)))
+    }
+
+    // Lease is written to a temp file and renamed, so the lock file is never seen half written
+    async fn write_lease(&self) -> Result<(), AzureStorageError> {
+        let expires = DateTimeAsMicroseconds::now().unix_microseconds
+            + self.lease_duration.as_micros() as i64;
+
+        let mut temp_file = self.lock_file.clone().into_os_string();
+        temp_file.push(format!(".{}", self.owner_id));
+
+        tokio::fs::write(&temp_file, format!("{}\n{}", self.owner_id, expires))
+            .await
+            .map_err(io_error)?;
+
+        tokio::fs::rename(&temp_file, &self.lock_file)
+            .await
+            .map_err(io_error)
+    }
+
+    async fn is_owned(&self) -> Result<bool, AzureStorageError> {
+        match self.read_lease().await? {
+            Some((owner_id, _)) => Ok(owner_id == self.owner_id),
+            None => Ok(false),
+        }
+    }
+}
+
+#[async_trait]
+impl WriterLock for FileWriterLock {
+    fn get_lease_duration(&self) -> Duration {
+        self.lease_duration
+    }
+
+    async fn acquire(&mut self) -> Result<bool, AzureStorageError> {
+        let _guard = self.lock_guard().await?;
+
+        if let Some((owner_id, expires)) = self.read_lease().await? {
+            if owner_id != self.owner_id
+                && expires > DateTimeAsMicroseconds::now().unix_microseconds
+            {
+                return Ok(false);
+            }
+        }
+
+        self.write_lease().await?;
+        Ok(true)
+    }
+
+    async fn renew(&mut self) -> Result<bool, AzureStorageError> {
+        let _guard = self.lock_guard().await?;
+
+        if !self.is_owned().await? {
+            return Ok(false);
+        }
+
+        self.write_lease().await?;
+        Ok(true)
+    }
+
+    async fn release(&mut self) -> Result<(), AzureStorageError> {
+        let _guard = self.lock_guard().await?;
+
+        if !self.is_owned().await? {
+            return Ok(());
+        }
+
+        match tokio::fs::remove_file(&self.lock_file).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(io_error(err)),
+        }
+    }
+}
+
+pub struct WriterLease {
+    lock: Box<dyn WriterLock>,
+    renewed: Option<Instant>,
+    lost: bool,
+}
+
+impl WriterLease {
+    pub fn new(lock: Box<dyn WriterLock>) -> Self {
+        Self {
+            lock,
+            renewed: None,
+            lost: false,
+        }
+    }
+
+    // Lease is renewed when half of it has passed, so it does not expire between the check and the write
+    pub async fn ensure_ownership(&mut self, blob_name: &str) -> Result<(), PageBlobAppendError> {
+        if self.lost {
+            return Err(PageBlobAppendError::LostOwnership(format!(
+                "Writer lost the lease of {}. Reopen it to continue",
+                blob_name
+            )));
+        }
+
+        let renew_after = self.lock.get_lease_duration() / 2;
+
+        let owned = match self.renewed {
+            None => self.lock.acquire().await?,
+            Some(renewed) if renewed.elapsed() >= renew_after => self.lock.renew().await?,
+            Some(_) => return Ok(()),
+        };
+
+        if owned {
+            self.renewed = Some(Instant::now());
+            return Ok(());
+        }
+
+        if self.renewed.is_none() {
+            return Err(PageBlobAppendError::LostOwnership(format!(
+                "Lease of {} is held by another writer",
+                blob_name
+            )));
+        }
+
+        self.lost = true;
+
+        Err(PageBlobAppendError::LostOwnership(format!(
+            "Writer lost the lease of {}. Another writer could have appended to it",
+            blob_name
+        )))
+    }
+
+    pub async fn release(&mut self) -> Result<(), AzureStorageError> {
+        if self.renewed.is_none() || self.lost {
+            return Ok(());
+        }
+
+        self.renewed = None;
+        self.lock.release().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils::get_settings, PageBlobAppend, RecordFormat};
+
+    fn get_test_root_folder(test_name: &str) -> PathBuf {
+        let result = std::env::temp_dir().join(format!(
+            "page-blob-append-lock-{}-{}",
+            test_name,
+            std::process::id()
+        ));
+
+        let _ = std::fs::remove_dir_all(&result);
+
+        result
+    }
+
+    async fn open_writer(
+        root_folder: &PathBuf,
+        owner_id: &str,
+        lease_duration: Duration,
+    ) -> PageBlobAppend<FilePageBlob> {
+        let mut page_blob = FilePageBlob::new(root_folder, "test", "log");
+        crate::with_retries::create_container_if_not_exist(
+            &mut page_blob,
+            &crate::RetryPolicy::default(),
+            &crate::PageBlobMetrics::disabled(),
+        )
+        .await
+        .unwrap();
+        crate::with_retries::create_blob_if_not_exists(
+            &mut page_blob,
+            0,
+            &crate::RetryPolicy::default(),
+            &crate::PageBlobMetrics::disabled(),
+        )
+        .await
+        .unwrap();
+
+        let writer_lock = FileWriterLock::new(&page_blob, owner_id, lease_duration);
+
+        let mut page_blob_append =
+            PageBlobAppend::new(page_blob, get_settings(RecordFormat::Crc32cWithSequence));
+        page_blob_append.set_writer_lock(Box::new(writer_lock));
+        page_blob_append
+    }
+
+    #[tokio::test]
+    async fn test_second_writer_is_rejected() {
+        let root_folder = get_test_root_folder("second_writer");
+
+        let mut first = open_writer(&root_folder, "first", Duration::from_secs(60)).await;
+        assert!(first.get_next_payload().await.unwrap().is_none());
+        first.append_and_write(&vec![vec![1u8]]).await.unwrap();
+
+        let mut second = open_writer(&root_folder, "second", Duration::from_secs(60)).await;
+        let err = second.get_next_payload().await.unwrap_err();
+        assert!(err.is_lost_ownership());
+
+        // Released lease can be taken
+        first.release_writer_lock().await.unwrap();
+
+        let mut second = open_writer(&root_folder, "second", Duration::from_secs(60)).await;
+        assert_eq!(
+            Some((1, vec![1u8])),
+            second.get_next_payload().await.unwrap()
+        );
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_only_one_owner_takes_expired_lease() {
+        let root_folder = get_test_root_folder("expired_lease");
+
+        let page_blob = FilePageBlob::new(&root_folder, "test", "log");
+        std::fs::create_dir_all(page_blob.get_file_path().parent().unwrap()).unwrap();
+
+        let mut expired = FileWriterLock::new(&page_blob, "expired", Duration::from_millis(1));
+        assert!(expired.acquire().await.unwrap());
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        for round in 0..20 {
+            let mut tasks = Vec::new();
+
+            for owner_no in 0..8 {
+                let mut lock = FileWriterLock::new(
+                    &page_blob,
+                    &format!("owner-{}-{}", round, owner_no),
+                    Duration::from_millis(50),
+                );
+
+                tasks.push(tokio::spawn(async move { lock.acquire().await.unwrap() }));
+            }
+
+            let mut owners = 0;
+
+            for task in tasks {
+                if task.await.unwrap() {
+                    owners += 1;
+                }
+            }
+
+            assert_eq!(1, owners, "Round {}", round);
+
+            tokio::time::sleep(Duration::from_millis(60)).await;
+        }
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+
+    #[tokio::test]
+    async fn test_writer_which_lost_lease_can_not_write() {
+        let root_folder = get_test_root_folder("lost_lease");
+
+        let mut first = open_writer(&root_folder, "first", Duration::from_millis(1)).await;
+        assert!(first.get_next_payload().await.unwrap().is_none());
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // Lease of the first writer expired
+        let mut second = open_writer(&root_folder, "second", Duration::from_secs(60)).await;
+        assert!(second.get_next_payload().await.unwrap().is_none());
+        second.append_and_write(&vec![vec![2u8]]).await.unwrap();
+
+        let err = first.append_and_write(&vec![vec![1u8]]).await.unwrap_err();
+        assert!(err.is_lost_ownership());
+
+        let err = first.append_and_write(&vec![vec![1u8]]).await.unwrap_err();
+        assert!(err.is_lost_ownership());
+
+        second.release_writer_lock().await.unwrap();
+
+        let mut reader = PageBlobAppend::new(
+            FilePageBlob::new(&root_folder, "test", "log"),
+            get_settings(RecordFormat::Crc32cWithSequence),
+        );
+        assert_eq!(
+            Some((1, vec![2u8])),
+            reader.get_next_payload().await.unwrap()
+        );
+        assert!(reader.get_next_payload().await.unwrap().is_none());
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+
+    #[tokio::test]
+    async fn test_writer_which_lost_lease_does_not_truncate() {
+        let root_folder = get_test_root_folder("lost_lease_truncate");
+
+        let mut setup = open_writer(&root_folder, "setup", Duration::from_secs(60)).await;
+        assert!(setup.get_next_payload().await.unwrap().is_none());
+        setup
+            .append_and_write(&vec![vec![1u8], vec![2u8], vec![3u8]])
+            .await
+            .unwrap();
+        setup.release_writer_lock().await.unwrap();
+
+        // Payload of the second record
+        let file_path = FilePageBlob::new(&root_folder, "test", "log").get_file_path();
+        let mut content = std::fs::read(&file_path).unwrap();
+        content[545] = 7;
+        std::fs::write(&file_path, &content).unwrap();
+
+        let page_blob = FilePageBlob::new(&root_folder, "test", "log");
+        let writer_lock = FileWriterLock::new(&page_blob, "first", Duration::from_millis(1));
+
+        let mut settings = get_settings(RecordFormat::Crc32cWithSequence);
+        settings.recovery_mode = crate::RecoveryMode::TruncateAtLastGood;
+
+        let mut first = PageBlobAppend::new(page_blob, settings);
+        first.set_writer_lock(Box::new(writer_lock));
+
+        assert_eq!(
+            Some((1, vec![1u8])),
+            first.get_next_payload().await.unwrap()
+        );
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let mut second = FileWriterLock::new(
+            &FilePageBlob::new(&root_folder, "test", "log"),
+            "second",
+            Duration::from_secs(60),
+        );
+        assert!(second.acquire().await.unwrap());
+
+        let err = first.get_next_payload().await.unwrap_err();
+        assert!(err.is_lost_ownership());
+
+        assert_eq!(content, std::fs::read(&file_path).unwrap());
+
+        let _ = std::fs::remove_dir_all(&root_folder);
+    }
+}